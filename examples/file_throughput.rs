@@ -0,0 +1,84 @@
+/*
+ * 静态文件响应吞吐量测试：向运行中的服务器重复请求同一文件，统计下载速度
+ * 测量的是服务器实际的响应路径（明文TCP在Linux下走sendfile，其余连接走缓冲复制）
+ *
+ * 用法：cargo run --release --example file_throughput -- <地址> <路径> [次数]
+ * 例如：cargo run --release --example file_throughput -- 127.0.0.1:80 /big.bin 20
+ */
+use std::{
+    env,
+    io::{self, BufRead, BufReader, Write},
+    net::TcpStream,
+    process,
+    time::Instant,
+};
+
+//请求一次文件，返回响应体字节数
+fn fetch(addr: &str, path: &str) -> io::Result<u64> {
+    let mut stream = TcpStream::connect(addr)?;
+    write!(
+        stream,
+        "GET {} HTTP/1.1\r\nHost: {}\r\nConnection: close\r\n\r\n",
+        path, addr
+    )?;
+    let mut reader = BufReader::with_capacity(256 * 1024, stream);
+    let mut line = String::new();
+    reader.read_line(&mut line)?;
+    if line.split_whitespace().nth(1) != Some("200") {
+        return Err(io::Error::other(format!(
+            "unexpected status: {}",
+            line.trim_end()
+        )));
+    }
+    let mut content_length = None;
+    loop {
+        line.clear();
+        if reader.read_line(&mut line)? == 0 || line == "\r\n" {
+            break;
+        }
+        if let Some((name, value)) = line.split_once(':') {
+            if name.eq_ignore_ascii_case("content-length") {
+                content_length = value.trim().parse::<u64>().ok();
+            }
+        }
+    }
+    let size = io::copy(&mut reader, &mut io::sink())?;
+    if content_length.is_some_and(|len| len != size) {
+        return Err(io::ErrorKind::UnexpectedEof.into());
+    }
+    Ok(size)
+}
+
+fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+    if args.len() < 2 {
+        eprintln!("usage: file_throughput <addr> <path> [rounds]");
+        process::exit(2);
+    }
+    let (addr, path) = (&args[0], &args[1]);
+    let rounds: u32 = args.get(2).and_then(|n| n.parse().ok()).unwrap_or(10);
+    //预热一次，使文件进入页缓存与打开文件缓存
+    if let Err(e) = fetch(addr, path) {
+        eprintln!("request failed: {}", e);
+        process::exit(1);
+    }
+    let mut total = 0;
+    let start = Instant::now();
+    for _ in 0..rounds {
+        match fetch(addr, path) {
+            Ok(size) => total += size,
+            Err(e) => {
+                eprintln!("request failed: {}", e);
+                process::exit(1);
+            }
+        }
+    }
+    let secs = start.elapsed().as_secs_f64();
+    println!(
+        "{} requests, {} bytes, {:.3}s, {:.0} MB/s",
+        rounds,
+        total,
+        secs,
+        total as f64 / 1024.0 / 1024.0 / secs
+    );
+}
@@ -0,0 +1,343 @@
+use std::{
+    collections::HashMap,
+    fs::{File, OpenOptions},
+    io::Write,
+    path::{Path, PathBuf},
+    sync::Mutex,
+    time::Instant,
+};
+
+use crate::{config::AccessLogConfig, http::RequestHeader, log_error, time::LocalTime};
+
+//Common Log Format
+pub const COMMON: &str =
+    "$remote_addr - $remote_user [$time_local] \"$request\" $status $body_bytes_sent";
+//Combined Log Format，在Common的基础上增加Referer与User-Agent
+pub const COMBINED: &str = "$remote_addr - $remote_user [$time_local] \"$request\" $status $body_bytes_sent \"$http_referer\" \"$http_user_agent\"";
+
+#[derive(Clone, Debug, PartialEq)]
+enum Var {
+    RemoteAddr,
+    RemoteUser,
+    TimeLocal,
+    TimeIso8601,
+    Request,
+    RequestMethod,
+    RequestUri,
+    ServerProtocol,
+    Status,
+    BodyBytesSent,
+    BytesSent,
+    RequestTime,
+    //本服务器不转发请求，上游耗时始终为“-”，保留变量便于沿用其他服务器的格式
+    UpstreamTime,
+    //请求头，如$http_user_agent
+    Header(String),
+}
+
+impl Var {
+    fn parse(name: &str) -> Option<Var> {
+        let var = match name {
+            "remote_addr" => Var::RemoteAddr,
+            "remote_user" => Var::RemoteUser,
+            "time_local" => Var::TimeLocal,
+            "time_iso8601" => Var::TimeIso8601,
+            "request" => Var::Request,
+            "request_method" => Var::RequestMethod,
+            "request_uri" => Var::RequestUri,
+            "server_protocol" => Var::ServerProtocol,
+            "status" => Var::Status,
+            "body_bytes_sent" => Var::BodyBytesSent,
+            "bytes_sent" => Var::BytesSent,
+            "request_time" => Var::RequestTime,
+            "upstream_connect_time" | "upstream_header_time" | "upstream_response_time" => {
+                Var::UpstreamTime
+            }
+            "referer" => Var::Header("referer".to_owned()),
+            "user_agent" => Var::Header("user-agent".to_owned()),
+            "host" => Var::Header("host".to_owned()),
+            name => match name.strip_prefix("http_") {
+                Some(header) if !header.is_empty() => Var::Header(header.replace('_', "-")),
+                _ => return None,
+            },
+        };
+        Some(var)
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Segment {
+    Text(String),
+    Var(Var),
+}
+
+//访问日志格式：common、combined或包含$变量的模板，变量名后紧跟字母时写作${name}
+#[derive(Clone, Debug, PartialEq)]
+pub struct LogFormat {
+    segments: Vec<Segment>,
+}
+
+impl LogFormat {
+    pub fn parse(text: &str) -> Result<LogFormat, String> {
+        let template = match text {
+            "common" => COMMON,
+            "combined" => COMBINED,
+            text => text,
+        };
+        let mut segments = Vec::new();
+        let mut rest = template;
+        while let Some(pos) = rest.find('$') {
+            if pos > 0 {
+                segments.push(Segment::Text(rest[..pos].to_owned()));
+            }
+            rest = &rest[pos + 1..];
+            let (name, next) = match rest.strip_prefix('{') {
+                Some(braced) => match braced.find('}') {
+                    Some(end) => (&braced[..end], &braced[end + 1..]),
+                    None => return Err(format!("unclosed '${{' in \"{}\"", template)),
+                },
+                None => {
+                    let end = rest
+                        .find(|c: char| !(c.is_ascii_alphanumeric() || c == '_'))
+                        .unwrap_or(rest.len());
+                    (&rest[..end], &rest[end..])
+                }
+            };
+            match Var::parse(name) {
+                Some(var) => segments.push(Segment::Var(var)),
+                None => return Err(format!("unknown variable '${}'", name)),
+            }
+            rest = next;
+        }
+        if !rest.is_empty() {
+            segments.push(Segment::Text(rest.to_owned()));
+        }
+        Ok(LogFormat { segments })
+    }
+
+    pub fn format(&self, record: &Record) -> String {
+        let mut parts = record.request.splitn(3, ' ');
+        let (method, uri, protocol) = (parts.next(), parts.next(), parts.next());
+        let time = LocalTime::now_in(record.timezone);
+        let mut line = String::new();
+        for segment in &self.segments {
+            let var = match segment {
+                Segment::Text(text) => {
+                    line.push_str(text);
+                    continue;
+                }
+                Segment::Var(var) => var,
+            };
+            let value = match var {
+                Var::RemoteAddr => escape(record.client),
+                Var::RemoteUser | Var::UpstreamTime => String::from("-"),
+                Var::TimeLocal => time.clf(),
+                Var::TimeIso8601 => time.iso8601(),
+                Var::Request => escape(record.request),
+                Var::RequestMethod => method.map_or_else(|| String::from("-"), escape),
+                Var::RequestUri => uri.map_or_else(|| String::from("-"), escape),
+                Var::ServerProtocol => protocol.map_or_else(|| String::from("-"), escape),
+                Var::Status => record
+                    .status
+                    .map_or_else(|| String::from("-"), |s| s.to_string()),
+                Var::BodyBytesSent => record.body_bytes.to_string(),
+                Var::BytesSent => record.bytes_sent.to_string(),
+                Var::RequestTime => format!("{:.3}", record.start.elapsed().as_secs_f64()),
+                Var::Header(name) => record
+                    .header
+                    .params
+                    .get(name)
+                    .map_or_else(|| String::from("-"), |value| escape(value)),
+            };
+            line.push_str(&value);
+        }
+        line
+    }
+}
+
+//双引号、反斜杠与控制字符转义为\xHH，防止伪造日志行
+fn escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '"' | '\\' => escaped.push_str(&format!("\\x{:02X}", c as u32)),
+            c if c.is_control() => escaped.push_str(&format!("\\x{:02X}", c as u32)),
+            c => escaped.push(c),
+        }
+    }
+    if escaped.is_empty() {
+        escaped.push('-');
+    }
+    escaped
+}
+
+//一次请求的访问记录
+pub struct Record<'a> {
+    //客户端地址（经可信代理解析后）
+    pub client: &'a str,
+    //请求行
+    pub request: &'a str,
+    pub header: &'a RequestHeader,
+    //未发送响应时为None
+    pub status: Option<u16>,
+    pub body_bytes: u64,
+    //含响应头
+    pub bytes_sent: u64,
+    pub start: Instant,
+    //所属实例配置的时区（小时）
+    pub timezone: i32,
+}
+
+/*
+ * 访问日志文件，按路径缓存打开的文件，重新加载配置时关闭以便日志轮转后重新创建
+ */
+pub struct AccessLog {
+    files: Mutex<HashMap<PathBuf, File>>,
+}
+
+impl AccessLog {
+    pub fn new() -> AccessLog {
+        AccessLog {
+            files: Mutex::new(HashMap::new()),
+        }
+    }
+
+    //按请求的主机名选择日志文件，未单独配置的主机写入access_log
+    pub fn log(&self, config: &AccessLogConfig, host: Option<&str>, record: &Record) {
+        let path = match host.and_then(|host| config.hosts.get(&host.to_lowercase())) {
+            Some(path) => path.as_ref(),
+            None => config.path.as_ref(),
+        };
+        if let Some(path) = path {
+            self.write(path, &config.format.format(record));
+        }
+    }
+
+    fn write(&self, path: &Path, line: &str) {
+        let mut files = self.files.lock().unwrap_or_else(|e| e.into_inner());
+        if !files.contains_key(path) {
+            match OpenOptions::new().create(true).append(true).open(path) {
+                Ok(file) => {
+                    files.insert(path.to_owned(), file);
+                }
+                Err(e) => {
+                    log_error!("Failed to open access log {}. Error:{}", path.display(), e);
+                    return;
+                }
+            }
+        }
+        if let Some(file) = files.get_mut(path) {
+            if let Err(e) = file.write_all(format!("{}\n", line).as_bytes()) {
+                log_error!("Failed to write access log {}. Error:{}", path.display(), e);
+                files.remove(path);
+            }
+        }
+    }
+
+    pub fn reopen(&self) {
+        self.files.lock().unwrap_or_else(|e| e.into_inner()).clear();
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{env, fs};
+
+    use super::*;
+
+    fn record<'a>(header: &'a RequestHeader) -> Record<'a> {
+        Record {
+            client: "203.0.113.7",
+            request: "GET /index.html HTTP/1.1",
+            header,
+            status: Some(200),
+            body_bytes: 512,
+            bytes_sent: 640,
+            start: Instant::now(),
+            timezone: 8,
+        }
+    }
+
+    fn header() -> RequestHeader {
+        let mut params = HashMap::new();
+        params.insert("user-agent".to_owned(), "curl/8.0 \"x\"".to_owned());
+        params.insert("x-request-id".to_owned(), "abc".to_owned());
+        RequestHeader { params }
+    }
+
+    #[test]
+    fn test_format() {
+        let header = header();
+        let line = LogFormat::parse("combined")
+            .unwrap()
+            .format(&record(&header));
+        assert!(line.starts_with("203.0.113.7 - - ["), "{}", line);
+        assert!(
+            line.ends_with("] \"GET /index.html HTTP/1.1\" 200 512 \"-\" \"curl/8.0 \\x22x\\x22\""),
+            "{}",
+            line
+        );
+        let format = LogFormat::parse(
+            "$request_method ${request_uri}x $status $bytes_sent $http_x_request_id $upstream_response_time",
+        )
+        .unwrap();
+        assert_eq!(
+            format.format(&record(&header)),
+            "GET /index.htmlx 200 640 abc -"
+        );
+        let mut no_response = record(&header);
+        no_response.status = None;
+        no_response.request = "GET /\n";
+        assert_eq!(
+            LogFormat::parse("$status $request")
+                .unwrap()
+                .format(&no_response),
+            "- GET /\\x0A"
+        );
+        //使用记录中所属实例的时区
+        assert!(LogFormat::parse("$time_iso8601")
+            .unwrap()
+            .format(&record(&header))
+            .ends_with("+08:00"));
+        assert!(LogFormat::parse("$request_time")
+            .unwrap()
+            .format(&record(&header))
+            .starts_with("0.00"));
+        assert_eq!(
+            LogFormat::parse("$bogus").unwrap_err(),
+            "unknown variable '$bogus'"
+        );
+        assert!(LogFormat::parse("${status").is_err());
+    }
+
+    #[test]
+    fn test_targets() {
+        let dir = env::temp_dir().join(format!("star-http-access-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let mut hosts = HashMap::new();
+        hosts.insert("example.com".to_owned(), Some(dir.join("example.log")));
+        hosts.insert("quiet.example.com".to_owned(), None);
+        let config = AccessLogConfig {
+            path: Some(dir.join("access.log")),
+            format: LogFormat::parse("$remote_addr $status").unwrap(),
+            hosts,
+        };
+        let log = AccessLog::new();
+        let header = header();
+        log.log(&config, Some("Example.com"), &record(&header));
+        log.log(&config, Some("other.com"), &record(&header));
+        log.log(&config, None, &record(&header));
+        log.log(&config, Some("quiet.example.com"), &record(&header));
+        log.reopen();
+        log.log(&config, None, &record(&header));
+        assert_eq!(
+            fs::read_to_string(dir.join("example.log")).unwrap(),
+            "203.0.113.7 200\n"
+        );
+        assert_eq!(
+            fs::read_to_string(dir.join("access.log")).unwrap(),
+            "203.0.113.7 200\n".repeat(3)
+        );
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
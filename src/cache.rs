@@ -0,0 +1,421 @@
+use std::{
+    collections::BTreeMap,
+    fs::{self, File, Metadata},
+    io,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+use crate::{log_error, mime, stats::Stats};
+
+//缓存的静态文件内容
+pub struct CachedFile {
+    pub bytes: Arc<Vec<u8>>,
+    //预压缩的.gz版本
+    pub gzip: Option<Gzip>,
+    pub etag: String,
+    pub mime: Option<&'static str>,
+    modified: Option<SystemTime>,
+    gzip_modified: Option<SystemTime>,
+}
+
+//.gz版本是不同的表示，使用单独的ETag
+pub struct Gzip {
+    pub bytes: Arc<Vec<u8>>,
+    pub etag: String,
+}
+
+struct Entry {
+    file: Arc<CachedFile>,
+    size: usize,
+    last_used: u64,
+}
+
+struct Lru {
+    entries: BTreeMap<PathBuf, Entry>,
+    //按最近使用时间排序的索引，最早的在前
+    order: BTreeMap<u64, PathBuf>,
+    //已使用字节数
+    used: usize,
+    clock: u64,
+}
+
+impl Lru {
+    fn tick(&mut self) -> u64 {
+        self.clock += 1;
+        self.clock
+    }
+
+    //命中时更新使用时间
+    fn touch(&mut self, path: &Path) -> Option<Arc<CachedFile>> {
+        let clock = self.tick();
+        let entry = self.entries.get_mut(path)?;
+        let path = self.order.remove(&entry.last_used)?;
+        entry.last_used = clock;
+        let file = Arc::clone(&entry.file);
+        self.order.insert(clock, path);
+        Some(file)
+    }
+
+    fn insert(&mut self, path: &Path, file: Arc<CachedFile>, size: usize) {
+        self.remove(path);
+        let last_used = self.tick();
+        self.entries.insert(
+            path.to_path_buf(),
+            Entry {
+                file,
+                size,
+                last_used,
+            },
+        );
+        self.order.insert(last_used, path.to_path_buf());
+        self.used += size;
+    }
+
+    fn remove(&mut self, path: &Path) {
+        if let Some(entry) = self.entries.remove(path) {
+            self.order.remove(&entry.last_used);
+            self.used -= entry.size;
+        }
+    }
+
+    //淘汰最久未使用的条目直到不超过预算
+    fn evict(&mut self, max_bytes: usize) {
+        while self.used > max_bytes {
+            match self.order.pop_first() {
+                Some((_, path)) => {
+                    if let Some(entry) = self.entries.remove(&path) {
+                        self.used -= entry.size;
+                    }
+                }
+                None => break,
+            }
+        }
+    }
+}
+
+/*
+ * 小文件内存缓存，按总字节预算LRU淘汰，文件修改时间变化时失效
+ */
+pub struct ContentCache {
+    lru: Mutex<Lru>,
+}
+
+impl ContentCache {
+    pub const fn new() -> ContentCache {
+        ContentCache {
+            lru: Mutex::new(Lru {
+                entries: BTreeMap::new(),
+                order: BTreeMap::new(),
+                used: 0,
+                clock: 0,
+            }),
+        }
+    }
+
+    /*
+     * 获取文件内容，未缓存时读取并加入缓存
+     * metadata与gzip_metadata为文件及其.gz版本的元数据，用于判断缓存是否失效
+     * 超过单文件上限时返回None，由调用方直接读取磁盘，不计入未命中
+     */
+    pub fn get(
+        &self,
+        stats: &Stats,
+        path: &Path,
+        metadata: &Metadata,
+        gzip_metadata: Option<&Metadata>,
+        max_bytes: usize,
+        max_file_size: usize,
+    ) -> Option<Arc<CachedFile>> {
+        let modified = metadata.modified().ok();
+        let gzip_modified = gzip_metadata.as_ref().and_then(|m| m.modified().ok());
+        if metadata.len() > max_file_size as u64 {
+            return None;
+        }
+        {
+            let mut lru = self.lru.lock().unwrap_or_else(|e| e.into_inner());
+            if let Some(entry) = lru.entries.get(path) {
+                if entry.file.modified == modified
+                    && entry.file.gzip_modified == gzip_modified
+                    && entry.file.bytes.len() as u64 == metadata.len()
+                {
+                    Stats::incr(&stats.cache_hits);
+                    return lru.touch(path);
+                }
+                lru.remove(path);
+            }
+        }
+        Stats::incr(&stats.cache_misses);
+        let bytes = match fs::read(path) {
+            Ok(bytes) => bytes,
+            Err(e) => {
+                log_error!("Failed to cache {}. Error:{}", path.display(), e);
+                return None;
+            }
+        };
+        let gzip_metadata = gzip_metadata.filter(|m| m.len() <= max_file_size as u64);
+        let gzip = gzip_metadata.and_then(|metadata| {
+            fs::read(gzip_path(path)).ok().map(|bytes| Gzip {
+                bytes: Arc::new(bytes),
+                etag: gzip_etag(metadata),
+            })
+        });
+        let size = bytes.len() + gzip.as_ref().map_or(0, |gzip| gzip.bytes.len());
+        let file = Arc::new(CachedFile {
+            bytes: Arc::new(bytes),
+            gzip,
+            etag: etag(metadata),
+            mime: mime::from_path(path),
+            modified,
+            gzip_modified,
+        });
+        if size <= max_bytes {
+            let mut lru = self.lru.lock().unwrap_or_else(|e| e.into_inner());
+            lru.insert(path, Arc::clone(&file), size);
+            lru.evict(max_bytes);
+        }
+        Some(file)
+    }
+}
+
+//已打开的文件句柄及其元数据，可在工作线程间共享
+#[derive(Clone)]
+pub struct OpenFile {
+    pub file: Arc<File>,
+    pub metadata: Metadata,
+}
+
+struct OpenEntry {
+    //None表示文件不存在（negative缓存）
+    file: Option<OpenFile>,
+    checked: Instant,
+    last_used: u64,
+}
+
+struct OpenFiles {
+    entries: BTreeMap<PathBuf, OpenEntry>,
+    clock: u64,
+}
+
+/*
+ * 打开文件缓存：在有效期内复用文件句柄、元数据及不存在的查找结果，减少stat/open调用
+ */
+pub struct OpenFileCache {
+    files: Mutex<OpenFiles>,
+}
+
+impl OpenFileCache {
+    pub const fn new() -> OpenFileCache {
+        OpenFileCache {
+            files: Mutex::new(OpenFiles {
+                entries: BTreeMap::new(),
+                clock: 0,
+            }),
+        }
+    }
+
+    /*
+     * 打开普通文件，不存在或不是普通文件时返回None
+     * max_entries为0时不使用缓存
+     */
+    pub fn open(
+        &self,
+        stats: &Stats,
+        path: &Path,
+        max_entries: usize,
+        valid: Duration,
+    ) -> io::Result<Option<OpenFile>> {
+        if max_entries == 0 {
+            return open_file(path);
+        }
+        {
+            let mut files = self.files.lock().unwrap_or_else(|e| e.into_inner());
+            files.clock += 1;
+            let clock = files.clock;
+            if let Some(entry) = files.entries.get_mut(path) {
+                if entry.checked.elapsed() < valid {
+                    entry.last_used = clock;
+                    Stats::incr(&stats.open_file_hits);
+                    return Ok(entry.file.clone());
+                }
+            }
+        }
+        Stats::incr(&stats.open_file_misses);
+        //打开失败（如权限不足）不缓存
+        let file = open_file(path)?;
+        let mut files = self.files.lock().unwrap_or_else(|e| e.into_inner());
+        let last_used = files.clock;
+        files.entries.insert(
+            path.to_path_buf(),
+            OpenEntry {
+                file: file.clone(),
+                checked: Instant::now(),
+                last_used,
+            },
+        );
+        while files.entries.len() > max_entries {
+            let oldest = files
+                .entries
+                .iter()
+                .min_by_key(|(_, entry)| entry.last_used)
+                .map(|(path, _)| path.clone());
+            match oldest {
+                Some(path) => files.entries.remove(&path),
+                None => break,
+            };
+        }
+        Ok(file)
+    }
+}
+
+fn open_file(path: &Path) -> io::Result<Option<OpenFile>> {
+    match File::open(path) {
+        Ok(file) => {
+            let metadata = file.metadata()?;
+            if !metadata.is_file() {
+                return Ok(None);
+            }
+            Ok(Some(OpenFile {
+                file: Arc::new(file),
+                metadata,
+            }))
+        }
+        Err(e)
+            if e.kind() == io::ErrorKind::NotFound || e.kind() == io::ErrorKind::NotADirectory =>
+        {
+            Ok(None)
+        }
+        Err(e) => Err(e),
+    }
+}
+
+//由修改时间与大小生成的弱校验ETag
+pub fn etag(metadata: &Metadata) -> String {
+    let modified = metadata
+        .modified()
+        .ok()
+        .and_then(|m| m.duration_since(UNIX_EPOCH).ok())
+        .map_or(0, |d| d.as_secs());
+    format!("\"{:x}-{:x}\"", modified, metadata.len())
+}
+
+//.gz版本的ETag，由其自身的元数据生成并加上-gzip后缀
+pub fn gzip_etag(metadata: &Metadata) -> String {
+    let etag = etag(metadata);
+    format!("{}-gzip\"", &etag[..etag.len() - 1])
+}
+
+pub fn gzip_path(path: &Path) -> PathBuf {
+    let mut gzip_path = path.as_os_str().to_owned();
+    gzip_path.push(".gz");
+    PathBuf::from(gzip_path)
+}
+
+#[cfg(test)]
+mod test {
+    use std::{env, fs, sync::atomic::Ordering, thread, time::Duration};
+
+    use super::*;
+
+    fn get(
+        cache: &ContentCache,
+        path: &Path,
+        max_bytes: usize,
+        max_file_size: usize,
+    ) -> Option<Arc<CachedFile>> {
+        let metadata = fs::metadata(path).unwrap();
+        let gzip_metadata = fs::metadata(gzip_path(path)).ok();
+        cache.get(
+            &Stats::new(),
+            path,
+            &metadata,
+            gzip_metadata.as_ref(),
+            max_bytes,
+            max_file_size,
+        )
+    }
+
+    #[test]
+    fn test_lru_and_invalidation() {
+        let dir = env::temp_dir().join(format!("star-http-cache-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let a = dir.join("a.txt");
+        let b = dir.join("b.css");
+        fs::write(&a, "aaaa").unwrap();
+        fs::write(dir.join("a.txt.gz"), "gz").unwrap();
+        fs::write(&b, "bbbbb").unwrap();
+        let cache = ContentCache::new();
+        let file = get(&cache, &a, 10, 10).unwrap();
+        assert_eq!(file.bytes.as_slice(), b"aaaa");
+        let gzip = file.gzip.as_ref().unwrap();
+        assert_eq!(gzip.bytes.as_slice(), b"gz");
+        assert_eq!(
+            gzip.etag,
+            gzip_etag(&fs::metadata(dir.join("a.txt.gz")).unwrap())
+        );
+        assert!(gzip.etag.ends_with("-gzip\""));
+        assert_eq!(file.mime, Some("text/plain; charset=utf-8"));
+        assert!(Arc::ptr_eq(&file, &get(&cache, &a, 10, 10).unwrap()));
+        //预算只能容纳一个文件，a被淘汰
+        get(&cache, &b, 10, 10).unwrap();
+        assert_eq!(cache.lru.lock().unwrap().entries.len(), 1);
+        assert!(!Arc::ptr_eq(&file, &get(&cache, &a, 10, 10).unwrap()));
+        //超过单文件上限不缓存，也不计入未命中
+        let c = dir.join("c.txt");
+        fs::write(&c, "cccccc").unwrap();
+        assert!(get(&cache, &c, 10, 2).is_none());
+        let stats = Stats::new();
+        let metadata = fs::metadata(&c).unwrap();
+        assert!(cache.get(&stats, &c, &metadata, None, 10, 2).is_none());
+        assert_eq!(stats.cache_misses.load(Ordering::Relaxed), 0);
+        //淘汰最久未使用的条目：访问b后加入c，淘汰a
+        let cache = ContentCache::new();
+        get(&cache, &a, 12, 10).unwrap();
+        get(&cache, &b, 12, 10).unwrap();
+        get(&cache, &a, 12, 10).unwrap();
+        get(&cache, &b, 12, 10).unwrap();
+        get(&cache, &c, 12, 10).unwrap();
+        let lru = cache.lru.lock().unwrap();
+        assert!(lru.entries.contains_key(&b) && lru.entries.contains_key(&c));
+        assert!(!lru.entries.contains_key(&a));
+        assert_eq!(lru.order.len(), 2);
+        assert_eq!(lru.used, 11);
+        drop(lru);
+        //修改后失效
+        thread::sleep(Duration::from_millis(20));
+        fs::write(&a, "aaaaa").unwrap();
+        assert_eq!(get(&cache, &a, 10, 10).unwrap().bytes.as_slice(), b"aaaaa");
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_open_file_cache() {
+        let dir = env::temp_dir().join(format!("star-http-open-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let a = dir.join("a.txt");
+        let cache = OpenFileCache::new();
+        let stats = Stats::new();
+        let valid = Duration::from_secs(60);
+        //negative缓存在有效期内生效
+        assert!(cache.open(&stats, &a, 2, valid).unwrap().is_none());
+        fs::write(&a, "a").unwrap();
+        assert!(cache.open(&stats, &a, 2, valid).unwrap().is_none());
+        assert!(cache.open(&stats, &a, 2, Duration::ZERO).unwrap().is_some());
+        let file = cache.open(&stats, &a, 2, valid).unwrap().unwrap();
+        assert_eq!(file.metadata.len(), 1);
+        assert!(Arc::ptr_eq(
+            &file.file,
+            &cache.open(&stats, &a, 2, valid).unwrap().unwrap().file
+        ));
+        //目录与不存在的上级路径
+        assert!(cache.open(&stats, &dir, 2, valid).unwrap().is_none());
+        assert!(cache
+            .open(&stats, &a.join("x"), 2, valid)
+            .unwrap()
+            .is_none());
+        assert_eq!(cache.files.lock().unwrap().entries.len(), 2);
+        assert!(open_file(&a).unwrap().is_some());
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,194 @@
+use std::path::PathBuf;
+
+use crate::{
+    config::{ConfigSource, Origin},
+    toml::Value,
+};
+
+pub const USAGE: &str = "\
+Usage: star-http [OPTIONS]
+       star-http serve [DIR] [OPTIONS]
+
+`serve` serves DIR (default: the current directory) on 127.0.0.1:8000 without a configuration file.
+
+Options:
+  -c, --config <PATH>   Configuration file (default: config.toml next to the binary, then in the current directory)
+  -r, --root <DIR>      Static resource directory
+  -l, --listen <ADDR>   Listen address, IP:PORT, [IPV6]:PORT or PORT (all IPv4 addresses);
+                        may be given more than once, replaces `listen` in the configuration file
+  -p, --port <PORT>     Listen port (used when `listen` is not configured)
+  -t, --threads <N>     Number of worker threads
+      --dev             Development mode: reload pages when files change, disable caching
+      --check-config    Validate the configuration and exit
+      --dump-config     Print the effective configuration and where each value came from
+  -V, --version         Print version
+  -h, --help            Print help
+
+Configuration values are taken from, in order of precedence: command-line options,
+STARHTTP_* environment variables (e.g. STARHTTP_PORT=8080), the configuration file, defaults.
+";
+
+//命令行指定的操作
+pub enum Command {
+    //启动服务
+    Run(ConfigSource),
+    //只校验配置，有错误时以非零状态退出
+    Check(ConfigSource),
+    //输出生效的配置及其来源
+    Dump(ConfigSource),
+    Version,
+    Help,
+}
+
+fn set(source: &mut ConfigSource, key: &'static str, value: Value, option: &'static str) {
+    source.overrides.push((key, value, Origin::Cli(option)));
+}
+
+fn integer(option: &str, value: &str) -> Result<Value, String> {
+    value
+        .parse()
+        .map(Value::Integer)
+        .map_err(|_| format!("{} expects an integer, found '{}'", option, value))
+}
+
+//多次指定时合并为一个监听列表，地址格式由配置校验
+fn listen(source: &mut ConfigSource, value: String) {
+    let addr = Value::String(value);
+    let existing = source
+        .overrides
+        .iter_mut()
+        .find(|(key, _, _)| *key == "listen");
+    match existing {
+        Some((_, Value::Array(addrs), _)) => addrs.push(addr),
+        _ => set(source, "listen", Value::Array(vec![addr]), "--listen"),
+    }
+}
+
+/*
+ * 解析启动参数（不含程序名），命令行参数覆盖配置文件中的同名配置
+ * 参数值可写作“--port 8000”或“--port=8000”
+ */
+pub fn parse(args: impl IntoIterator<Item = String>) -> Result<Command, String> {
+    let mut args = args.into_iter().peekable();
+    let mut source = ConfigSource::default();
+    let mut check = false;
+    let mut dump = false;
+    if args.peek().is_some_and(|arg| arg == "serve") {
+        args.next();
+        source.serve = true;
+    }
+    let mut dir_given = false;
+    while let Some(arg) = args.next() {
+        let (name, inline) = match arg.split_once('=') {
+            Some((name, value)) if name.starts_with("--") => (name, Some(value.to_owned())),
+            _ => (arg.as_str(), None),
+        };
+        let mut value = |option: &str| {
+            inline
+                .clone()
+                .or_else(|| args.next())
+                .ok_or_else(|| format!("{} requires a value", option))
+        };
+        match name {
+            "-c" | "--config" => source.path = Some(PathBuf::from(value("--config")?)),
+            "-r" | "--root" => {
+                let root = Value::String(value("--root")?);
+                set(&mut source, "static_resource_path", root, "--root");
+            }
+            "-l" | "--listen" => listen(&mut source, value("--listen")?),
+            "-p" | "--port" => {
+                let port = integer("--port", &value("--port")?)?;
+                set(&mut source, "port", port, "--port");
+            }
+            //固定大小的线程池
+            "-t" | "--threads" => {
+                let threads = integer("--threads", &value("--threads")?)?;
+                set(&mut source, "min_threads", threads.clone(), "--threads");
+                set(&mut source, "max_threads", threads, "--threads");
+            }
+            "--dev" => set(&mut source, "dev", Value::Boolean(true), "--dev"),
+            "--check-config" => check = true,
+            "--dump-config" => dump = true,
+            "-V" | "--version" => return Ok(Command::Version),
+            "-h" | "--help" => return Ok(Command::Help),
+            //serve子命令的目录
+            _ if source.serve && !dir_given && !arg.starts_with('-') => {
+                dir_given = true;
+                set(
+                    &mut source,
+                    "static_resource_path",
+                    Value::String(arg),
+                    "serve",
+                );
+            }
+            _ => return Err(format!("unexpected argument '{}'", arg)),
+        }
+    }
+    if dump {
+        return Ok(Command::Dump(source));
+    }
+    if check {
+        return Ok(Command::Check(source));
+    }
+    Ok(Command::Run(source))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn args(line: &str) -> Result<Command, String> {
+        parse(line.split_whitespace().map(String::from))
+    }
+
+    fn overrides(command: Command) -> Vec<(&'static str, Value)> {
+        match command {
+            Command::Run(source) | Command::Check(source) | Command::Dump(source) => source
+                .overrides
+                .into_iter()
+                .map(|(key, value, _)| (key, value))
+                .collect(),
+            _ => panic!("not a run command"),
+        }
+    }
+
+    #[test]
+    fn test_parse_args() {
+        let text = |s: &str| Value::String(s.to_owned());
+        assert_eq!(
+            overrides(args("--root ./www --listen=0.0.0.0:8080 -t 4 -l [::]:8080 --dev").unwrap()),
+            vec![
+                ("static_resource_path", text("./www")),
+                (
+                    "listen",
+                    Value::Array(vec![text("0.0.0.0:8080"), text("[::]:8080")])
+                ),
+                ("min_threads", Value::Integer(4)),
+                ("max_threads", Value::Integer(4)),
+                ("dev", Value::Boolean(true)),
+            ]
+        );
+        match args("serve ./dir --port 8000 --check-config").unwrap() {
+            Command::Check(source) => {
+                assert!(source.serve);
+                assert_eq!(source.overrides[0].1, text("./dir"));
+                assert_eq!(source.overrides[1].1, Value::Integer(8000));
+            }
+            _ => panic!("expected --check-config"),
+        }
+        match args("-c /etc/star-http.toml").unwrap() {
+            Command::Run(source) => {
+                assert_eq!(source.path, Some(PathBuf::from("/etc/star-http.toml")));
+                assert!(!source.serve);
+            }
+            _ => panic!("expected run"),
+        }
+        assert!(matches!(args("--port 80 --help"), Ok(Command::Help)));
+        assert!(matches!(args("-V"), Ok(Command::Version)));
+        assert!(matches!(args("--dump-config"), Ok(Command::Dump(_))));
+        assert!(args("--port").is_err());
+        assert!(args("--port http").is_err());
+        assert!(args("./dir").is_err());
+        assert!(args("serve ./a ./b").is_err());
+    }
+}
@@ -1,230 +1,1303 @@
-use std::{collections::HashMap, env, fs::File, io::Read, path::PathBuf, sync::Once};
+use std::{
+    collections::HashMap,
+    env,
+    fmt::Display,
+    fs,
+    net::{IpAddr, SocketAddr},
+    path::{Path, PathBuf},
+    sync::Arc,
+    thread,
+    time::{Duration, SystemTime},
+};
+
+use crate::{
+    access_log::LogFormat,
+    glob,
+    log::{Formatter, JsonFormatter, TextFormatter},
+    log_error,
+    proxy::Cidr,
+    toml::{self, Table, Value},
+};
 
 const CONFIG_PATH: &str = "config.toml";
-static ONCE: Once = Once::new();
-static mut CONFIG: Option<HashMap<String, ConfValType>> = Option::None;
+//覆盖配置项的环境变量前缀，如STARHTTP_PORT对应port，“__”对应下级表，如STARHTTP_A__B对应a.b
+const ENV_PREFIX: &str = "STARHTTP_";
+//配置文件监听的轮询间隔
+const WATCH_INTERVAL: Duration = Duration::from_secs(1);
+
+//配置项的类型
+#[derive(Clone, Copy)]
+enum Type {
+    Text,
+    //整数及取值范围（含两端）
+    Integer(i64, i64),
+    //开关，兼容旧版本的1/0写法
+    Bool,
+    //可选值之一
+    Choice(&'static [&'static str]),
+    //IPv4或IPv6地址
+    Ip,
+    //监听地址列表，见parse_listen
+    Listen,
+    //IP网段列表，见proxy::Cidr
+    Cidrs,
+    //访问日志格式，见access_log::LogFormat
+    LogFormat,
+    //主机名到文件路径的表
+    HostPaths,
+    //SNI证书表数组，见parse_sni
+    Sni,
+}
+
+impl Type {
+    fn check(&self, value: &Value) -> Result<(), String> {
+        match (self, value) {
+            (Type::Text, Value::String(_)) => Ok(()),
+            (Type::Integer(min, max), Value::Integer(n)) if n < min || n > max => {
+                if *max == i64::MAX {
+                    Err(format!("{} is less than {}", n, min))
+                } else {
+                    Err(format!("{} is out of range {}..={}", n, min, max))
+                }
+            }
+            (Type::Integer(..), Value::Integer(_)) => Ok(()),
+            (Type::Bool, Value::Boolean(_) | Value::Integer(0 | 1)) => Ok(()),
+            (Type::Choice(choices), Value::String(text)) if choices.contains(&text.as_str()) => {
+                Ok(())
+            }
+            (Type::Choice(choices), Value::String(text)) => Err(format!(
+                "\"{}\" is not one of \"{}\"",
+                text,
+                choices.join("\", \"")
+            )),
+            (Type::Ip, Value::String(text)) if text.parse::<IpAddr>().is_ok() => Ok(()),
+            (Type::Ip, Value::String(text)) => Err(format!("\"{}\" is not an IP address", text)),
+            (Type::Listen, value) => parse_listen(value).map(|_| ()),
+            (Type::Cidrs, value) => parse_cidrs(value).map(|_| ()),
+            (Type::LogFormat, Value::String(text)) => LogFormat::parse(text).map(|_| ()),
+            (Type::HostPaths, Value::Table(table)) => parse_host_paths(table).map(|_| ()),
+            (Type::Sni, Value::Array(items)) => parse_sni(items).map(|_| ()),
+            (_, Value::Array(_) | Value::Table(_)) => Err(format!(
+                "expected {}, found {}",
+                self.name(),
+                value.type_name()
+            )),
+            _ => Err(format!(
+                "expected {}, found {} {}",
+                self.name(),
+                value.type_name(),
+                value
+            )),
+        }
+    }
+
+    fn name(&self) -> &'static str {
+        match self {
+            Type::Text | Type::Choice(_) | Type::Ip | Type::LogFormat => "a string",
+            Type::HostPaths => "a table",
+            Type::Sni => "an array of tables",
+            Type::Listen | Type::Cidrs => "a string or an array",
+            Type::Integer(..) => "an integer",
+            Type::Bool => "a boolean",
+        }
+    }
+}
+
+//未配置时的取值
+#[derive(Clone, Copy)]
+enum Default {
+    Required,
+    Optional,
+    Text(&'static str),
+    Integer(i64),
+    Bool(bool),
+}
+
+struct Field {
+    key: &'static str,
+    kind: Type,
+    default: Default,
+}
+
+const fn field(key: &'static str, kind: Type, default: Default) -> Field {
+    Field { key, kind, default }
+}
+
+const UNLIMITED: i64 = i64::MAX;
+const THREADS: Type = Type::Integer(1, 4096);
+const PORT: Type = Type::Integer(1, 65535);
+
+//配置模式：config.toml中允许的配置项
+const SCHEMA: &[Field] = &[
+    field("static_resource_path", Type::Text, Default::Required),
+    field("index_page_path", Type::Text, Default::Text("index.html")),
+    field("page404_path", Type::Text, Default::Optional),
+    field("page500_path", Type::Text, Default::Optional),
+    //与min_threads至少配置一个
+    field("thread_pool_size", THREADS, Default::Optional),
+    field("min_threads", THREADS, Default::Optional),
+    field("max_threads", THREADS, Default::Optional),
+    field(
+        "thread_idle_timeout",
+        Type::Integer(1, UNLIMITED),
+        Default::Integer(60),
+    ),
+    field(
+        "queue_capacity",
+        Type::Integer(1, UNLIMITED),
+        Default::Integer(1024),
+    ),
+    field(
+        "queue_full_policy",
+        Type::Choice(&["reject", "drop"]),
+        Default::Text("reject"),
+    ),
+    field(
+        "retry_after",
+        Type::Integer(0, UNLIMITED),
+        Default::Integer(5),
+    ),
+    field(
+        "shutdown_grace_period",
+        Type::Integer(0, UNLIMITED),
+        Default::Integer(30),
+    ),
+    field("config_watch", Type::Bool, Default::Bool(false)),
+    //运行日志格式
+    field(
+        "log_format",
+        Type::Choice(&["text", "json"]),
+        Default::Text("text"),
+    ),
+    field("dev", Type::Bool, Default::Bool(false)),
+    field(
+        "io_model",
+        Type::Choice(&["classic", "event"]),
+        Default::Text("classic"),
+    ),
+    field("timezone", Type::Integer(-12, 14), Default::Required),
+    //未配置listen时监听ip:port
+    field("listen", Type::Listen, Default::Optional),
+    field("ip", Type::Ip, Default::Optional),
+    field("port", PORT, Default::Optional),
+    //可信代理，来自这些地址的请求按Forwarded或X-Forwarded-For确定客户端地址
+    field("trusted_proxies", Type::Cidrs, Default::Optional),
+    field(
+        "cache_max_bytes",
+        Type::Integer(0, UNLIMITED),
+        Default::Integer(0),
+    ),
+    field(
+        "cache_max_file_size",
+        Type::Integer(1, UNLIMITED),
+        Default::Integer(1024 * 1024),
+    ),
+    field(
+        "open_file_cache_max",
+        Type::Integer(0, UNLIMITED),
+        Default::Integer(0),
+    ),
+    field(
+        "open_file_cache_valid",
+        Type::Integer(0, UNLIMITED),
+        Default::Integer(60),
+    ),
+    field("stats_path", Type::Text, Default::Optional),
+    //访问日志文件，off或未配置时不记录
+    field("access_log", Type::Text, Default::Optional),
+    field(
+        "access_log_format",
+        Type::LogFormat,
+        Default::Text("combined"),
+    ),
+    //按主机名单独配置的访问日志文件
+    field("access_log_hosts", Type::HostPaths, Default::Optional),
+    field("sse_path", Type::Text, Default::Optional),
+    field(
+        "sse_heartbeat",
+        Type::Integer(1, UNLIMITED),
+        Default::Integer(15),
+    ),
+    field("http2", Type::Bool, Default::Bool(true)),
+    field("tls_port", PORT, Default::Optional),
+    field("tls_cert_path", Type::Text, Default::Optional),
+    field("tls_key_path", Type::Text, Default::Optional),
+    field("tls_sni", Type::Sni, Default::Optional),
+    field("tls_redirect", Type::Bool, Default::Bool(false)),
+];
+
+fn schema(key: &str) -> Option<&'static Field> {
+    SCHEMA.iter().find(|field| field.key == key)
+}
+
+fn merge_table(into: &mut Table, from: Table) {
+    for (key, value) in from {
+        match (into.get_mut(&key), value) {
+            (Some(Value::Table(existing)), Value::Table(table)) => merge_table(existing, table),
+            (Some(Value::Array(existing)), Value::Array(items)) => existing.extend(items),
+            (_, value) => {
+                into.insert(key, value);
+            }
+        }
+    }
+}
+
+//环境变量的值按配置模式中的类型转换，无法转换或未知的配置项保留字符串，由校验报告
+fn env_value(key: &str, raw: &str) -> Value {
+    let text = || Value::String(raw.to_owned());
+    match schema(key).map(|field| field.kind) {
+        Some(Type::Integer(..)) => raw.trim().parse().map_or_else(|_| text(), Value::Integer),
+        Some(Type::Bool) => match raw.trim().to_ascii_lowercase().as_str() {
+            "true" | "1" | "yes" | "on" => Value::Boolean(true),
+            "false" | "0" | "no" | "off" => Value::Boolean(false),
+            _ => text(),
+        },
+        //多个地址以逗号分隔
+        Some(Type::Listen | Type::Cidrs) => Value::Array(
+            raw.split(',')
+                .map(|addr| Value::String(addr.trim().to_owned()))
+                .collect(),
+        ),
+        _ => text(),
+    }
+}
 
-#[derive(Debug, Clone)]
-enum ConfValType {
-    Text(String),
-    Num(isize),
-    None,
+//编辑距离，用于提示拼写错误的配置项
+fn distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut row: Vec<usize> = (0..=b.len()).collect();
+    for (i, ca) in a.chars().enumerate() {
+        let mut diagonal = row[0];
+        row[0] = i + 1;
+        for (j, cb) in b.iter().enumerate() {
+            let above = row[j + 1];
+            row[j + 1] = if ca == *cb {
+                diagonal
+            } else {
+                1 + diagonal.min(row[j]).min(above)
+            };
+            diagonal = above;
+        }
+    }
+    row[b.len()]
+}
+
+//配置值的来源
+#[derive(Clone, Debug, PartialEq)]
+pub enum Origin {
+    //配置文件及行号
+    File(String, usize),
+    //STARHTTP_*环境变量
+    Env(String),
+    //命令行参数
+    Cli(&'static str),
+}
+
+impl Origin {
+    //优先级，命令行最高
+    fn rank(&self) -> u8 {
+        match self {
+            Origin::File(..) => 0,
+            Origin::Env(_) => 1,
+            Origin::Cli(_) => 2,
+        }
+    }
+}
+
+impl Display for Origin {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Origin::File(path, line) => write!(f, "{}:{}", path, line),
+            Origin::Env(name) => write!(f, "env {}", name),
+            Origin::Cli(option) => write!(f, "option {}", option),
+        }
+    }
+}
+
+/*
+ * 配置来源：配置文件与命令行参数，重新加载时按相同的来源读取
+ */
+#[derive(Clone, Default)]
+pub struct ConfigSource {
+    //配置文件路径，未指定时使用默认位置
+    pub path: Option<PathBuf>,
+    //不读取配置文件（serve子命令）
+    pub serve: bool,
+    //命令行参数覆盖的配置项
+    pub overrides: Vec<(&'static str, Value, Origin)>,
+}
+
+impl ConfigSource {
+    //要读取的配置文件，serve子命令时为None
+    pub fn file(&self) -> Result<Option<PathBuf>, String> {
+        match &self.path {
+            _ if self.serve => Ok(None),
+            Some(path) if path.is_file() => Ok(Some(path.clone())),
+            Some(path) => Err(format!(
+                "The configuration file {} does not exist!",
+                path.display()
+            )),
+            None => config_path().map(Some),
+        }
+    }
 }
 
 pub struct Config {
-    config: HashMap<String, ConfValType>,
+    //配置文件名，用于错误提示
+    path: String,
+    config: Table,
+    //各配置项的来源
+    origins: HashMap<String, Origin>,
+    //读取的配置文件（含include的文件），用于监听修改
+    files: Vec<PathBuf>,
 }
 
 impl Config {
-    pub fn build() -> Config {
-        ONCE.call_once(|| {
-            parse_config(read_config());
-        });
-        unsafe {
-            match CONFIG.clone() {
-                Some(config) => Config { config },
-                None => panic!("The initial configuration fails!"),
+    pub fn load(source: &ConfigSource) -> Result<Config, String> {
+        let mut config = match source.file()? {
+            Some(path) => Self::load_file(&path)?,
+            None => Self::serve_defaults(),
+        };
+        config.apply(source, env::vars());
+        Ok(config)
+    }
+
+    fn new(path: &str) -> Config {
+        Config {
+            path: path.to_owned(),
+            config: Table::new(),
+            origins: HashMap::new(),
+            files: Vec::new(),
+        }
+    }
+
+    //读取配置文件及其包含的文件
+    fn load_file(path: &Path) -> Result<Config, String> {
+        let mut config = Self::new(&path.display().to_string());
+        config.include(path, &mut Vec::new(), None)?;
+        Ok(config)
+    }
+
+    #[cfg(test)]
+    fn parse(path: &str, text: &str) -> Result<Config, String> {
+        let mut config = Self::new(path);
+        config.merge(path, text)?;
+        Ok(config)
+    }
+
+    /*
+     * 读取一个配置文件合并到当前配置，再依次读取其中include的文件
+     * include的路径相对于所在文件，可使用通配符，匹配到的文件按路径排序
+     * stack为正在读取的文件，用于检测循环包含；from为include所在位置
+     */
+    fn include(
+        &mut self,
+        path: &Path,
+        stack: &mut Vec<PathBuf>,
+        from: Option<&Origin>,
+    ) -> Result<(), String> {
+        let name = path.display().to_string();
+        let prefix = from.map_or(String::new(), |from| format!("{}: ", from));
+        let text = fs::read_to_string(path).map_err(|e| {
+            format!(
+                "{}Failed to read the configuration file {}. Error:{}",
+                prefix, name, e
+            )
+        })?;
+        let canonical = path.canonicalize().unwrap_or_else(|_| path.to_path_buf());
+        if stack.contains(&canonical) {
+            let chain: Vec<String> = stack
+                .iter()
+                .chain([&canonical])
+                .map(|file| file.display().to_string())
+                .collect();
+            return Err(format!("{}include cycle: {}", prefix, chain.join(" -> ")));
+        }
+        let include = self.merge(&name, &text)?;
+        self.files.push(canonical.clone());
+        let (include, line) = match include {
+            Some(include) => include,
+            None => return Ok(()),
+        };
+        let from = Origin::File(name, line);
+        let patterns = match include {
+            Value::String(pattern) => vec![pattern],
+            Value::Array(items) => items
+                .into_iter()
+                .map(|item| match item {
+                    Value::String(pattern) => Ok(pattern),
+                    _ => Err(format!("{}: include: expected an array of strings", from)),
+                })
+                .collect::<Result<_, _>>()?,
+            value => {
+                return Err(format!(
+                    "{}: include: expected a string or an array of strings, found {}",
+                    from,
+                    value.type_name()
+                ))
+            }
+        };
+        let dir = path.parent().unwrap_or(Path::new(""));
+        stack.push(canonical);
+        for pattern in patterns {
+            let pattern = dir.join(pattern);
+            if !glob::has_wildcard(&pattern.to_string_lossy()) && !pattern.is_file() {
+                return Err(format!(
+                    "{}: included file {} does not exist",
+                    from,
+                    pattern.display()
+                ));
+            }
+            for file in glob::expand(&pattern) {
+                if file.is_file() {
+                    self.include(&file, stack, Some(&from))?;
+                }
             }
         }
+        stack.pop();
+        Ok(())
     }
 
-    fn get_text(&self, key: &str) -> Option<String> {
-        match self.config.get(key) {
-            Some(config) => match config {
-                ConfValType::Text(config) => Some(config.clone()),
-                _ => None,
-            },
-            None => None,
+    /*
+     * 解析一个文件的配置并合并：表逐项合并，数组追加，其余配置项由后读取的文件覆盖
+     * 返回其中的include及所在行号
+     */
+    fn merge(&mut self, name: &str, text: &str) -> Result<Option<(Value, usize)>, String> {
+        let document = toml::parse(text).map_err(|e| format!("{}:{}", name, e))?;
+        let mut table = document.table;
+        let include = table.remove("include");
+        merge_table(&mut self.config, table);
+        let mut include_line = 0;
+        for (key, line) in document.lines {
+            if key == "include" {
+                include_line = line;
+                continue;
+            }
+            self.origins
+                .insert(key, Origin::File(name.to_owned(), line));
         }
+        Ok(include.map(|include| (include, include_line)))
     }
 
-    fn get_num(&self, key: &str) -> Option<isize> {
-        match self.config.get(key) {
-            Some(config) => match config {
-                ConfValType::Num(config) => Some(config.clone()),
-                _ => None,
-            },
-            None => None,
+    //serve子命令：不读取配置文件，在当前目录的8000端口提供服务
+    fn serve_defaults() -> Config {
+        let threads = thread::available_parallelism().map_or(4, |n| n.get());
+        let mut config = Self::new("serve");
+        config.config = Table::from([
+            (
+                "static_resource_path".to_owned(),
+                Value::String(".".to_owned()),
+            ),
+            ("timezone".to_owned(), Value::Integer(0)),
+            ("ip".to_owned(), Value::String("127.0.0.1".to_owned())),
+            ("port".to_owned(), Value::Integer(8000)),
+            (
+                "thread_pool_size".to_owned(),
+                Value::Integer(threads as i64),
+            ),
+        ]);
+        config
+    }
+
+    //带来源（文件行号或命令行参数）的提示信息，配置文件中的按行号排序
+    fn at(&self, key: &str, message: String) -> (usize, String) {
+        match self.origins.get(key) {
+            Some(origin @ Origin::File(_, line)) => (*line, format!("{}: {}", origin, message)),
+            Some(origin) => (0, format!("{}: {}", origin, message)),
+            None => (0, format!("{}: {}", self.path, message)),
         }
     }
-}
 
-fn read_config() -> String {
-    let exe_path = PathBuf::from(env::args().nth(0).unwrap());
-    let exe_dir = exe_path.parent().unwrap();
-    let mut config_path = exe_dir.join(CONFIG_PATH);
-    if !config_path.exists() {
-        config_path = PathBuf::from(CONFIG_PATH);
+    //依次应用环境变量与命令行参数，优先级：命令行 > 环境变量 > 配置文件 > 默认值
+    fn apply(&mut self, source: &ConfigSource, vars: impl Iterator<Item = (String, String)>) {
+        for (name, raw) in vars {
+            let key = match name.strip_prefix(ENV_PREFIX) {
+                Some(key) if !key.is_empty() => key.to_lowercase().replace("__", "."),
+                _ => continue,
+            };
+            let value = env_value(&key, &raw);
+            self.set(&key, value, Origin::Env(name));
+        }
+        for (key, value, origin) in &source.overrides {
+            self.set(key, value.clone(), origin.clone());
+        }
     }
-    if !config_path.exists() || !config_path.is_file() {
-        panic!("The configuration file does not exist!");
-    }
-    let mut file = File::open(config_path).unwrap();
-    let mut config = String::new();
-    file.read_to_string(&mut config).unwrap();
-    config
-}
-
-fn parse_config(config: String) {
-    let lines: Vec<&str> = config
-        .split('\n')
-        .map(|l| l.trim())
-        .filter(|l| !l.is_empty())
-        .collect();
-    let mut config_kv: HashMap<String, ConfValType> = HashMap::new();
-    for line in lines {
-        if line.starts_with("#") {
-            continue;
-        }
-        if line.contains("=") && !line.starts_with("=") {
-            let kv: Vec<&str> = line.split('=').map(|l| l.trim()).collect();
-            let key = *kv.get(0).unwrap();
-            let mut value = ConfValType::None;
-            if let Some(v) = kv.get(1) {
-                if v.starts_with("\"") {
-                    //读取字符串
-                    let vs: Vec<&str> = v.split("\"").collect();
-                    value = ConfValType::Text((*vs.get(1).unwrap()).to_owned());
-                } else {
-                    //读取数值
-                    let vs: Vec<&str> = v.split("#").collect();
-                    let v = (*(vs.get(0).unwrap())).trim();
-                    value = ConfValType::Num(v.parse::<isize>().unwrap());
+
+    //设置配置项，key中的“.”表示下级表
+    fn set(&mut self, key: &str, value: Value, origin: Origin) {
+        let parts: Vec<&str> = key.split('.').collect();
+        let (last, parents) = parts.split_last().unwrap();
+        let mut table = &mut self.config;
+        for (i, part) in parents.iter().enumerate() {
+            //新建的上级表沿用该项的来源，用于提示未知配置项
+            self.origins
+                .entry(parts[..=i].join("."))
+                .or_insert_with(|| origin.clone());
+            let entry = table
+                .entry(part.to_string())
+                .or_insert_with(|| Value::Table(Table::new()));
+            if !matches!(entry, Value::Table(_)) {
+                *entry = Value::Table(Table::new());
+            }
+            table = match entry {
+                Value::Table(next) => next,
+                _ => unreachable!(),
+            };
+        }
+        table.insert(last.to_string(), value);
+        self.origins.insert(key.to_owned(), origin);
+    }
+
+    /*
+     * 生效的配置及各项来源（--dump-config），未配置的项显示默认值
+     */
+    pub fn dump(&self) -> String {
+        let mut lines = Vec::new();
+        for field in SCHEMA {
+            let (value, origin) = match self.config.get(field.key) {
+                Some(value) => {
+                    let origin = match self.origins.get(field.key) {
+                        Some(origin) => origin.to_string(),
+                        None => self.path.clone(),
+                    };
+                    (value.clone(), origin)
+                }
+                None => match field.default {
+                    Default::Text(text) => (Value::String(text.to_owned()), "default".to_owned()),
+                    Default::Integer(n) => (Value::Integer(n), "default".to_owned()),
+                    Default::Bool(b) => (Value::Boolean(b), "default".to_owned()),
+                    Default::Required | Default::Optional => {
+                        lines.push(format!("#{} is not set", field.key));
+                        continue;
+                    }
+                },
+            };
+            lines.push(format!(
+                "{:<48} # {}",
+                format!("{} = {}", field.key, value),
+                origin
+            ));
+        }
+        lines.join("\n") + "\n"
+    }
+
+    /*
+     * 按配置模式校验，返回全部错误与警告（未知配置项），按行号排序
+     */
+    fn check(&self) -> (Vec<String>, Vec<String>) {
+        let mut errors = Vec::new();
+        let mut warnings = Vec::new();
+        for (key, value) in &self.config {
+            match schema(key) {
+                Some(field) => {
+                    if let Err(e) = field.kind.check(value) {
+                        errors.push(self.at(key, format!("{}: {}", key, e)));
+                    }
+                }
+                None => {
+                    let similar = SCHEMA
+                        .iter()
+                        .map(|field| (distance(key, field.key), field.key))
+                        .filter(|(distance, _)| *distance <= 2)
+                        .min();
+                    let message = match similar {
+                        Some((_, similar)) => {
+                            format!("unknown key '{}', did you mean '{}'?", key, similar)
+                        }
+                        None => format!("unknown key '{}'", key),
+                    };
+                    warnings.push(self.at(key, message));
+                }
+            }
+        }
+        for field in SCHEMA {
+            if matches!(field.default, Default::Required) && !self.config.contains_key(field.key) {
+                errors.push(self.at(field.key, format!("missing required key '{}'", field.key)));
+            }
+        }
+        //相互关联的配置项
+        if self.config.contains_key("listen") {
+            //优先级不低于listen的ip、port才提示，serve模式的默认值与被命令行覆盖的配置文件不提示
+            let ignored: Vec<&str> = ["ip", "port"]
+                .into_iter()
+                .filter(|key| {
+                    let rank = |key: &str| self.origins.get(key).map(Origin::rank);
+                    rank(key).is_some() && rank(key) >= rank("listen")
+                })
+                .collect();
+            if let Some(key) = ignored.first() {
+                let verb = if ignored.len() > 1 { "are" } else { "is" };
+                warnings.push(self.at(
+                    key,
+                    format!(
+                        "{} {} ignored because 'listen' is set",
+                        ignored.join(" and "),
+                        verb
+                    ),
+                ));
+            }
+        } else {
+            for key in ["ip", "port"] {
+                if !self.config.contains_key(key) {
+                    errors.push(self.at(key, format!("missing required key '{}'", key)));
+                }
+            }
+        }
+        let (min_threads, max_threads) = self.threads();
+        if min_threads.is_none() {
+            errors.push(self.at(
+                "thread_pool_size",
+                "missing required key 'thread_pool_size' (or 'min_threads')".to_owned(),
+            ));
+        }
+        if let (Some(min_threads), Some(max_threads)) = (min_threads, max_threads) {
+            if max_threads < min_threads {
+                errors.push(self.at(
+                    "max_threads",
+                    format!(
+                        "max_threads: {} is less than min_threads {}",
+                        max_threads, min_threads
+                    ),
+                ));
+            }
+        }
+        //未编译tls特性时无法监听HTTPS，tls_redirect会把明文请求重定向到无人监听的端口
+        if !cfg!(feature = "tls") && self.integer("tls_port").is_some() {
+            errors.push(self.at(
+                "tls_port",
+                "tls_port: the server was built without the tls feature".to_owned(),
+            ));
+        } else if self.integer("tls_port").is_some()
+            && (self.text("tls_cert_path").is_none() || self.text("tls_key_path").is_none())
+        {
+            errors.push(self.at(
+                "tls_port",
+                "tls_port: tls_cert_path and tls_key_path are required".to_owned(),
+            ));
+        }
+        if !cfg!(target_os = "linux") && self.text("io_model").as_deref() == Some("event") {
+            errors.push(self.at(
+                "io_model",
+                "io_model: \"event\" is only supported on Linux".to_owned(),
+            ));
+        }
+        //其它系统直接使用标准库绑定，无法设置这些套接字选项
+        if !cfg!(target_os = "linux") {
+            let listen = self.config.get("listen").and_then(|v| parse_listen(v).ok());
+            for listen in listen.unwrap_or_default() {
+                let default = ListenConfig::new(listen.addr.clone());
+                let options = [
+                    ("backlog", listen.backlog != default.backlog),
+                    ("reuse_port", listen.reuse_port),
+                    ("tcp_nodelay", listen.tcp_nodelay),
+                    ("ipv6_only", listen.ipv6_only),
+                ];
+                if let Some((key, _)) = options.iter().find(|(_, set)| *set) {
+                    errors.push(self.at(
+                        "listen",
+                        format!(
+                            "listen: {}: {} is only supported on Linux",
+                            listen.addr, key
+                        ),
+                    ));
                 }
             }
-            config_kv.insert(key.to_owned(), value);
         }
+        errors.sort_by_key(|(line, _)| *line);
+        warnings.sort_by_key(|(line, _)| *line);
+        let messages = |issues: Vec<(usize, String)>| issues.into_iter().map(|(_, m)| m).collect();
+        (messages(errors), messages(warnings))
+    }
+
+    //字符串配置，未配置时使用模式中的默认值
+    fn text(&self, key: &str) -> Option<String> {
+        match (self.config.get(key), schema(key).map(|field| field.default)) {
+            (Some(Value::String(config)), _) => Some(config.clone()),
+            (_, Some(Default::Text(default))) => Some(default.to_owned()),
+            _ => None,
+        }
+    }
+
+    fn integer(&self, key: &str) -> Option<i64> {
+        match (self.config.get(key), schema(key).map(|field| field.default)) {
+            (Some(Value::Integer(config)), _) => Some(*config),
+            (_, Some(Default::Integer(default))) => Some(default),
+            _ => None,
+        }
+    }
+
+    fn bool(&self, key: &str) -> bool {
+        match (self.config.get(key), schema(key).map(|field| field.default)) {
+            (Some(Value::Boolean(config)), _) => *config,
+            (Some(Value::Integer(config)), _) => *config != 0,
+            (_, Some(Default::Bool(default))) => default,
+            _ => false,
+        }
+    }
+
+    //常驻与最大线程数，min_threads未配置时与thread_pool_size相同（固定大小）
+    fn threads(&self) -> (Option<i64>, Option<i64>) {
+        let pool_size = self.integer("thread_pool_size");
+        let min_threads = self.integer("min_threads").or(pool_size);
+        let max_threads = self
+            .integer("max_threads")
+            .or_else(|| Some(pool_size?.max(min_threads?)))
+            .or(min_threads);
+        (min_threads, max_threads)
+    }
+}
+
+//优先使用程序所在目录下的配置文件，其次是当前目录
+fn config_path() -> Result<PathBuf, String> {
+    let exe_path = PathBuf::from(env::args().next().unwrap_or_default());
+    let mut config_path = exe_path
+        .parent()
+        .map(|dir| dir.join(CONFIG_PATH))
+        .unwrap_or_else(|| PathBuf::from(CONFIG_PATH));
+    if !config_path.exists() {
+        config_path = PathBuf::from(CONFIG_PATH);
     }
-    unsafe {
-        CONFIG = Option::Some(config_kv);
+    if !config_path.is_file() {
+        return Err("The configuration file does not exist!".to_owned());
     }
+    Ok(config_path)
 }
 
 //业务逻辑
-static MY_CONFIG_ONCE: Once = Once::new();
-static mut MY_CONFIG: Option<MyConfig> = None;
 #[derive(Clone)]
 pub struct MyConfig {
     pub static_resource_path: String,
     pub index_page_path: String,
     pub page404_path: Option<String>,
     pub page500_path: Option<String>,
-    pub thread_pool_size: usize,
+    pub min_threads: usize,
+    pub max_threads: usize,
+    pub thread_idle_timeout: u64,
     pub timezone: i32,
-    pub ip: std::net::Ipv4Addr,
+    //监听地址，至少一个
+    pub listen: Vec<ListenConfig>,
+    pub trusted_proxies: Vec<Cidr>,
+    pub sse_path: Option<String>,
+    pub sse_heartbeat: u64,
+    pub dev: bool,
+    pub tls: Option<TlsConfig>,
+    pub http2: bool,
+    pub io_model: IoModel,
+    pub cache_max_bytes: usize,
+    pub cache_max_file_size: usize,
+    pub stats_path: Option<String>,
+    pub access_log: Option<AccessLogConfig>,
+    pub open_file_cache_max: usize,
+    pub open_file_cache_valid: u64,
+    pub queue_capacity: usize,
+    pub queue_full_policy: QueueFullPolicy,
+    pub retry_after: u64,
+    pub shutdown_grace_period: u64,
+    pub config_watch: bool,
+    pub log_format: LogOutput,
+}
+
+//任务队列已满时对新连接的处理方式
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum QueueFullPolicy {
+    //返回503 Service Unavailable
+    Reject,
+    //直接关闭连接
+    Drop,
+}
+
+//运行日志的输出格式
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum LogOutput {
+    //时间 级别: 消息 key=value
+    Text,
+    //JSON Lines，包含timestamp、level、target、message与fields
+    Json,
+}
+
+impl LogOutput {
+    pub fn formatter(&self) -> Arc<dyn Formatter> {
+        match self {
+            LogOutput::Text => Arc::new(TextFormatter),
+            LogOutput::Json => Arc::new(JsonFormatter),
+        }
+    }
+}
+
+//连接处理模型
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum IoModel {
+    //每个连接占用一个工作线程
+    Classic,
+    //epoll事件循环读写，工作线程只负责生成响应（仅Linux）
+    Event,
+}
+
+//HTTPS监听配置
+#[derive(Clone, PartialEq)]
+#[cfg_attr(not(feature = "tls"), allow(dead_code))]
+pub struct TlsConfig {
     pub port: u16,
+    pub cert_path: String,
+    pub key_path: String,
+    //按SNI主机名选择的证书
+    pub sni: Vec<SniCert>,
+    //明文端口是否全部重定向到HTTPS
+    pub redirect: bool,
+    //是否通过ALPN提供HTTP/2
+    pub http2: bool,
+}
+
+//访问日志配置，路径为None时不记录
+#[derive(Clone, Debug, PartialEq)]
+pub struct AccessLogConfig {
+    pub path: Option<PathBuf>,
+    pub format: LogFormat,
+    //键为小写的主机名
+    pub hosts: HashMap<String, Option<PathBuf>>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct SniCert {
+    pub host: String,
+    pub cert_path: String,
+    pub key_path: String,
+}
+
+//监听地址：TCP地址或Unix套接字路径
+#[derive(Clone, Debug, PartialEq)]
+pub enum ListenAddr {
+    Tcp(SocketAddr),
+    Unix(PathBuf),
+}
+
+impl Display for ListenAddr {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ListenAddr::Tcp(addr) => write!(f, "{}", addr),
+            ListenAddr::Unix(path) => write!(f, "unix:{}", path.display()),
+        }
+    }
+}
+
+//监听地址及其选项
+#[derive(Clone, Debug, PartialEq)]
+pub struct ListenConfig {
+    pub addr: ListenAddr,
+    //等待accept的连接队列长度
+    pub backlog: i32,
+    //SO_REUSEPORT，允许多个进程监听同一地址
+    pub reuse_port: bool,
+    //关闭Nagle算法，小响应不等待合并
+    pub tcp_nodelay: bool,
+    //IPv6地址只接受IPv6连接，默认双栈（同时接受IPv4连接）
+    pub ipv6_only: bool,
+    //请求没有Host头时使用的主机名
+    pub default_host: Option<String>,
+    //Unix套接字文件的权限，未配置时由umask决定
+    pub mode: Option<u32>,
+    //连接开头带有PROXY协议头（v1或v2），没有时关闭连接
+    pub proxy_protocol: bool,
+}
+
+impl ListenConfig {
+    pub fn new(addr: ListenAddr) -> ListenConfig {
+        ListenConfig {
+            addr,
+            backlog: 511,
+            reuse_port: false,
+            tcp_nodelay: false,
+            ipv6_only: false,
+            default_host: None,
+            mode: None,
+            proxy_protocol: false,
+        }
+    }
+
+    //默认主机名，未配置时为监听的IP，Unix套接字为localhost
+    pub fn host(&self) -> String {
+        match (&self.default_host, &self.addr) {
+            (Some(host), _) => host.clone(),
+            (None, ListenAddr::Tcp(SocketAddr::V6(addr))) => format!("[{}]", addr.ip()),
+            (None, ListenAddr::Tcp(addr)) => addr.ip().to_string(),
+            (None, ListenAddr::Unix(_)) => String::from("localhost"),
+        }
+    }
 }
 
 impl MyConfig {
-    pub fn new() -> MyConfig {
-        MY_CONFIG_ONCE.call_once(|| {
-            let config = Config::build();
-            unsafe {
-                MY_CONFIG = Some(MyConfig {
-                    static_resource_path: Self::get_static_resource_path(&config),
-                    index_page_path: Self::get_index_page_path(&config),
-                    page404_path: Self::get_page404_path(&config),
-                    page500_path: Self::get_page500_path(&config),
-                    thread_pool_size: Self::get_thread_pool_size(&config),
-                    timezone: Self::get_timezone(&config),
-                    ip: Self::get_ip(&config),
-                    port: Self::get_port(&config),
-                })
-            }
-        });
-        unsafe {
-            match &MY_CONFIG {
-                Some(config) => config.clone(),
-                None => {
-                    panic!("Configuration initialization error, please check the configuration!")
+    pub fn load(source: &ConfigSource) -> Result<MyConfig, String> {
+        Self::build(&Config::load(source)?)
+    }
+
+    //校验通过后按配置模式生成配置，未知配置项只记录警告
+    fn build(config: &Config) -> Result<MyConfig, String> {
+        let (errors, warnings) = config.check();
+        for warning in warnings {
+            log_error!("{}", warning);
+        }
+        if !errors.is_empty() {
+            return Err(errors.join("\n"));
+        }
+        let (min_threads, max_threads) = config.threads();
+        let number = |key: &str| config.integer(key).unwrap_or_default();
+        Ok(MyConfig {
+            static_resource_path: config.text("static_resource_path").unwrap_or_default(),
+            index_page_path: config.text("index_page_path").unwrap_or_default(),
+            page404_path: config.text("page404_path"),
+            page500_path: config.text("page500_path"),
+            min_threads: min_threads.unwrap_or(1) as usize,
+            max_threads: max_threads.unwrap_or(1) as usize,
+            thread_idle_timeout: number("thread_idle_timeout") as u64,
+            timezone: number("timezone") as i32,
+            listen: Self::get_listen(config),
+            trusted_proxies: config
+                .config
+                .get("trusted_proxies")
+                .and_then(|value| parse_cidrs(value).ok())
+                .unwrap_or_default(),
+            sse_path: config.text("sse_path"),
+            sse_heartbeat: number("sse_heartbeat") as u64,
+            dev: config.bool("dev"),
+            tls: Self::get_tls(config),
+            http2: config.bool("http2"),
+            io_model: match config.text("io_model").as_deref() {
+                Some("event") => IoModel::Event,
+                _ => IoModel::Classic,
+            },
+            cache_max_bytes: number("cache_max_bytes") as usize,
+            cache_max_file_size: number("cache_max_file_size") as usize,
+            stats_path: config.text("stats_path"),
+            access_log: Self::get_access_log(config),
+            open_file_cache_max: number("open_file_cache_max") as usize,
+            open_file_cache_valid: number("open_file_cache_valid") as u64,
+            queue_capacity: number("queue_capacity") as usize,
+            queue_full_policy: match config.text("queue_full_policy").as_deref() {
+                Some("drop") => QueueFullPolicy::Drop,
+                _ => QueueFullPolicy::Reject,
+            },
+            retry_after: number("retry_after") as u64,
+            shutdown_grace_period: number("shutdown_grace_period") as u64,
+            config_watch: config.bool("config_watch"),
+            log_format: match config.text("log_format").as_deref() {
+                Some("json") => LogOutput::Json,
+                _ => LogOutput::Text,
+            },
+        })
+    }
+
+    //将需要重新绑定或重建的配置恢复为旧值，返回发生变化的配置项
+    pub fn keep_restart_only(&mut self, old: &MyConfig) -> Vec<&'static str> {
+        let mut ignored = Vec::new();
+        macro_rules! keep {
+            ($($field:ident),*) => {$(
+                if self.$field != old.$field {
+                    self.$field = old.$field.clone();
+                    ignored.push(stringify!($field));
                 }
-            }
+            )*};
         }
+        keep!(
+            static_resource_path,
+            dev,
+            listen,
+            tls,
+            io_model,
+            min_threads,
+            max_threads,
+            thread_idle_timeout,
+            queue_capacity
+        );
+        ignored
     }
-    fn get_static_resource_path(config: &Config) -> String {
-        match config.get_text("static_resource_path") {
-            Some(static_resource_path) => {
-                return static_resource_path;
-            }
-            None => {
-                panic!(
-                    "The static resource path is incorrectly configured. Check the configuration."
-                )
-            }
-        };
+
+    //未配置listen时使用ip与port
+    fn get_listen(config: &Config) -> Vec<ListenConfig> {
+        if let Some(listen) = config.config.get("listen") {
+            return parse_listen(listen).unwrap_or_default();
+        }
+        let ip = config
+            .text("ip")
+            .and_then(|ip| ip.parse().ok())
+            .unwrap_or(IpAddr::from([127, 0, 0, 1]));
+        let port = config.integer("port").unwrap_or_default() as u16;
+        vec![ListenConfig::new(ListenAddr::Tcp(SocketAddr::new(
+            ip, port,
+        )))]
     }
 
-    fn get_index_page_path(config: &Config) -> String {
-        match config.get_text("index_page_path") {
-            Some(index_page_path) => {
-                return index_page_path;
-            }
-            None => {
-                return "index.html".to_owned();
-            }
+    //配置了access_log或access_log_hosts时记录访问日志
+    fn get_access_log(config: &Config) -> Option<AccessLogConfig> {
+        let path = config.text("access_log").and_then(log_path);
+        let hosts = match config.config.get("access_log_hosts") {
+            Some(Value::Table(table)) => parse_host_paths(table).unwrap_or_default(),
+            _ => HashMap::new(),
         };
+        if path.is_none() && hosts.values().all(Option::is_none) {
+            return None;
+        }
+        Some(AccessLogConfig {
+            path,
+            format: config
+                .text("access_log_format")
+                .and_then(|format| LogFormat::parse(&format).ok())?,
+            hosts,
+        })
     }
 
-    fn get_page404_path(config: &Config) -> Option<String> {
-        match config.get_text("page404_path") {
-            Some(page404_path) => {
-                return Some(page404_path);
-            }
-            None => {
-                return None;
-            }
-        };
+    //配置了tls_port时开启HTTPS监听
+    fn get_tls(config: &Config) -> Option<TlsConfig> {
+        let port = config.integer("tls_port")?;
+        Some(TlsConfig {
+            port: port as u16,
+            cert_path: config.text("tls_cert_path").unwrap_or_default(),
+            key_path: config.text("tls_key_path").unwrap_or_default(),
+            sni: match config.config.get("tls_sni") {
+                Some(Value::Array(items)) => parse_sni(items).unwrap_or_default(),
+                _ => Vec::new(),
+            },
+            redirect: config.bool("tls_redirect"),
+            http2: config.bool("http2"),
+        })
     }
+}
 
-    fn get_page500_path(config: &Config) -> Option<String> {
-        match config.get_text("page500_path") {
-            Some(page500_path) => {
-                return Some(page500_path);
-            }
-            None => {
-                return None;
+/*
+ * 启动配置文件监听线程，配置文件或include的文件修改、增删后调用on_change
+ */
+pub fn watch(path: PathBuf, on_change: impl Fn() + Send + 'static) {
+    let spawned = thread::Builder::new()
+        .name("config-watcher".to_string())
+        .spawn(move || {
+            let modified = || {
+                let files = Config::load_file(&path)
+                    .map(|config| config.files)
+                    .unwrap_or_else(|_| vec![path.clone()]);
+                files
+                    .into_iter()
+                    .map(|file| {
+                        let modified = fs::metadata(&file)
+                            .ok()
+                            .and_then(|metadata| metadata.modified().ok());
+                        (file, modified)
+                    })
+                    .collect::<Vec<(PathBuf, Option<SystemTime>)>>()
+            };
+            let mut last = modified();
+            loop {
+                thread::sleep(WATCH_INTERVAL);
+                let current = modified();
+                if current != last {
+                    last = current;
+                    on_change();
+                }
             }
-        };
+        });
+    if let Err(e) = spawned {
+        log_error!("Failed to start the config watcher. Error:{}", e);
     }
+}
 
-    fn get_thread_pool_size(config: &Config) -> usize {
-        match config.get_num("thread_pool_size") {
-            Some(thread_pool_size) => thread_pool_size as usize,
-            None => panic!(
-                "The thread pool configuration is incorrect. Please check the configuration."
-            ),
+//IP:PORT、[IPv6]:PORT、PORT（监听所有IPv4地址）或unix:路径
+fn parse_address(text: &str) -> Result<ListenAddr, String> {
+    if let Some(path) = text.strip_prefix("unix:") {
+        if path.is_empty() {
+            return Err(format!("\"{}\": the socket path is empty", text));
         }
+        return Ok(ListenAddr::Unix(PathBuf::from(path)));
+    }
+    let addr = match text.parse::<u16>() {
+        Ok(port) => SocketAddr::new(IpAddr::from([0, 0, 0, 0]), port),
+        Err(_) => text
+            .parse()
+            .map_err(|_| format!("\"{}\" is not an address like 0.0.0.0:80 or [::]:80", text))?,
+    };
+    if addr.port() == 0 {
+        return Err(format!("\"{}\": the port must be 1..=65535", text));
     }
+    Ok(ListenAddr::Tcp(addr))
+}
 
-    fn get_timezone(config: &Config) -> i32 {
-        match config.get_num("timezone") {
-            Some(timezone) => timezone as i32,
-            None => {
-                panic!("The time zone configuration is incorrect. Please check the configuration.")
+/*
+ * 解析监听配置：地址字符串、地址字符串数组或表数组，如
+ * listen = ["0.0.0.0:80", "[::]:8080", "unix:/run/star-http.sock"]
+ * [[listen]]
+ * address = "[::]:80"
+ * backlog = 1024
+ */
+fn parse_listen(value: &Value) -> Result<Vec<ListenConfig>, String> {
+    let items = match value {
+        Value::Array(items) => items.as_slice(),
+        value => std::slice::from_ref(value),
+    };
+    if items.is_empty() {
+        return Err("at least one address is required".to_owned());
+    }
+    let mut listen: Vec<ListenConfig> = Vec::new();
+    for item in items {
+        let config = match item {
+            Value::String(text) => ListenConfig::new(parse_address(text)?),
+            Value::Table(table) => parse_listen_table(table)?,
+            item => {
+                return Err(format!(
+                    "expected an address or a table, found {}",
+                    item.type_name()
+                ))
             }
+        };
+        if listen.iter().any(|l| l.addr == config.addr) {
+            return Err(format!("{} is listed more than once", config.addr));
         }
+        listen.push(config);
     }
+    Ok(listen)
+}
 
-    fn get_ip(config: &Config) -> std::net::Ipv4Addr {
-        match config.get_text("ip") {
-            Some(ip) => match ip.parse::<std::net::Ipv4Addr>() {
-                Ok(ip) => ip,
-                Err(_) => panic!(
-                    "The ip address configuration is incorrect. Please check the configuration."
-                ),
-            },
-            None => {
-                panic!("The ip address configuration is incorrect. Please check the configuration.")
+fn parse_listen_table(table: &Table) -> Result<ListenConfig, String> {
+    let mut config = match table.get("address") {
+        Some(Value::String(text)) => ListenConfig::new(parse_address(text)?),
+        Some(value) => {
+            return Err(format!(
+                "address: expected a string, found {}",
+                value.type_name()
+            ))
+        }
+        None => return Err("missing required key 'address'".to_owned()),
+    };
+    for (key, value) in table {
+        match (key.as_str(), value) {
+            ("address", _) => {}
+            ("backlog", Value::Integer(n)) if (1..=65535).contains(n) => config.backlog = *n as i32,
+            ("backlog", Value::Integer(n)) => {
+                return Err(format!("backlog: {} is out of range 1..=65535", n))
+            }
+            ("reuse_port", Value::Boolean(on)) => config.reuse_port = *on,
+            ("tcp_nodelay", Value::Boolean(on)) => config.tcp_nodelay = *on,
+            ("ipv6_only", Value::Boolean(on)) => config.ipv6_only = *on,
+            ("default_host", Value::String(host)) => config.default_host = Some(host.clone()),
+            ("proxy_protocol", Value::Boolean(on)) => config.proxy_protocol = *on,
+            ("mode", Value::Integer(mode)) if (0..=0o777).contains(mode) => {
+                config.mode = Some(*mode as u32)
+            }
+            ("mode", Value::Integer(mode)) => {
+                return Err(format!("mode: {:o} is not a permission like 0o660", mode))
             }
+            (
+                "backlog" | "reuse_port" | "tcp_nodelay" | "ipv6_only" | "default_host" | "mode"
+                | "proxy_protocol",
+                value,
+            ) => {
+                return Err(format!(
+                    "{}: unexpected {} {}",
+                    key,
+                    value.type_name(),
+                    value
+                ))
+            }
+            (key, _) => return Err(format!("unknown key '{}'", key)),
         }
     }
+    //只适用于其中一种套接字的选项
+    let unix = matches!(config.addr, ListenAddr::Unix(_));
+    let tcp_only = ["reuse_port", "tcp_nodelay", "ipv6_only"];
+    let invalid = match unix {
+        true => tcp_only.iter().find(|key| table.contains_key(**key)),
+        false => ["mode"].iter().find(|key| table.contains_key(**key)),
+    };
+    if let Some(key) = invalid {
+        return Err(format!(
+            "{}: not supported for {}",
+            key,
+            if unix {
+                "unix sockets"
+            } else {
+                "TCP addresses"
+            }
+        ));
+    }
+    Ok(config)
+}
+
+//日志文件路径，off表示不记录
+fn log_path(text: String) -> Option<PathBuf> {
+    match text.as_str() {
+        "off" | "" => None,
+        _ => Some(PathBuf::from(text)),
+    }
+}
+
+//[access_log_hosts]中的"主机名" = "路径"
+fn parse_host_paths(table: &Table) -> Result<HashMap<String, Option<PathBuf>>, String> {
+    table
+        .iter()
+        .map(|(host, value)| match value {
+            Value::String(path) => Ok((host.to_lowercase(), log_path(path.clone()))),
+            value => Err(format!(
+                "{}: expected a string, found {}",
+                host,
+                value.type_name()
+            )),
+        })
+        .collect()
+}
+
+//trusted_proxies = ["10.0.0.0/8", "::1", "unix:"]
+fn parse_cidrs(value: &Value) -> Result<Vec<Cidr>, String> {
+    let items = match value {
+        Value::Array(items) => items.as_slice(),
+        value => std::slice::from_ref(value),
+    };
+    items
+        .iter()
+        .map(|item| match item {
+            Value::String(text) => Cidr::parse(text.trim()),
+            item => Err(format!("expected a string, found {}", item.type_name())),
+        })
+        .collect()
+}
 
-    fn get_port(config: &Config) -> u16 {
-        match config.get_num("port") {
-            Some(port) => port as u16,
-            None => panic!("The port configuration is incorrect. Please check the configuration."),
+/*
+ * 按SNI主机名选择的证书，每个主机名一个表：
+ * [[tls_sni]]
+ * host = "blog.example.com"
+ * cert = "./cert/blog.crt"
+ * key = "./cert/blog.key"
+ */
+fn parse_sni(items: &[Value]) -> Result<Vec<SniCert>, String> {
+    let mut certs: Vec<SniCert> = Vec::new();
+    for item in items {
+        let table = match item {
+            Value::Table(table) => table,
+            item => return Err(format!("expected a table, found {}", item.type_name())),
+        };
+        if let Some(key) = table
+            .keys()
+            .find(|key| !["host", "cert", "key"].contains(&key.as_str()))
+        {
+            return Err(format!("unknown key '{}'", key));
         }
+        let text = |key: &str| match table.get(key) {
+            Some(Value::String(text)) if !text.is_empty() => Ok(text.clone()),
+            Some(Value::String(_)) => Err(format!("{}: must not be empty", key)),
+            Some(value) => Err(format!(
+                "{}: expected a string, found {}",
+                key,
+                value.type_name()
+            )),
+            None => Err(format!("missing required key '{}'", key)),
+        };
+        //主机名不区分大小写
+        let host = text("host")?.to_lowercase();
+        if certs.iter().any(|cert| cert.host == host) {
+            return Err(format!("{} is listed more than once", host));
+        }
+        certs.push(SniCert {
+            host,
+            cert_path: text("cert")?,
+            key_path: text("key")?,
+        });
     }
+    Ok(certs)
 }
 
 #[cfg(test)]
@@ -233,12 +1306,444 @@ mod test {
 
     #[test]
     fn test_parse_config() {
-        let config = MyConfig::new();
+        let config = MyConfig::load(&ConfigSource::default()).unwrap();
         println!("{}", config.static_resource_path);
-        println!("{}", config.thread_pool_size);
+        println!("{} {}", config.min_threads, config.max_threads);
         println!("{}", config.timezone);
-        let config = MyConfig::new();
-        println!("{}", config.ip);
-        println!("{}", config.port);
+        for listen in &config.listen {
+            println!("{}", listen.addr);
+        }
+    }
+
+    #[test]
+    fn test_reload_validation() {
+        let build = |text: &str| MyConfig::build(&Config::parse("config.toml", text)?);
+        let text = "static_resource_path = \"./static\"\nthread_pool_size = 4\ntimezone = 8\nip = \"127.0.0.1\"\nport = 80\n";
+        let old = build(text).unwrap();
+        assert!(build(&text.replace("port = 80", "port = abc")).is_err());
+        assert!(build(&text.replace("127.0.0.1", "localhost")).is_err());
+        //需要重启的配置沿用旧值，其余配置生效
+        let mut config =
+            build(&format!("{}retry_after = 9\n", text.replace("80", "8080"))).unwrap();
+        assert_eq!(config.keep_restart_only(&old), vec!["listen"]);
+        assert_eq!(config.listen[0].addr.to_string(), "127.0.0.1:80");
+        assert_eq!(config.retry_after, 9);
+    }
+
+    #[test]
+    fn test_toml_values() {
+        let text = "static_resource_path = \"./a=b\" # 注释\nthread_pool_size = 4\ntimezone = -5\nip = \"127.0.0.1\"\nport = 80\nhttp2 = false\nconfig_watch = 1\n";
+        let config = MyConfig::build(&Config::parse("config.toml", text).unwrap()).unwrap();
+        assert_eq!(config.static_resource_path, "./a=b");
+        assert_eq!(config.timezone, -5);
+        assert!(!config.http2);
+        assert!(config.config_watch);
+        let error = Config::parse("config.toml", "port = 80\nport = 81\n")
+            .err()
+            .unwrap();
+        assert!(error.starts_with("config.toml:2:1:"), "{}", error);
+    }
+
+    #[test]
+    fn test_schema_errors() {
+        let text = "static_resource_path = \"./static\"\nthread_pool_sise = 4\ntimezone = 8\nport = 70000\nio_model = \"epoll\"\nhttp2 = \"yes\"\n";
+        let config = Config::parse("config.toml", text).unwrap();
+        let (errors, warnings) = config.check();
+        assert_eq!(
+            warnings,
+            vec!["config.toml:2: unknown key 'thread_pool_sise', did you mean 'thread_pool_size'?"]
+        );
+        assert_eq!(
+            errors,
+            vec![
+                "config.toml: missing required key 'ip'",
+                "config.toml: missing required key 'thread_pool_size' (or 'min_threads')",
+                "config.toml:4: port: 70000 is out of range 1..=65535",
+                "config.toml:5: io_model: \"epoll\" is not one of \"classic\", \"event\"",
+                "config.toml:6: http2: expected a boolean, found string \"yes\"",
+            ]
+        );
+        assert!(MyConfig::build(&config).is_err());
+    }
+
+    #[test]
+    fn test_listen() {
+        let base = "static_resource_path = \"./static\"\nthread_pool_size = 4\ntimezone = 8\n";
+        let build = |text: &str| MyConfig::build(&Config::parse("config.toml", text)?);
+        let config = build(&format!(
+            "{}listen = [\"0.0.0.0:80\", \"8080\", {{ address = \"[::]:443\", backlog = 64, reuse_port = true, tcp_nodelay = true, ipv6_only = true, default_host = \"example.com\" }}]\n",
+            base
+        ))
+        .unwrap();
+        let addrs: Vec<String> = config.listen.iter().map(|l| l.addr.to_string()).collect();
+        assert_eq!(addrs, vec!["0.0.0.0:80", "0.0.0.0:8080", "[::]:443"]);
+        assert_eq!(
+            config.listen[0],
+            ListenConfig::new(ListenAddr::Tcp("0.0.0.0:80".parse().unwrap()))
+        );
+        let v6 = &config.listen[2];
+        assert_eq!(v6.backlog, 64);
+        assert!(v6.reuse_port && v6.tcp_nodelay && v6.ipv6_only);
+        assert_eq!(v6.host(), "example.com");
+        assert_eq!(config.listen[1].host(), "0.0.0.0");
+        let config = build(&format!(
+            "{}\n[[listen]]\naddress = \"[::]:80\"\n\n[[listen]]\naddress = \"0.0.0.0:81\"\n",
+            base
+        ))
+        .unwrap();
+        assert_eq!(config.listen.len(), 2);
+        //未配置listen时使用ip与port
+        let config = build(&format!("{}ip = \"::1\"\nport = 80\n", base)).unwrap();
+        assert_eq!(config.listen[0].addr.to_string(), "[::1]:80");
+        assert_eq!(config.listen[0].host(), "[::1]");
+        let error = |listen: &str| {
+            let text = format!("{}listen = {}\n", base, listen);
+            build(&text).err().unwrap()
+        };
+        assert_eq!(
+            error("[]"),
+            "config.toml:4: listen: at least one address is required"
+        );
+        assert_eq!(
+            error("\"localhost:80\""),
+            "config.toml:4: listen: \"localhost:80\" is not an address like 0.0.0.0:80 or [::]:80"
+        );
+        assert_eq!(
+            error("[\"80\", \"0.0.0.0:80\"]"),
+            "config.toml:4: listen: 0.0.0.0:80 is listed more than once"
+        );
+        assert_eq!(
+            error("[{ address = \"[::]:80\", backlg = 5 }]"),
+            "config.toml:4: listen: unknown key 'backlg'"
+        );
+        assert_eq!(
+            error("[{ address = \"[::]:80\", reuse_port = \"yes\" }]"),
+            "config.toml:4: listen: reuse_port: unexpected string \"yes\""
+        );
+        //Unix套接字
+        let config = build(&format!(
+            "{}listen = [\"unix:/run/star-http.sock\", {{ address = \"unix:./a.sock\", mode = 0o660 }}]\n",
+            base
+        ))
+        .unwrap();
+        assert_eq!(
+            config.listen[0].addr,
+            ListenAddr::Unix(PathBuf::from("/run/star-http.sock"))
+        );
+        assert_eq!(
+            config.listen[0].addr.to_string(),
+            "unix:/run/star-http.sock"
+        );
+        assert_eq!(config.listen[0].host(), "localhost");
+        assert_eq!(config.listen[1].mode, Some(0o660));
+        assert_eq!(
+            error("\"unix:\""),
+            "config.toml:4: listen: \"unix:\": the socket path is empty"
+        );
+        assert_eq!(
+            error("[{ address = \"unix:/a.sock\", tcp_nodelay = true }]"),
+            "config.toml:4: listen: tcp_nodelay: not supported for unix sockets"
+        );
+        assert_eq!(
+            error("[{ address = \"80\", mode = 0o600 }]"),
+            "config.toml:4: listen: mode: not supported for TCP addresses"
+        );
+        assert_eq!(
+            error("[{ address = \"unix:/a.sock\", mode = 0o1777 }]"),
+            "config.toml:4: listen: mode: 1777 is not a permission like 0o660"
+        );
+        //配置listen后ip与port不再生效
+        let config = Config::parse(
+            "config.toml",
+            &format!("{}port = 80\nlisten = \"81\"\n", base),
+        )
+        .unwrap();
+        assert_eq!(
+            config.check().1,
+            vec!["config.toml:4: port is ignored because 'listen' is set"]
+        );
+        //命令行的--listen覆盖配置文件时不提示文件中的port
+        let mut config = Config::parse("config.toml", &format!("{}port = 80\n", base)).unwrap();
+        let mut source = ConfigSource::default();
+        let listen = Value::Array(vec![Value::String("81".to_owned())]);
+        source
+            .overrides
+            .push(("listen", listen, Origin::Cli("--listen")));
+        config.apply(&source, std::iter::empty());
+        assert!(config.check().1.is_empty());
+        let mut config = Config::parse("config.toml", base).unwrap();
+        config.apply(
+            &ConfigSource::default(),
+            [(
+                "STARHTTP_LISTEN".to_owned(),
+                "127.0.0.1:81, [::1]:81".to_owned(),
+            )]
+            .into_iter(),
+        );
+        let config = MyConfig::build(&config).unwrap();
+        assert_eq!(config.listen.len(), 2);
+    }
+
+    #[test]
+    fn test_trusted_proxies() {
+        let base = "static_resource_path = \"./static\"\nthread_pool_size = 4\ntimezone = 8\n";
+        let build = |text: &str| MyConfig::build(&Config::parse("config.toml", text)?);
+        let config = build(&format!(
+            "{}trusted_proxies = [\"10.0.0.0/8\", \"::1\", \"unix:\"]\nlisten = {{ address = \"80\", proxy_protocol = true }}\n",
+            base
+        ))
+        .unwrap();
+        assert_eq!(
+            config.trusted_proxies,
+            vec![
+                Cidr::Ip([10, 0, 0, 0].into(), 8),
+                Cidr::Ip("::1".parse().unwrap(), 128),
+                Cidr::Unix
+            ]
+        );
+        assert!(config.listen[0].proxy_protocol);
+        assert!(build(&format!("{}listen = \"80\"\n", base))
+            .unwrap()
+            .trusted_proxies
+            .is_empty());
+        assert_eq!(
+            build(&format!(
+                "{}listen = \"80\"\ntrusted_proxies = \"10.0.0.0/40\"\n",
+                base
+            ))
+            .err()
+            .unwrap(),
+            "config.toml:5: trusted_proxies: \"10.0.0.0/40\": the prefix length must be 0..=32"
+        );
+        //环境变量以逗号分隔
+        assert_eq!(
+            env_value("trusted_proxies", "10.0.0.1, unix:"),
+            Value::Array(vec![
+                Value::String("10.0.0.1".to_owned()),
+                Value::String("unix:".to_owned())
+            ])
+        );
+    }
+
+    #[test]
+    fn test_access_log() {
+        let base = "static_resource_path = \"./static\"\nthread_pool_size = 4\ntimezone = 8\nport = 80\nip = \"127.0.0.1\"\n";
+        let build = |text: &str| MyConfig::build(&Config::parse("config.toml", text)?);
+        assert_eq!(build(base).unwrap().access_log, None);
+        let config = build(&format!(
+            "{}access_log = \"logs/access.log\"\naccess_log_format = \"common\"\n\n[access_log_hosts]\n\"Example.com\" = \"logs/example.log\"\n\"quiet.com\" = \"off\"\n",
+            base
+        ))
+        .unwrap();
+        let access_log = config.access_log.unwrap();
+        assert_eq!(access_log.path, Some(PathBuf::from("logs/access.log")));
+        assert_eq!(access_log.format, LogFormat::parse("common").unwrap());
+        assert_eq!(
+            access_log.hosts.get("example.com"),
+            Some(&Some(PathBuf::from("logs/example.log")))
+        );
+        assert_eq!(access_log.hosts.get("quiet.com"), Some(&None));
+        //默认Combined格式
+        let config = build(&format!("{}access_log = \"a.log\"\n", base)).unwrap();
+        assert_eq!(
+            config.access_log.unwrap().format,
+            LogFormat::parse("combined").unwrap()
+        );
+        assert_eq!(
+            build(&format!("{}access_log = \"off\"\n", base))
+                .unwrap()
+                .access_log,
+            None
+        );
+        assert_eq!(
+            build(&format!(
+                "{}access_log = \"a.log\"\naccess_log_format = \"$remote_adr\"\n",
+                base
+            ))
+            .err()
+            .unwrap(),
+            "config.toml:7: access_log_format: unknown variable '$remote_adr'"
+        );
+        assert_eq!(
+            build(&format!("{}\n[access_log_hosts]\n\"a.com\" = 1\n", base))
+                .err()
+                .unwrap(),
+            "config.toml:7: access_log_hosts: a.com: expected a string, found integer"
+        );
+    }
+
+    #[test]
+    fn test_log_format() {
+        let base = "static_resource_path = \"./static\"\nthread_pool_size = 4\ntimezone = 8\nport = 80\nip = \"127.0.0.1\"\n";
+        let build = |text: &str| MyConfig::build(&Config::parse("config.toml", text)?);
+        assert_eq!(build(base).unwrap().log_format, LogOutput::Text);
+        assert_eq!(
+            build(&format!("{}log_format = \"json\"\n", base))
+                .unwrap()
+                .log_format,
+            LogOutput::Json
+        );
+        assert!(build(&format!("{}log_format = \"xml\"\n", base)).is_err());
+    }
+
+    #[test]
+    fn test_env_overrides() {
+        let text = "static_resource_path = \"./static\"\nthread_pool_size = 4\ntimezone = 8\nip = \"127.0.0.1\"\nport = 80\n";
+        let mut config = Config::parse("config.toml", text).unwrap();
+        let source = ConfigSource {
+            overrides: vec![("port", Value::Integer(9000), Origin::Cli("--port"))],
+            ..ConfigSource::default()
+        };
+        let vars = [
+            ("STARHTTP_PORT", "8080"),
+            ("STARHTTP_TIMEZONE", "0"),
+            ("STARHTTP_HTTP2", "off"),
+            ("STARHTTP_RETRY_AFTER", "soon"),
+            ("STARHTTP_SERVER__NAME", "blog"),
+            ("HOME", "/root"),
+        ];
+        config.apply(
+            &source,
+            vars.into_iter().map(|(k, v)| (k.to_owned(), v.to_owned())),
+        );
+        let (errors, warnings) = config.check();
+        assert_eq!(
+            errors,
+            vec![
+                "env STARHTTP_RETRY_AFTER: retry_after: expected an integer, found string \"soon\""
+            ]
+        );
+        assert_eq!(
+            warnings,
+            vec!["env STARHTTP_SERVER__NAME: unknown key 'server'"]
+        );
+        //命令行 > 环境变量 > 配置文件 > 默认值
+        let dump = config.dump();
+        let line = |key: &str| {
+            dump.lines()
+                .find(|line| line.starts_with(&format!("{} ", key)))
+                .map(|line| line.split_whitespace().collect::<Vec<&str>>().join(" "))
+                .unwrap()
+        };
+        assert_eq!(line("port"), "port = 9000 # option --port");
+        assert_eq!(line("timezone"), "timezone = 0 # env STARHTTP_TIMEZONE");
+        assert_eq!(line("http2"), "http2 = false # env STARHTTP_HTTP2");
+        assert_eq!(line("ip"), "ip = \"127.0.0.1\" # config.toml:4");
+        assert_eq!(
+            line("index_page_path"),
+            "index_page_path = \"index.html\" # default"
+        );
+        assert!(dump.contains("#page404_path is not set"));
+    }
+
+    #[test]
+    fn test_includes() {
+        let dir = env::temp_dir().join(format!("star-http-include-{}", std::process::id()));
+        fs::create_dir_all(dir.join("conf.d")).unwrap();
+        let write = |name: &str, text: &str| fs::write(dir.join(name), text).unwrap();
+        write(
+            "main.toml",
+            "static_resource_path = \"./static\"\nthread_pool_size = 4\ntimezone = 8\nip = \"127.0.0.1\"\nport = 80\ninclude = [\"conf.d/*.toml\", \"extra.toml\"]\n",
+        );
+        write("conf.d/1.toml", "port = 81\nretry_after = 7\n");
+        write("conf.d/2.toml", "\nport = 82\n");
+        write("extra.toml", "sse_heartbeat = 0\n");
+        let config = Config::load_file(&dir.join("main.toml")).unwrap();
+        assert_eq!(config.files.len(), 4);
+        //后读取的文件覆盖先读取的，错误提示所在的文件与行号
+        assert_eq!(config.integer("port"), Some(82));
+        assert_eq!(config.integer("retry_after"), Some(7));
+        let (errors, _) = config.check();
+        assert_eq!(errors.len(), 1);
+        assert!(
+            errors[0].ends_with("extra.toml:1: sse_heartbeat: 0 is less than 1"),
+            "{}",
+            errors[0]
+        );
+        assert!(config
+            .dump()
+            .lines()
+            .any(|line| line.starts_with("port = 82 ") && line.ends_with("conf.d/2.toml:2")));
+        write("a.toml", "include = \"conf.d/../b.toml\"\n");
+        write("b.toml", "include = 'a.toml'\n");
+        let error = Config::load_file(&dir.join("a.toml")).err().unwrap();
+        assert!(error.contains("include cycle"), "{}", error);
+        write("c.toml", "\ninclude = \"missing.toml\"\n");
+        let error = Config::load_file(&dir.join("c.toml")).err().unwrap();
+        assert!(error.contains("c.toml:2: included file"), "{}", error);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_merge_table() {
+        let parse = |text: &str| toml::parse(text).unwrap().table;
+        let mut table = parse("a = 1\nlist = [1]\n[t]\nx = 1\n[[vhost]]\nname = 'a'\n");
+        merge_table(
+            &mut table,
+            parse("a = 2\nlist = [2]\n[t]\ny = 2\n[[vhost]]\nname = 'b'\n"),
+        );
+        assert_eq!(
+            table,
+            parse(
+                "a = 2\nlist = [1, 2]\nt = {x = 1, y = 2}\nvhost = [{name = 'a'}, {name = 'b'}]\n"
+            )
+        );
+    }
+
+    #[test]
+    fn test_tls_feature() {
+        let build = |text: &str| MyConfig::build(&Config::parse("config.toml", text)?);
+        let text = "static_resource_path = \"./static\"\nthread_pool_size = 4\ntimezone = 8\nip = \"127.0.0.1\"\nport = 80\ntls_port = 443\ntls_cert_path = \"a.crt\"\ntls_key_path = \"a.key\"\ntls_redirect = true\n";
+        match cfg!(feature = "tls") {
+            true => assert_eq!(build(text).unwrap().tls.unwrap().port, 443),
+            false => assert_eq!(
+                build(text).err().unwrap(),
+                "config.toml:6: tls_port: the server was built without the tls feature"
+            ),
+        }
+    }
+
+    #[test]
+    fn test_parse_sni() {
+        let base = "static_resource_path = \"./static\"\nthread_pool_size = 4\ntimezone = 8\nip = \"127.0.0.1\"\nport = 80\n";
+        let sni = |text: &str| {
+            let config = Config::parse("config.toml", &format!("{}{}", base, text))?;
+            let (errors, _) = config.check();
+            match config.config.get("tls_sni") {
+                Some(Value::Array(items)) if errors.is_empty() => parse_sni(items),
+                _ => Err(errors.join("\n")),
+            }
+        };
+        let certs = sni(
+            "[[tls_sni]]\nhost = \"Blog.example.com\"\ncert = \"./cert/a,b;c.crt\"\nkey = \"blog.key\"\n\n[[tls_sni]]\nhost = \"www.example.com\"\ncert = \"www.crt\"\nkey = \"www.key\"\n",
+        )
+        .unwrap();
+        assert_eq!(certs.len(), 2);
+        //路径中可以包含逗号与分号
+        assert_eq!(
+            certs[0],
+            SniCert {
+                host: "blog.example.com".to_owned(),
+                cert_path: "./cert/a,b;c.crt".to_owned(),
+                key_path: "blog.key".to_owned(),
+            }
+        );
+        assert_eq!(
+            sni("[[tls_sni]]\nhost = \"a.com\"\ncert = \"a.crt\"\n").unwrap_err(),
+            "config.toml:6: tls_sni: missing required key 'key'"
+        );
+        assert_eq!(
+            sni("[[tls_sni]]\nhost = \"a.com\"\ncert = \"a.crt\"\nkey = \"a.key\"\npath = \"x\"\n")
+                .unwrap_err(),
+            "config.toml:6: tls_sni: unknown key 'path'"
+        );
+        assert_eq!(
+            sni("tls_sni = \"a.com,a.crt,a.key\"\n").unwrap_err(),
+            "config.toml:6: tls_sni: expected an array of tables, found string \"a.com,a.crt,a.key\""
+        );
+        assert_eq!(
+            sni("tls_sni = [{ host = \"A.com\", cert = \"a.crt\", key = \"a.key\" }, { host = \"a.com\", cert = \"b.crt\", key = \"b.key\" }]\n").unwrap_err(),
+            "config.toml:6: tls_sni: a.com is listed more than once"
+        );
     }
 }
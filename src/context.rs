@@ -0,0 +1,109 @@
+use std::sync::{Arc, RwLock};
+
+use crate::{
+    access_log::AccessLog,
+    cache::{ContentCache, OpenFileCache},
+    config::{self, ConfigSource, MyConfig},
+    log::{self, LogSettings},
+    log_error, log_info,
+    shutdown::Shutdown,
+    sse::Broadcaster,
+    stats::Stats,
+};
+
+/*
+ * 一个服务器实例共享的状态：配置快照、缓存、统计、事件推送与关闭控制
+ * 在main中创建后传给各处理函数，同一进程可运行多个配置不同的实例
+ */
+pub struct ServerContext {
+    //当前配置快照，重新加载时整体替换，处理中的请求继续使用取到的旧快照
+    config: RwLock<Arc<MyConfig>>,
+    //重新加载时读取的配置文件与命令行参数
+    source: ConfigSource,
+    pub content_cache: ContentCache,
+    pub open_file_cache: OpenFileCache,
+    pub stats: Arc<Stats>,
+    pub broadcaster: Broadcaster,
+    pub shutdown: Arc<Shutdown>,
+    pub access_log: AccessLog,
+    //运行日志的时区与格式，处理本实例工作的线程通过log_scope使用
+    pub log: Arc<LogSettings>,
+}
+
+impl ServerContext {
+    pub fn new(config: MyConfig, source: ConfigSource) -> Arc<ServerContext> {
+        let log = Arc::new(LogSettings::new());
+        log.set(config.timezone, config.log_format.formatter());
+        Arc::new(ServerContext {
+            config: RwLock::new(Arc::new(config)),
+            source,
+            content_cache: ContentCache::new(),
+            open_file_cache: OpenFileCache::new(),
+            stats: Arc::new(Stats::new()),
+            broadcaster: Broadcaster::new(),
+            shutdown: Arc::new(Shutdown::new()),
+            access_log: AccessLog::new(),
+            log,
+        })
+    }
+
+    //使用配置文件创建的实例，供测试启动服务端
+    #[cfg(test)]
+    pub fn load() -> Arc<ServerContext> {
+        let source = ConfigSource::default();
+        ServerContext::new(MyConfig::load(&source).unwrap(), source)
+    }
+
+    //当前线程的运行日志改用本实例的设置，返回值销毁时恢复
+    pub fn log_scope(&self) -> log::Scope {
+        log::enter(&self.log)
+    }
+
+    pub fn config(&self) -> Arc<MyConfig> {
+        Arc::clone(&self.config.read().unwrap_or_else(|e| e.into_inner()))
+    }
+
+    /*
+     * 重新读取配置文件并替换当前快照，配置无效时保留旧配置
+     * 监听地址、线程池等需要重启才能生效的配置沿用旧值，并记录日志
+     */
+    pub fn reload(&self) -> Result<Arc<MyConfig>, String> {
+        let mut config = MyConfig::load(&self.source)?;
+        let mut current = self.config.write().unwrap_or_else(|e| e.into_inner());
+        let ignored = config.keep_restart_only(&current);
+        if !ignored.is_empty() {
+            log_error!(
+                "Config changes to {} require a restart to take effect",
+                ignored.join(", ")
+            );
+        }
+        self.log.set(config.timezone, config.log_format.formatter());
+        let config = Arc::new(config);
+        *current = Arc::clone(&config);
+        //日志轮转后通过重新加载配置重新打开访问日志
+        self.access_log.reopen();
+        log_info!("Configuration reloaded");
+        Ok(config)
+    }
+
+    //配置文件修改后自动重新加载
+    pub fn watch_config(self: &Arc<Self>) {
+        let path = match self.source.file() {
+            Ok(Some(path)) => path,
+            _ => return,
+        };
+        let context = Arc::clone(self);
+        config::watch(path, move || {
+            let _log = context.log_scope();
+            if let Err(e) = context.reload() {
+                log_error!("Config reload rejected, keep the old one. Error:{}", e);
+            }
+        });
+    }
+
+    //开始优雅关闭：停止接收、关闭空闲连接并结束事件推送
+    pub fn begin_shutdown(&self) {
+        self.shutdown.begin();
+        self.broadcaster.close();
+    }
+}
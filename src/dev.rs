@@ -0,0 +1,120 @@
+use std::{
+    collections::HashMap,
+    fs,
+    path::{Path, PathBuf},
+    sync::Arc,
+    thread,
+    time::{Duration, SystemTime},
+};
+
+use crate::{context::ServerContext, log_error, log_info};
+
+//开发模式下的页面自动刷新事件地址
+pub const LIVE_RELOAD_PATH: &str = "/__starhttp/livereload";
+//目录轮询间隔
+const POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+const LIVE_RELOAD_SCRIPT: &str = "<script>new EventSource(\"/__starhttp/livereload\").addEventListener(\"reload\",function(){location.reload()});</script>";
+
+/*
+ * 启动静态目录监听线程，文件变化时推送reload事件
+ */
+pub fn watch(ctx: Arc<ServerContext>) {
+    let spawned = thread::Builder::new()
+        .name("dev-watcher".to_string())
+        .spawn(move || {
+            let _log = ctx.log_scope();
+            let root = PathBuf::from(&ctx.config().static_resource_path);
+            let mut snapshot = scan(&root);
+            log_info!("Dev mode: watching {} for changes", root.display());
+            loop {
+                thread::sleep(POLL_INTERVAL);
+                let current = scan(&root);
+                if current != snapshot {
+                    snapshot = current;
+                    let id = ctx.broadcaster.publish(Some("reload"), "changed");
+                    log_info!("Dev mode: static files changed, reload event {} sent", id);
+                }
+            }
+        });
+    if let Err(e) = spawned {
+        log_error!("Failed to start the dev mode watcher. Error:{}", e);
+    }
+}
+
+//递归记录目录下所有文件的修改时间
+fn scan(root: &Path) -> HashMap<PathBuf, SystemTime> {
+    let mut files = HashMap::new();
+    let mut dirs = vec![root.to_path_buf()];
+    while let Some(dir) = dirs.pop() {
+        let entries = match fs::read_dir(&dir) {
+            Ok(entries) => entries,
+            Err(_) => continue,
+        };
+        for entry in entries.flatten() {
+            let path = entry.path();
+            if let Ok(metadata) = entry.metadata() {
+                if metadata.is_dir() {
+                    dirs.push(path);
+                } else if let Ok(modified) = metadata.modified() {
+                    files.insert(path, modified);
+                }
+            }
+        }
+    }
+    files
+}
+
+pub fn is_html(path: &Path) -> bool {
+    matches!(
+        path.extension().and_then(|e| e.to_str()).map(|e| e.to_ascii_lowercase()),
+        Some(ext) if ext == "html" || ext == "htm"
+    )
+}
+
+//在</body>前注入刷新脚本，没有</body>时追加到末尾
+pub fn inject_reload_script(html: &str) -> String {
+    let mut result = String::with_capacity(html.len() + LIVE_RELOAD_SCRIPT.len());
+    match html.to_ascii_lowercase().rfind("</body>") {
+        Some(index) => {
+            result.push_str(&html[..index]);
+            result.push_str(LIVE_RELOAD_SCRIPT);
+            result.push_str(&html[index..]);
+        }
+        None => {
+            result.push_str(html);
+            result.push_str(LIVE_RELOAD_SCRIPT);
+        }
+    }
+    result
+}
+
+#[cfg(test)]
+mod test {
+    use std::{fs, path::Path};
+
+    use super::*;
+
+    #[test]
+    fn test_inject_reload_script() {
+        let html = "<html><BODY><h1>hi</h1></BODY></html>";
+        let injected = inject_reload_script(html);
+        assert!(injected.ends_with(&format!("{}</BODY></html>", LIVE_RELOAD_SCRIPT)));
+        assert_eq!(
+            inject_reload_script("<p>hi</p>"),
+            format!("<p>hi</p>{}", LIVE_RELOAD_SCRIPT)
+        );
+        assert!(is_html(Path::new("index首页.HTML")));
+        assert!(!is_html(Path::new("style.css")));
+    }
+
+    #[test]
+    fn test_scan() {
+        let dir = std::env::temp_dir().join("star-http-dev-scan");
+        fs::create_dir_all(dir.join("sub")).unwrap();
+        fs::write(dir.join("sub").join("a.html"), "a").unwrap();
+        let files = scan(&dir);
+        assert!(files.contains_key(&dir.join("sub").join("a.html")));
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
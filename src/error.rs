@@ -47,6 +47,80 @@ impl From<Utf8Error> for HttpError {
     }
 }
 
+#[cfg(feature = "tls")]
+impl From<rustls::Error> for HttpError {
+    fn from(error: rustls::Error) -> Self {
+        HttpError {
+            kind: "tls".to_string(),
+            message: error.to_string(),
+        }
+    }
+}
+
+/*
+ * HTTP/2错误，code为RFC 7540定义的错误码（用于GOAWAY/RST_STREAM）
+ */
+pub struct Http2Error {
+    pub code: u32,
+    pub message: String,
+}
+
+impl Http2Error {
+    pub const NO_ERROR: u32 = 0x0;
+    pub const PROTOCOL_ERROR: u32 = 0x1;
+    pub const INTERNAL_ERROR: u32 = 0x2;
+    pub const FLOW_CONTROL_ERROR: u32 = 0x3;
+    pub const STREAM_CLOSED: u32 = 0x5;
+    pub const FRAME_SIZE_ERROR: u32 = 0x6;
+    pub const COMPRESSION_ERROR: u32 = 0x9;
+
+    pub fn new(code: u32, message: &str) -> Http2Error {
+        Http2Error {
+            code,
+            message: message.to_string(),
+        }
+    }
+
+    pub fn protocol(message: &str) -> Http2Error {
+        Http2Error::new(Http2Error::PROTOCOL_ERROR, message)
+    }
+
+    pub fn compression(message: &str) -> Http2Error {
+        Http2Error::new(Http2Error::COMPRESSION_ERROR, message)
+    }
+}
+
+impl Display for Http2Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "Http2Error{{code:{:#x},message:{}}}",
+            self.code, self.message
+        )
+    }
+}
+
+impl Debug for Http2Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Http2Error")
+            .field("code", &self.code)
+            .field("message", &self.message)
+            .finish()
+    }
+}
+
+impl From<io::Error> for Http2Error {
+    fn from(error: io::Error) -> Self {
+        Http2Error::new(Http2Error::INTERNAL_ERROR, &error.to_string())
+    }
+}
+
+impl From<HttpError> for Http2Error {
+    fn from(error: HttpError) -> Self {
+        Http2Error::new(Http2Error::INTERNAL_ERROR, &error.to_string())
+    }
+}
+
 pub struct ThreadError {
     pub kind: String,
     pub message: String,
@@ -70,3 +144,28 @@ impl Debug for ThreadError {
             .finish()
     }
 }
+
+/*
+ * TOML解析错误，line与column从1开始（column按字符计）
+ */
+pub struct TomlError {
+    pub line: usize,
+    pub column: usize,
+    pub message: String,
+}
+
+impl Display for TomlError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}:{}: {}", self.line, self.column, self.message)
+    }
+}
+
+impl Debug for TomlError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("TomlError")
+            .field("line", &self.line)
+            .field("column", &self.column)
+            .field("message", &self.message)
+            .finish()
+    }
+}
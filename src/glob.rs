@@ -0,0 +1,132 @@
+use std::{
+    fs,
+    path::{Component, Path, PathBuf},
+};
+
+//是否包含通配符
+pub fn has_wildcard(part: &str) -> bool {
+    part.contains(['*', '?'])
+}
+
+/*
+ * 文件名通配：*匹配任意个字符，?匹配一个字符
+ * 与shell一致，通配符不匹配以“.”开头的隐藏文件
+ */
+pub fn matches(pattern: &str, name: &str) -> bool {
+    if name.starts_with('.') && !pattern.starts_with('.') {
+        return false;
+    }
+    let pattern: Vec<char> = pattern.chars().collect();
+    let name: Vec<char> = name.chars().collect();
+    let (mut p, mut n) = (0, 0);
+    //最近一个*的位置及其匹配到的名称位置，失配时回溯
+    let mut star = None;
+    while n < name.len() {
+        if p < pattern.len() && (pattern[p] == '?' || pattern[p] == name[n]) {
+            p += 1;
+            n += 1;
+        } else if p < pattern.len() && pattern[p] == '*' {
+            star = Some((p, n));
+            p += 1;
+        } else if let Some((star_p, star_n)) = star {
+            p = star_p + 1;
+            n = star_n + 1;
+            star = Some((star_p, star_n + 1));
+        } else {
+            return false;
+        }
+    }
+    pattern[p..].iter().all(|c| *c == '*')
+}
+
+/*
+ * 展开路径中含通配符的部分，结果按路径排序
+ * 不含通配符的路径原样返回，由调用方检查是否存在
+ */
+pub fn expand(pattern: &Path) -> Vec<PathBuf> {
+    let mut paths = vec![PathBuf::new()];
+    for component in pattern.components() {
+        let part = match component {
+            Component::Normal(part) => part.to_string_lossy(),
+            _ => {
+                paths.iter_mut().for_each(|path| path.push(component));
+                continue;
+            }
+        };
+        if !has_wildcard(&part) {
+            paths.iter_mut().for_each(|path| path.push(&*part));
+            continue;
+        }
+        let mut matched = Vec::new();
+        for dir in &paths {
+            let entries = match fs::read_dir(if dir.as_os_str().is_empty() {
+                Path::new(".")
+            } else {
+                dir
+            }) {
+                Ok(entries) => entries,
+                Err(_) => continue,
+            };
+            for entry in entries.flatten() {
+                let name = entry.file_name();
+                if matches(&part, &name.to_string_lossy()) {
+                    matched.push(dir.join(name));
+                }
+            }
+        }
+        paths = matched;
+    }
+    paths.sort();
+    paths
+}
+
+#[cfg(test)]
+mod test {
+    use std::env;
+
+    use super::*;
+
+    #[test]
+    fn test_matches() {
+        assert!(matches("*.toml", "blog.toml"));
+        assert!(!matches("*.toml", ".toml.toml"));
+        assert!(matches("v?-*.toml", "v1-a.b.toml"));
+        assert!(matches("a*b*c", "aXbYbZc"));
+        assert!(!matches("*.toml", "blog.toml.bak"));
+        assert!(!matches("?.toml", "ab.toml"));
+        assert!(matches("*", "x"));
+    }
+
+    #[test]
+    fn test_expand() {
+        let dir = env::temp_dir().join(format!("star-http-glob-{}", std::process::id()));
+        for sub in ["a", "b"] {
+            fs::create_dir_all(dir.join("conf.d").join(sub)).unwrap();
+            fs::write(dir.join("conf.d").join(sub).join("2.toml"), "").unwrap();
+            fs::write(dir.join("conf.d").join(sub).join("1.toml"), "").unwrap();
+        }
+        fs::write(dir.join("conf.d/a/.hidden.toml"), "").unwrap();
+        let found = expand(&dir.join("conf.d/*/*.toml"));
+        let names: Vec<String> = found
+            .iter()
+            .map(|path| {
+                path.strip_prefix(&dir)
+                    .unwrap()
+                    .to_string_lossy()
+                    .into_owned()
+            })
+            .collect();
+        assert_eq!(
+            names,
+            vec![
+                "conf.d/a/1.toml",
+                "conf.d/a/2.toml",
+                "conf.d/b/1.toml",
+                "conf.d/b/2.toml"
+            ]
+        );
+        assert!(expand(&dir.join("missing/*.toml")).is_empty());
+        assert_eq!(expand(&dir.join("x.toml")), vec![dir.join("x.toml")]);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -32,7 +32,7 @@ fn hex_to_byte(hex: &str) -> u8 {
     let mut res: u8 = 0;
     for s in hex.chars() {
         let s = s.to_ascii_lowercase() as u8;
-        if s >= b'a' && s <= b'f' {
+        if (b'a'..=b'f').contains(&s) {
             if res == 0 {
                 res = (s - b'a' + 10) << 4;
             } else {
@@ -40,7 +40,7 @@ fn hex_to_byte(hex: &str) -> u8 {
             }
         } else {
             if res == 0 {
-                res = s - b'0' << 4;
+                res = (s - b'0') << 4;
             } else {
                 res += s - b'0';
             }
@@ -1,58 +1,100 @@
 use std::{
     collections::HashMap,
     fs::File,
-    io::{BufRead, BufReader, Read, Write},
-    net::TcpStream,
-    path::PathBuf,
+    io::{self, Read, Write},
+    os::{fd::RawFd, unix::fs::FileExt},
+    panic::{self, AssertUnwindSafe},
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::{Duration, Instant},
 };
 
-use crate::{config::MyConfig, error::HttpError, hex, log_error, log_info};
+use crate::{
+    access_log::Record,
+    cache::{self, CachedFile, OpenFile},
+    config::{MyConfig, QueueFullPolicy},
+    context::ServerContext,
+    dev,
+    error::HttpError,
+    hex, http2, log_error, log_info, mime,
+    proxy::{self, Cidr},
+    stream::Connection,
+};
+
+#[cfg(target_os = "linux")]
+use crate::sendfile;
 
-enum HttpStatus {
+pub enum HttpStatus {
     OK,                  //"HTTP/1.0 200 OK\r\n"
+    PartialContent,      //"HTTP/1.0 206 PARTIAL CONTENT\r\n"
+    MovedPermanently,    //"HTTP/1.0 301 MOVED PERMANENTLY\r\n"
+    NotModified,         //"HTTP/1.0 304 NOT MODIFIED\r\n"
     NotFound,            //"HTTP/1.0 400 NOT FOUND\r\n"
+    RangeNotSatisfiable, //"HTTP/1.0 416 RANGE NOT SATISFIABLE\r\n"
     InternalServerError, //"HTTP/1.0 500 INTERNAL SERVER ERROR\r\n"
+    ServiceUnavailable,  //"HTTP/1.0 503 SERVICE UNAVAILABLE\r\n"
 }
 
 impl HttpStatus {
     fn get_http_status(&self) -> &str {
         match self {
             HttpStatus::OK => "HTTP/1.0 200 OK\r\n",
+            HttpStatus::PartialContent => "HTTP/1.0 206 PARTIAL CONTENT\r\n",
+            HttpStatus::MovedPermanently => "HTTP/1.0 301 MOVED PERMANENTLY\r\n",
+            HttpStatus::NotModified => "HTTP/1.0 304 NOT MODIFIED\r\n",
             HttpStatus::NotFound => "HTTP/1.0 404 NOT FOUND\r\n",
+            HttpStatus::RangeNotSatisfiable => "HTTP/1.0 416 RANGE NOT SATISFIABLE\r\n",
             HttpStatus::InternalServerError => "HTTP/1.0 500 INTERNAL SERVER ERROR\r\n",
+            HttpStatus::ServiceUnavailable => "HTTP/1.0 503 SERVICE UNAVAILABLE\r\n",
         }
     }
-    fn get_status_default_html(&self) -> Box<dyn ResponseBody> {
+    //状态码（HTTP/2的:status伪首部使用）
+    pub fn code(&self) -> u16 {
+        match self {
+            HttpStatus::OK => 200,
+            HttpStatus::PartialContent => 206,
+            HttpStatus::MovedPermanently => 301,
+            HttpStatus::NotModified => 304,
+            HttpStatus::NotFound => 404,
+            HttpStatus::RangeNotSatisfiable => 416,
+            HttpStatus::InternalServerError => 500,
+            HttpStatus::ServiceUnavailable => 503,
+        }
+    }
+    fn get_status_default_html(&self, ctx: &ServerContext) -> Box<dyn ResponseBody> {
         //获取配置
-        let config = MyConfig::new();
+        let config = ctx.config();
         //构建文件路径
-        let mut current_path = PathBuf::from(config.static_resource_path);
+        let mut current_path = PathBuf::from(&config.static_resource_path);
         match self {
-            HttpStatus::OK => Box::new(String::from("")),
+            HttpStatus::OK
+            | HttpStatus::PartialContent
+            | HttpStatus::MovedPermanently
+            | HttpStatus::NotModified => Box::new(String::from("")),
+            HttpStatus::ServiceUnavailable => Box::new(String::from("<!DOCTYPE html><head><title>503 SERVICE UNAVAILABLE</title></head><body><h1>503 SERVICE UNAVAILABLE!</h1></body></html>")),
+            HttpStatus::RangeNotSatisfiable => Box::new(String::from("<!DOCTYPE html><head><title>416 RANGE NOT SATISFIABLE</title></head><body><h1>416 RANGE NOT SATISFIABLE!</h1></body></html>")),
             HttpStatus::NotFound => {
-                if let Some(path) = config.page404_path {
+                if let Some(path) = &config.page404_path {
                     current_path = current_path.join(path);
-                    if current_path.exists() && current_path.is_file() {
-                        match File::open(current_path.as_path()) {
-                            Ok(file) => return Box::new(file),
-                            Err(e) => log_error!("Custom 404 page read error, error cause:{}", e),
-                        }
-                    } else {
-                        log_error!("Custom 404 page not found, please check the configuration!")
+                    match open_static(ctx, &config, &current_path) {
+                        Ok(Some(file)) => return Box::new(FileRange::whole(file)),
+                        Ok(None) => log_error!(
+                            "Custom 404 page not found, please check the configuration!"
+                        ),
+                        Err(e) => log_error!("Custom 404 page read error, error cause:{}", e),
                     }
                 }
                 Box::new(String::from("<!DOCTYPE html><head><title>404 NOT FOUND</title></head><body><h1>404 NOT FOUND!</h1></body></html>"))
             }
             HttpStatus::InternalServerError => {
-                if let Some(path) = config.page500_path {
+                if let Some(path) = &config.page500_path {
                     current_path = current_path.join(path);
-                    if current_path.exists() && current_path.is_file() {
-                        match File::open(current_path.as_path()) {
-                            Ok(file) => return Box::new(file),
-                            Err(e) => log_error!("Custom 500 page read error, error cause:{}", e),
-                        }
-                    } else {
-                        log_error!("Custom 500 page not found, please check the configuration!")
+                    match open_static(ctx, &config, &current_path) {
+                        Ok(Some(file)) => return Box::new(FileRange::whole(file)),
+                        Ok(None) => log_error!(
+                            "Custom 500 page not found, please check the configuration!"
+                        ),
+                        Err(e) => log_error!("Custom 500 page read error, error cause:{}", e),
                     }
                 }
                 Box::new(String::from("<!DOCTYPE html><head><title>500 INTERNAL SERVER ERROR</title></head><body><h1>500 INTERNAL SERVER ERROR!</h1></body></html>"))
@@ -75,32 +117,125 @@ impl ResponseHeader<'_> {
             header.push_str(&param);
         }
         header.push_str("\r\n");
-        return header;
+        header
     }
 }
 
-struct RequestHeader {
-    params: HashMap<String, String>,
+pub struct RequestHeader {
+    pub params: HashMap<String, String>,
 }
 
 impl RequestHeader {
     //读取请求标头
-    fn read_request_header(stream: &TcpStream) -> Result<RequestHeader, HttpError> {
+    fn read_request_header(stream: &mut dyn Connection) -> Result<RequestHeader, HttpError> {
         let mut params: HashMap<String, String> = HashMap::new();
-        while let Some(line) = read_line(&stream)? {
-            let kv: Vec<&str> = line.split(':').map(|h| h.trim()).collect();
+        while let Some(line) = read_line(stream)? {
+            let kv: Vec<&str> = line.splitn(2, ':').map(|h| h.trim()).collect();
             params.insert(
-                kv.get(0).unwrap().to_lowercase(),
+                kv.first().unwrap().to_lowercase(),
                 kv.get(1).unwrap().to_string(),
             );
         }
         Ok(RequestHeader { params })
     }
 
+    //请求的主机名（不含端口）
+    pub fn get_host(&self) -> Option<String> {
+        let host = self.params.get("host")?;
+        let host = match host.strip_prefix('[') {
+            //IPv6地址
+            Some(v6) => format!("[{}]", v6.split(']').next()?),
+            None => host.split(':').next()?.to_owned(),
+        };
+        if host.is_empty() {
+            None
+        } else {
+            Some(host)
+        }
+    }
+
+    //h2c升级请求的HTTP2-Settings
+    fn get_h2c_settings(&self) -> Option<String> {
+        let upgrade = self.params.get("upgrade")?;
+        if !upgrade
+            .split(',')
+            .any(|u| u.trim().eq_ignore_ascii_case("h2c"))
+        {
+            return None;
+        }
+        self.params.get("http2-settings").cloned()
+    }
+
+    /*
+     * 解析单个字节区间的Range请求头，返回(起始位置, 长度)
+     * 未携带或格式不支持（如多区间）时返回None，按完整内容响应
+     * 区间超出文件范围时返回Some(Err(()))
+     */
+    fn get_range(&self, file_len: u64) -> Option<Result<(u64, u64), ()>> {
+        let range = self.params.get("range")?.strip_prefix("bytes=")?.trim();
+        if range.contains(',') {
+            return None;
+        }
+        let (start, end) = range.split_once('-')?;
+        let (start, end) = (start.trim(), end.trim());
+        let (start, end) = if start.is_empty() {
+            //最后n个字节
+            let suffix = end.parse::<u64>().ok()?;
+            if suffix == 0 {
+                return Some(Err(()));
+            }
+            (file_len.saturating_sub(suffix), file_len.checked_sub(1))
+        } else {
+            let start = start.parse::<u64>().ok()?;
+            let end = if end.is_empty() {
+                file_len.checked_sub(1)
+            } else {
+                Some(end.parse::<u64>().ok()?.min(file_len.saturating_sub(1)))
+            };
+            (start, end)
+        };
+        match end {
+            Some(end) if start <= end && start < file_len => Some(Ok((start, end - start + 1))),
+            _ => Some(Err(())),
+        }
+    }
+
+    //If-None-Match是否与当前ETag匹配
+    fn matches_etag(&self, etag: &str) -> bool {
+        self.params.get("if-none-match").is_some_and(|tags| {
+            tags.split(',')
+                .map(|tag| tag.trim().trim_start_matches("W/"))
+                .any(|tag| tag == etag || tag == "*")
+        })
+    }
+
+    //Accept-Encoding是否包含指定编码
+    fn accepts_encoding(&self, encoding: &str) -> bool {
+        self.params.get("accept-encoding").is_some_and(|accept| {
+            accept.split(',').any(|item| {
+                let mut parts = item.split(';');
+                let name = parts.next().unwrap_or_default().trim();
+                let disabled =
+                    parts.any(|p| matches!(p.trim(), "q=0" | "q=0.0" | "q=0.00" | "q=0.000"));
+                name.eq_ignore_ascii_case(encoding) && !disabled
+            })
+        })
+    }
+
+    //客户端地址，对端是可信代理时取自Forwarded或X-Forwarded-For
+    pub fn client_addr(&self, peer: &str, trusted: &[Cidr]) -> String {
+        proxy::client_addr(
+            peer,
+            self.params.get("forwarded").map(String::as_str),
+            self.params.get("x-forwarded-for").map(String::as_str),
+            trusted,
+        )
+    }
+
     fn get_first_accept(&self) -> Option<String> {
         if let Some(accept) = self.params.get("accept") {
             let accepts: Vec<&str> = accept.split(',').map(|a| a.trim()).collect();
-            if let Some(accept) = accepts.get(0) {
+            if let Some(accept) = accepts.first() {
                 return Option::Some((*accept).to_owned());
             }
         }
@@ -108,33 +243,123 @@ impl RequestHeader {
     }
 }
 
-trait ResponseBody {
-    fn write_in_connect(&self, stream: &TcpStream) -> Result<(), HttpError>;
+pub trait ResponseBody {
+    fn write_in_connect(&self, stream: &mut dyn Write) -> Result<(), HttpError>;
     fn len(&self) -> Result<usize, HttpError>;
+
+    //写入客户端连接，可直接操作套接字的实现（如sendfile）可覆盖此方法
+    fn send_to(&self, stream: &mut dyn Connection) -> Result<(), HttpError> {
+        self.write_in_connect(stream)
+    }
 }
 
 impl ResponseBody for File {
-    fn write_in_connect(&self, stream: &TcpStream) -> Result<(), HttpError> {
-        let mut tcp = stream;
-        let mut buf_reader = BufReader::new(self);
-        while buf_reader.fill_buf()?.len() > 0 {
-            let size = tcp.write(buf_reader.buffer())?;
-            buf_reader.consume(size);
+    fn write_in_connect(&self, stream: &mut dyn Write) -> Result<(), HttpError> {
+        copy_range(self, 0, self.metadata()?.len(), stream)
+    }
+
+    fn len(&self) -> Result<usize, HttpError> {
+        Ok(self.metadata()?.len() as usize)
+    }
+
+    fn send_to(&self, stream: &mut dyn Connection) -> Result<(), HttpError> {
+        send_range(self, 0, self.metadata()?.len(), stream)
+    }
+}
+
+//文件的指定区间（完整文件或Range请求），文件句柄可能来自打开文件缓存
+pub struct FileRange {
+    pub file: Arc<File>,
+    pub offset: u64,
+    pub len: u64,
+}
+
+impl FileRange {
+    pub fn whole(file: OpenFile) -> FileRange {
+        FileRange {
+            len: file.metadata.len(),
+            file: file.file,
+            offset: 0,
         }
-        tcp.flush()?;
+    }
+}
+
+impl ResponseBody for FileRange {
+    fn write_in_connect(&self, stream: &mut dyn Write) -> Result<(), HttpError> {
+        copy_range(&self.file, self.offset, self.len, stream)
+    }
+
+    fn len(&self) -> Result<usize, HttpError> {
+        Ok(self.len as usize)
+    }
+
+    fn send_to(&self, stream: &mut dyn Connection) -> Result<(), HttpError> {
+        send_range(&self.file, self.offset, self.len, stream)
+    }
+}
+
+//缓存中的文件内容
+pub struct CachedBody {
+    pub bytes: Arc<Vec<u8>>,
+    pub offset: usize,
+    pub len: usize,
+}
+
+impl ResponseBody for CachedBody {
+    fn write_in_connect(&self, stream: &mut dyn Write) -> Result<(), HttpError> {
+        stream.write_all(&self.bytes[self.offset..self.offset + self.len])?;
+        stream.flush()?;
         Ok(())
     }
 
     fn len(&self) -> Result<usize, HttpError> {
-        Ok(self.metadata()?.len() as usize)
+        Ok(self.len)
+    }
+}
+
+//缓冲复制文件区间，按位置读取，不影响共享文件句柄的偏移
+fn copy_range(file: &File, offset: u64, len: u64, stream: &mut dyn Write) -> Result<(), HttpError> {
+    let mut buf = vec![0u8; len.clamp(1, 64 * 1024) as usize];
+    let mut pos = offset;
+    let end = offset + len;
+    while pos < end {
+        let want = ((end - pos) as usize).min(buf.len());
+        let size = file.read_at(&mut buf[..want], pos)?;
+        if size == 0 {
+            return Err(io::Error::from(io::ErrorKind::UnexpectedEof).into());
+        }
+        stream.write_all(&buf[..size])?;
+        pos += size as u64;
+    }
+    stream.flush()?;
+    Ok(())
+}
+
+//事件驱动模型的连接由事件循环发送文件；明文TCP连接在Linux下使用sendfile零拷贝发送，否则回退为缓冲复制
+fn send_range(
+    file: &File,
+    offset: u64,
+    len: u64,
+    stream: &mut dyn Connection,
+) -> Result<(), HttpError> {
+    if stream.defer_file(file, offset, len)? {
+        return Ok(());
+    }
+    #[cfg(target_os = "linux")]
+    if let Some(fd) = stream.raw_fd() {
+        stream.flush()?;
+        match sendfile::send_file(fd, file, offset, len) {
+            Err(e) if e.kind() == io::ErrorKind::Unsupported => {}
+            result => return Ok(result?),
+        }
     }
+    copy_range(file, offset, len, stream)
 }
 
 impl ResponseBody for String {
-    fn write_in_connect(&self, stream: &TcpStream) -> Result<(), HttpError> {
-        let mut tcp = stream;
-        tcp.write_all(self.as_bytes())?;
-        tcp.flush()?;
+    fn write_in_connect(&self, stream: &mut dyn Write) -> Result<(), HttpError> {
+        stream.write_all(self.as_bytes())?;
+        stream.flush()?;
         Ok(())
     }
 
@@ -143,86 +368,367 @@ impl ResponseBody for String {
     }
 }
 
+//服务繁忙（任务队列已满）时拒绝连接，不读取请求
+pub fn reject<S: Connection>(mut stream: S, ctx: &ServerContext) {
+    log_error!("Job queue is full, connection rejected!");
+    let config = ctx.config();
+    if config.queue_full_policy == QueueFullPolicy::Reject {
+        let result =
+            failed_response(ctx, HttpStatus::ServiceUnavailable).and_then(|mut response| {
+                response
+                    .params
+                    .insert("Retry-After", config.retry_after.to_string());
+                send(&mut stream, response)
+            });
+        if let Err(e) = result {
+            log_error!("Failed to send 503 response. Error:{}", e);
+        }
+    }
+    shutdown(stream);
+}
+
 //连接控制，读取请求并判断请求类型
-pub fn handle_connect(stream: TcpStream) {
-    if let Ok(first_line) = read_line(&stream) {
-        if let Some(first_line) = first_line {
-            //读取请求第一行参数
-            log_info!("{}", first_line);
-            let mut header = first_line.split_whitespace();
-            let request_type = header.next().unwrap();
-            let url = hex::url_decoding(header.next().unwrap().to_string());
-            //读取请求头
-            match RequestHeader::read_request_header(&stream) {
-                Ok(request_header) => {
-                    //分发请求类型处理
-                    match request_type.to_lowercase().as_str() {
-                        "get" => {
-                            if let Err(e) = get(&stream, request_header, url) {
-                                log_error!("The GET request is abnormal. Error reason: {}", e);
-                                if let Err(e) =
-                                    send_failed(&stream, &HttpStatus::InternalServerError)
-                                {
+pub fn handle_connect<S: Connection>(stream: S, ctx: &ServerContext) {
+    catch_panic(stream, ctx, |stream| serve_connect(stream, ctx))
+}
+
+fn serve_connect(stream: &mut dyn Connection, ctx: &ServerContext) {
+    if let Ok(Some(first_line)) = read_line(stream) {
+        let start = Instant::now();
+        //读取请求第一行参数
+        let http2 = ctx.config().http2;
+        //HTTP/2 prior-knowledge
+        if http2 && first_line == "PRI * HTTP/2.0" {
+            log_info!("{} {}", stream.peer_addr(), first_line);
+            http2::serve_prior_knowledge(stream, ctx);
+            return;
+        }
+        let mut header = first_line.split_whitespace();
+        let request_type = header.next().unwrap();
+        let raw_url = header.next().unwrap().to_string();
+        let url = hex::url_decoding(raw_url.clone());
+        //读取请求头
+        match RequestHeader::read_request_header(stream) {
+            Ok(request_header) => {
+                let client =
+                    request_header.client_addr(&stream.peer_addr(), &ctx.config().trusted_proxies);
+                log_info!(client = &client, method = request_type, path = &raw_url; "{} {}", client, first_line);
+                //分发请求类型处理
+                let sent = match request_type.to_lowercase().as_str() {
+                    //h2c升级（仅明文连接），升级后的请求按HTTP/2流记录访问日志
+                    "get"
+                        if http2
+                            && !stream.is_secure()
+                            && request_header.get_h2c_settings().is_some() =>
+                    {
+                        let settings = request_header.get_h2c_settings().unwrap_or_default();
+                        http2::serve_upgrade(
+                            stream,
+                            ctx,
+                            &settings,
+                            request_type,
+                            &raw_url,
+                            &request_header,
+                        );
+                        return;
+                    }
+                    "get" => match get(stream, ctx, &request_header, url) {
+                        Ok(sent) => Some(sent),
+                        Err(e) => {
+                            log_error!("The GET request is abnormal. Error reason: {}", e);
+                            match send_failed(stream, ctx, HttpStatus::InternalServerError) {
+                                Ok(sent) => Some(sent),
+                                Err(e) => {
                                     log_error!("Response 500 failed. Error reason: {}", e);
+                                    None
                                 }
                             }
                         }
-                        val => {
-                            log_error!("Do not support request type! Request type: {}", val);
-                        }
-                    };
-                }
-                Err(e) => log_error!("The read request header is abnormal! Err:{}", e),
+                    },
+                    val => {
+                        log_error!("Do not support request type! Request type: {}", val);
+                        None
+                    }
+                };
+                log_access(ctx, &client, &first_line, &request_header, sent, start);
+            }
+            Err(e) => log_error!("The read request header is abnormal! Err:{}", e),
+        }
+    }
+}
+
+//明文端口重定向到HTTPS的目标
+#[derive(Clone)]
+pub struct Redirect {
+    pub tls_port: u16,
+    //请求没有Host头时使用的主机名
+    pub default_host: String,
+}
+
+//明文端口重定向到HTTPS
+pub fn handle_redirect<S: Connection>(stream: S, ctx: &ServerContext, redirect: &Redirect) {
+    catch_panic(stream, ctx, |stream| serve_redirect(stream, ctx, redirect))
+}
+
+fn serve_redirect(stream: &mut dyn Connection, ctx: &ServerContext, redirect: &Redirect) {
+    if let Ok(Some(first_line)) = read_line(stream) {
+        let start = Instant::now();
+        let url = first_line
+            .split_whitespace()
+            .nth(1)
+            .unwrap_or("/")
+            .to_owned();
+        match RequestHeader::read_request_header(stream) {
+            Ok(request_header) => {
+                let client =
+                    request_header.client_addr(&stream.peer_addr(), &ctx.config().trusted_proxies);
+                log_info!(client = &client, path = &url; "{} {}", client, first_line);
+                let host = request_header
+                    .get_host()
+                    .unwrap_or_else(|| redirect.default_host.clone());
+                let location = match redirect.tls_port {
+                    443 => format!("https://{}{}", host, url),
+                    port => format!("https://{}:{}{}", host, port, url),
+                };
+                let sent = match send_redirect(stream, &location) {
+                    Ok(sent) => Some(sent),
+                    Err(e) => {
+                        log_error!("Response 301 failed. Error reason: {}", e);
+                        None
+                    }
+                };
+                log_access(ctx, &client, &first_line, &request_header, sent, start);
+            }
+            Err(e) => log_error!("The read request header is abnormal! Err:{}", e),
+        }
+    }
+}
+
+/*
+ * 捕获请求处理中的panic，避免影响工作线程
+ * 尚未向客户端写出任何数据时返回500
+ */
+fn catch_panic<S: Connection>(
+    stream: S,
+    ctx: &ServerContext,
+    handler: impl FnOnce(&mut dyn Connection),
+) {
+    let mut stream = Tracked {
+        stream,
+        written: false,
+    };
+    if panic::catch_unwind(AssertUnwindSafe(|| handler(&mut stream))).is_err() {
+        log_error!("Request handling panicked!");
+        if !stream.written {
+            if let Err(e) = send_failed(&mut stream, ctx, HttpStatus::InternalServerError) {
+                log_error!("Response 500 failed. Error reason: {}", e);
             }
         }
     }
     shutdown(stream);
 }
 
+//记录是否已写出响应数据的连接
+struct Tracked<S> {
+    stream: S,
+    written: bool,
+}
+
+impl<S: Connection> Read for Tracked<S> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.stream.read(buf)
+    }
+}
+
+impl<S: Connection> Write for Tracked<S> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let size = self.stream.write(buf)?;
+        self.written |= size > 0;
+        Ok(size)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.stream.flush()
+    }
+}
+
+impl<S: Connection> Connection for Tracked<S> {
+    fn shutdown(&mut self) -> io::Result<()> {
+        self.stream.shutdown()
+    }
+
+    fn is_secure(&self) -> bool {
+        self.stream.is_secure()
+    }
+
+    fn raw_fd(&self) -> Option<RawFd> {
+        self.stream.raw_fd()
+    }
+
+    fn defer_file(&mut self, file: &File, offset: u64, len: u64) -> io::Result<bool> {
+        let deferred = self.stream.defer_file(file, offset, len)?;
+        self.written |= deferred;
+        Ok(deferred)
+    }
+
+    fn peer_addr(&self) -> String {
+        self.stream.peer_addr()
+    }
+}
+
+//已发送的响应，用于访问日志
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Sent {
+    pub status: u16,
+    pub body_bytes: u64,
+    //含响应头
+    pub bytes: u64,
+}
+
+//写入访问日志，未配置时忽略
+pub fn log_access(
+    ctx: &ServerContext,
+    client: &str,
+    request: &str,
+    request_header: &RequestHeader,
+    sent: Option<Sent>,
+    start: Instant,
+) {
+    let config = ctx.config();
+    if let Some(access_log) = &config.access_log {
+        let record = Record {
+            client,
+            request,
+            header: request_header,
+            status: sent.map(|sent| sent.status),
+            body_bytes: sent.map_or(0, |sent| sent.body_bytes),
+            bytes_sent: sent.map_or(0, |sent| sent.bytes),
+            start,
+            timezone: config.timezone,
+        };
+        ctx.access_log
+            .log(access_log, request_header.get_host().as_deref(), &record);
+    }
+}
+
+//完整的响应（状态、首部与响应体）
+pub struct Response {
+    pub http_status: HttpStatus,
+    pub params: HashMap<&'static str, String>,
+    pub body: Box<dyn ResponseBody>,
+}
+
+//GET请求的处理结果
+pub enum Route {
+    Response(Response),
+    //需要保持连接推送事件
+    EventStream,
+}
+
 //GET请求
 fn get(
-    stream: &TcpStream,
-    request_header: RequestHeader,
+    stream: &mut dyn Connection,
+    ctx: &ServerContext,
+    request_header: &RequestHeader,
+    url: String,
+) -> Result<Sent, HttpError> {
+    match route_get(ctx, request_header, url)? {
+        Route::Response(response) => send(stream, response),
+        Route::EventStream => send_event_stream(stream, ctx, request_header),
+    }
+}
+
+//解析GET请求对应的静态资源，HTTP/1与HTTP/2共用
+pub fn route_get(
+    ctx: &ServerContext,
+    request_header: &RequestHeader,
     mut url: String,
-) -> Result<(), HttpError> {
+) -> Result<Route, HttpError> {
     //默认页面
-    let config = MyConfig::new();
+    let config = ctx.config();
+    //SSE事件推送
+    if config.sse_path.as_deref() == Some(url.trim()) {
+        return Ok(Route::EventStream);
+    }
+    //开发模式页面刷新推送
+    if config.dev && dev::LIVE_RELOAD_PATH == url.trim() {
+        return Ok(Route::EventStream);
+    }
+    //运行统计
+    if config.stats_path.as_deref() == Some(url.trim()) {
+        return Ok(Route::Response(stats_response(ctx)));
+    }
     if "/".eq(url.trim()) {
-        url = config.index_page_path;
+        url = config.index_page_path.clone();
     }
     //构建文件路径
-    let mut current_path = PathBuf::from(config.static_resource_path);
+    let mut current_path = PathBuf::from(&config.static_resource_path);
     if !current_path.is_absolute() {
         current_path = current_path.canonicalize()?;
     }
     for node in url.split("/") {
         current_path = current_path.join(node);
     }
-    if current_path.exists() && current_path.is_file() {
-        match File::open(current_path.as_path()) {
-            Ok(file) => {
-                send_ok(stream, request_header, file)?;
-                log_info!("GET {} SUCCESS!", url);
-            }
-            Err(e) => return Result::Err(HttpError::from(e)),
+    let file = match open_static(ctx, &config, &current_path)? {
+        Some(file) => file,
+        None => return Ok(Route::Response(failed_response(ctx, HttpStatus::NotFound)?)),
+    };
+    let response = if config.dev && dev::is_html(&current_path) {
+        //开发模式下为页面注入自动刷新脚本
+        let mut html = Vec::new();
+        copy_range(&file.file, 0, file.metadata.len(), &mut html)?;
+        let html = dev::inject_reload_script(&String::from_utf8_lossy(&html));
+        ok_response(request_header, Box::new(html), config.dev)
+    } else {
+        //内存缓存
+        let cached = if config.cache_max_bytes > 0 {
+            let gzip = open_static(ctx, &config, &cache::gzip_path(&current_path))?;
+            ctx.content_cache.get(
+                &ctx.stats,
+                &current_path,
+                &file.metadata,
+                gzip.as_ref().map(|gzip| &gzip.metadata),
+                config.cache_max_bytes,
+                config.cache_max_file_size,
+            )
+        } else {
+            None
+        };
+        match cached {
+            Some(cached) => cached_response(ctx, request_header, cached, config.dev)?,
+            None => file_response(ctx, request_header, file, &current_path, config.dev)?,
         }
+    };
+    log_info!(path = &url; "GET {} SUCCESS!", url);
+    Ok(Route::Response(response))
+}
+
+//打开静态文件，配置了打开文件缓存时复用句柄（开发模式下需及时反映修改，不使用缓存）
+fn open_static(
+    ctx: &ServerContext,
+    config: &MyConfig,
+    path: &Path,
+) -> Result<Option<OpenFile>, HttpError> {
+    let max_entries = if config.dev {
+        0
     } else {
-        send_failed(stream, &HttpStatus::NotFound)?;
-    }
-    Ok(())
+        config.open_file_cache_max
+    };
+    let valid = Duration::from_secs(config.open_file_cache_valid);
+    Ok(ctx
+        .open_file_cache
+        .open(&ctx.stats, path, max_entries, valid)?)
 }
 
 /*
  * 读取一行数据
  */
-fn read_line(stream: &TcpStream) -> Result<Option<String>, HttpError> {
+fn read_line(stream: &mut dyn Connection) -> Result<Option<String>, HttpError> {
     let mut line = String::new();
-    let tcp = stream;
-    for buf in tcp.bytes() {
-        let val = buf? as char;
+    while let Some(val) = read_byte(stream)? {
+        let val = val as char;
         if val == '\r' {
-            let val = tcp.bytes().next().unwrap();
-            if val? as char != '\n' {
+            if read_byte(stream)? != Some(b'\n') {
                 return Err(HttpError {
                     kind: "read_line".to_string(),
                     message: "read line error: Read only '\\r', no '\\n'".to_string(),
@@ -234,60 +740,441 @@ fn read_line(stream: &TcpStream) -> Result<Option<String>, HttpError> {
             line.push(val);
         }
     }
-    if line.len() > 0 {
+    if !line.is_empty() {
         Ok(Some(line))
     } else {
         Ok(None)
     }
 }
 
-fn send_ok(stream: &TcpStream, request_header: RequestHeader, file: File) -> Result<(), HttpError> {
+/*
+ * 读取一个字节
+ */
+fn read_byte(stream: &mut dyn Connection) -> Result<Option<u8>, HttpError> {
+    let mut buf = [0u8; 1];
+    match stream.read(&mut buf)? {
+        0 => Ok(None),
+        _ => Ok(Some(buf[0])),
+    }
+}
+
+fn ok_response(
+    request_header: &RequestHeader,
+    body: Box<dyn ResponseBody>,
+    no_store: bool,
+) -> Response {
     let mut params: HashMap<&str, String> = HashMap::new();
     match request_header.get_first_accept() {
         Some(accept) => params.insert("Content-Type", format!("{}; charset=utf-8", accept)),
         _ => params.insert("Content-Type", String::from("text/html; charset=utf-8")),
     };
-    if let Ok(metadata) = file.metadata() {
-        params.insert("Content-Length", metadata.len().to_string());
+    if let Ok(len) = body.len() {
+        params.insert("Content-Length", len.to_string());
     }
-    let header = ResponseHeader {
-        http_status: &HttpStatus::OK,
+    //开发模式禁用缓存
+    if no_store {
+        params.insert("Cache-Control", String::from("no-store"));
+    }
+    Response {
+        http_status: HttpStatus::OK,
         params,
+        body,
+    }
+}
+
+//静态文件响应
+fn file_response(
+    ctx: &ServerContext,
+    request_header: &RequestHeader,
+    file: OpenFile,
+    path: &Path,
+    no_store: bool,
+) -> Result<Response, HttpError> {
+    let len = file.metadata.len();
+    let etag = cache::etag(&file.metadata);
+    entity_response(
+        ctx,
+        request_header,
+        len,
+        etag,
+        mime::from_path(path),
+        no_store,
+        |range| {
+            let (offset, len) = range.unwrap_or((0, len));
+            Box::new(FileRange {
+                file: file.file,
+                offset,
+                len,
+            })
+        },
+    )
+}
+
+//缓存命中的响应，客户端支持时发送预压缩版本
+fn cached_response(
+    ctx: &ServerContext,
+    request_header: &RequestHeader,
+    cached: Arc<CachedFile>,
+    no_store: bool,
+) -> Result<Response, HttpError> {
+    let gzip = cached
+        .gzip
+        .as_ref()
+        .filter(|_| request_header.accepts_encoding("gzip"));
+    let (bytes, etag) = match gzip {
+        Some(gzip) => (Arc::clone(&gzip.bytes), gzip.etag.clone()),
+        None => (Arc::clone(&cached.bytes), cached.etag.clone()),
     };
-    send(stream, header, Box::new(file))
+    let mut response = entity_response(
+        ctx,
+        request_header,
+        bytes.len() as u64,
+        etag,
+        cached.mime,
+        no_store,
+        |range| {
+            let (offset, len) = range.unwrap_or((0, bytes.len() as u64));
+            Box::new(CachedBody {
+                bytes,
+                offset: offset as usize,
+                len: len as usize,
+            })
+        },
+    )?;
+    if cached.gzip.is_some() {
+        response
+            .params
+            .insert("Vary", String::from("Accept-Encoding"));
+    }
+    if gzip.is_some() {
+        response
+            .params
+            .insert("Content-Encoding", String::from("gzip"));
+    }
+    Ok(response)
 }
 
-fn send_failed(stream: &TcpStream, http_status: &HttpStatus) -> Result<(), HttpError> {
-    let html = http_status.get_status_default_html();
+/*
+ * 文件类响应的公共处理：条件请求（ETag）、Range请求与Content-Type
+ * body根据请求的区间构建响应体，None表示完整内容
+ */
+fn entity_response(
+    ctx: &ServerContext,
+    request_header: &RequestHeader,
+    len: u64,
+    etag: String,
+    content_type: Option<&'static str>,
+    no_store: bool,
+    body: impl FnOnce(Option<(u64, u64)>) -> Box<dyn ResponseBody>,
+) -> Result<Response, HttpError> {
+    if request_header.matches_etag(&etag) {
+        let mut response = failed_response(ctx, HttpStatus::NotModified)?;
+        response.params.remove("Content-Type");
+        response.params.insert("ETag", etag);
+        return Ok(response);
+    }
+    let mut response = match request_header.get_range(len) {
+        None => ok_response(request_header, body(None), no_store),
+        Some(Ok((offset, range_len))) => {
+            let mut response =
+                ok_response(request_header, body(Some((offset, range_len))), no_store);
+            response.http_status = HttpStatus::PartialContent;
+            response.params.insert(
+                "Content-Range",
+                format!("bytes {}-{}/{}", offset, offset + range_len - 1, len),
+            );
+            response
+        }
+        Some(Err(())) => {
+            let mut response = failed_response(ctx, HttpStatus::RangeNotSatisfiable)?;
+            response
+                .params
+                .insert("Content-Range", format!("bytes */{}", len));
+            return Ok(response);
+        }
+    };
+    if let Some(content_type) = content_type {
+        response
+            .params
+            .insert("Content-Type", content_type.to_owned());
+    }
+    response
+        .params
+        .insert("Accept-Ranges", String::from("bytes"));
+    response.params.insert("ETag", etag);
+    Ok(response)
+}
+
+fn stats_response(ctx: &ServerContext) -> Response {
+    let body = ctx.stats.to_json();
+    let mut params: HashMap<&str, String> = HashMap::new();
+    params.insert("Content-Type", String::from("application/json"));
+    params.insert("Content-Length", body.len().to_string());
+    params.insert("Cache-Control", String::from("no-store"));
+    Response {
+        http_status: HttpStatus::OK,
+        params,
+        body: Box::new(body),
+    }
+}
+
+pub fn failed_response(
+    ctx: &ServerContext,
+    http_status: HttpStatus,
+) -> Result<Response, HttpError> {
+    let html = http_status.get_status_default_html(ctx);
     let mut params: HashMap<&str, String> = HashMap::new();
     params.insert(
         "Content-Type",
         String::from("text/html;text/html; charset=utf-8"),
     );
     params.insert("Content-Length", html.len()?.to_string());
-    let header = ResponseHeader {
+    Ok(Response {
         http_status,
         params,
-    };
-    send(stream, header, html)
+        body: html,
+    })
 }
 
-fn send(
-    stream: &TcpStream,
-    response_header: ResponseHeader,
-    body: Box<dyn ResponseBody>,
-) -> Result<(), HttpError> {
-    let mut tcp = stream;
-    tcp.write_all(response_header.get().as_bytes())?;
-    body.write_in_connect(stream)?;
-    Ok(())
+fn send_failed(
+    stream: &mut dyn Connection,
+    ctx: &ServerContext,
+    http_status: HttpStatus,
+) -> Result<Sent, HttpError> {
+    send(stream, failed_response(ctx, http_status)?)
+}
+
+fn send_redirect(stream: &mut dyn Connection, location: &str) -> Result<Sent, HttpError> {
+    let mut params: HashMap<&str, String> = HashMap::new();
+    params.insert("Location", location.to_owned());
+    params.insert("Content-Length", String::from("0"));
+    send(
+        stream,
+        Response {
+            http_status: HttpStatus::MovedPermanently,
+            params,
+            body: Box::new(String::new()),
+        },
+    )
+}
+
+//保持连接并持续推送事件，直到客户端断开
+fn send_event_stream(
+    stream: &mut dyn Connection,
+    ctx: &ServerContext,
+    request_header: &RequestHeader,
+) -> Result<Sent, HttpError> {
+    let heartbeat = ctx.config().sse_heartbeat;
+    let last_event_id = request_header
+        .params
+        .get("last-event-id")
+        .and_then(|id| id.parse::<u64>().ok());
+    let mut subscription = ctx.broadcaster.subscribe(last_event_id);
+    let mut params: HashMap<&str, String> = HashMap::new();
+    params.insert(
+        "Content-Type",
+        String::from("text/event-stream; charset=utf-8"),
+    );
+    params.insert("Cache-Control", String::from("no-cache"));
+    let header = ResponseHeader {
+        http_status: &HttpStatus::OK,
+        params,
+    }
+    .get();
+    stream.write_all(header.as_bytes())?;
+    stream.flush()?;
+    log_info!("SSE client subscribed, last event id: {:?}", last_event_id);
+    let mut body_bytes = 0;
+    while let Ok(event) = subscription.next(Duration::from_secs(heartbeat)) {
+        let data = match event {
+            Some(event) => event.to_string(),
+            None => String::from(": heartbeat\n\n"),
+        };
+        stream.write_all(data.as_bytes())?;
+        stream.flush()?;
+        body_bytes += data.len() as u64;
+    }
+    Ok(Sent {
+        status: HttpStatus::OK.code(),
+        body_bytes,
+        bytes: header.len() as u64 + body_bytes,
+    })
 }
 
-fn shutdown(stream: TcpStream) {
-    if let Err(e) = stream.shutdown(std::net::Shutdown::Both) {
-        log_error!(
+fn send(stream: &mut dyn Connection, response: Response) -> Result<Sent, HttpError> {
+    let header = ResponseHeader {
+        http_status: &response.http_status,
+        params: response.params,
+    }
+    .get();
+    stream.write_all(header.as_bytes())?;
+    response.body.send_to(stream)?;
+    let body_bytes = response.body.len().map_or(0, |len| len as u64);
+    Ok(Sent {
+        status: response.http_status.code(),
+        body_bytes,
+        bytes: header.len() as u64 + body_bytes,
+    })
+}
+
+fn shutdown<S: Connection>(mut stream: S) {
+    match stream.shutdown() {
+        //对端已先行关闭
+        Err(e) if e.kind() == std::io::ErrorKind::NotConnected => {}
+        Err(e) => log_error!(
             "Failed to shutdown the connection. Error reason: {}",
             e.to_string()
+        ),
+        Ok(()) => {}
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{
+        io::{Read, Write},
+        net::{TcpListener, TcpStream},
+        thread,
+    };
+
+    use super::*;
+    use crate::{
+        config::{ConfigSource, LogOutput},
+        thread::ThreadPool,
+    };
+
+    fn header(range: &str) -> RequestHeader {
+        let mut params = HashMap::new();
+        params.insert(String::from("range"), range.to_owned());
+        RequestHeader { params }
+    }
+
+    #[test]
+    fn test_get_range() {
+        assert_eq!(header("bytes=0-9").get_range(100), Some(Ok((0, 10))));
+        assert_eq!(header("bytes=90-").get_range(100), Some(Ok((90, 10))));
+        assert_eq!(header("bytes=-10").get_range(100), Some(Ok((90, 10))));
+        assert_eq!(header("bytes=50-500").get_range(100), Some(Ok((50, 50))));
+        assert_eq!(header("bytes=100-").get_range(100), Some(Err(())));
+        assert_eq!(header("bytes=9-0").get_range(100), Some(Err(())));
+        assert_eq!(header("bytes=0-1,5-6").get_range(100), None);
+        assert_eq!(header("items=0-1").get_range(100), None);
+    }
+
+    #[test]
+    fn test_conditional_headers() {
+        let mut request_header = header("bytes=0-1");
+        request_header.params.insert(
+            String::from("if-none-match"),
+            String::from("\"a\", W/\"b\""),
+        );
+        request_header.params.insert(
+            String::from("accept-encoding"),
+            String::from("br, gzip;q=0, deflate"),
         );
+        assert!(request_header.matches_etag("\"b\""));
+        assert!(!request_header.matches_etag("\"c\""));
+        assert!(request_header.accepts_encoding("br"));
+        assert!(!request_header.accepts_encoding("gzip"));
+    }
+
+    //同一进程中的两个实例使用各自的配置与统计
+    #[test]
+    fn test_independent_contexts() {
+        let plain = ServerContext::load();
+        let mut config = MyConfig::load(&ConfigSource::default()).unwrap();
+        config.stats_path = Some(String::from("/__test_stats"));
+        config.open_file_cache_max = 8;
+        let stats = ServerContext::new(config, ConfigSource::default());
+        let route = |ctx: &ServerContext, url: &str| match route_get(
+            ctx,
+            &header("bytes=0-"),
+            url.to_owned(),
+        )
+        .unwrap()
+        {
+            Route::Response(response) => response.http_status.code(),
+            Route::EventStream => 0,
+        };
+        assert_eq!(route(&plain, "/__test_stats"), 404);
+        assert_eq!(route(&stats, "/__test_stats"), 200);
+        route(&stats, "/404.html");
+        route(&stats, "/404.html");
+        assert!(
+            stats
+                .stats
+                .open_file_hits
+                .load(std::sync::atomic::Ordering::Relaxed)
+                > 0
+        );
+        assert_eq!(
+            plain
+                .stats
+                .open_file_hits
+                .load(std::sync::atomic::Ordering::Relaxed),
+            0
+        );
+        //运行日志的时区与格式各自独立
+        let mut config = MyConfig::load(&ConfigSource::default()).unwrap();
+        config.timezone += 1;
+        config.log_format = LogOutput::Json;
+        let json = ServerContext::new(config, ConfigSource::default());
+        assert_eq!(json.log.timezone(), plain.log.timezone() + 1);
+    }
+
+    //注入panic（请求行缺少URL），服务器返回500并继续处理后续请求
+    #[test]
+    fn test_panic_isolation() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let ctx = ServerContext::load();
+        let pool =
+            ThreadPool::new(1, 1, Duration::from_secs(60), 16, Arc::clone(&ctx.stats)).unwrap();
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                let ctx = Arc::clone(&ctx);
+                let _ = pool.try_exec(stream.unwrap(), move |stream| handle_connect(stream, &ctx));
+            }
+        });
+        for _ in 0..3 {
+            let mut client = TcpStream::connect(addr).unwrap();
+            client.write_all(b"GET\r\n\r\n").unwrap();
+            let mut response = String::new();
+            client.read_to_string(&mut response).unwrap();
+            assert!(response.starts_with("HTTP/1.0 500 INTERNAL SERVER ERROR"));
+        }
+        let mut client = TcpStream::connect(addr).unwrap();
+        client.write_all(b"GET / HTTP/1.1\r\n\r\n").unwrap();
+        let mut response = String::new();
+        client.read_to_string(&mut response).unwrap();
+        assert!(response.starts_with("HTTP/1.0 200 OK"));
+    }
+
+    #[test]
+    fn test_range_request() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let ctx = ServerContext::load();
+        thread::spawn(move || {
+            for stream in listener.incoming().take(2) {
+                handle_connect(stream.unwrap(), &ctx);
+            }
+        });
+        let mut client = TcpStream::connect(addr).unwrap();
+        client
+            .write_all(b"GET /404.html HTTP/1.1\r\nRange: bytes=0-14\r\n\r\n")
+            .unwrap();
+        let mut response = String::new();
+        client.read_to_string(&mut response).unwrap();
+        assert!(response.starts_with("HTTP/1.0 206 PARTIAL CONTENT"));
+        assert!(response.contains("Content-Range:bytes 0-14/"));
+        assert!(response.ends_with("\r\n\r\n<!DOCTYPE html>"));
+        let mut client = TcpStream::connect(addr).unwrap();
+        client
+            .write_all(b"GET /404.html HTTP/1.1\r\nRange: bytes=99999-\r\n\r\n")
+            .unwrap();
+        let mut response = String::new();
+        client.read_to_string(&mut response).unwrap();
+        assert!(response.starts_with("HTTP/1.0 416 RANGE NOT SATISFIABLE"));
     }
 }
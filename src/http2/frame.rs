@@ -0,0 +1,149 @@
+use std::io::{self, Read, Write};
+
+use crate::error::Http2Error;
+
+pub const DATA: u8 = 0x0;
+pub const HEADERS: u8 = 0x1;
+pub const PRIORITY: u8 = 0x2;
+pub const RST_STREAM: u8 = 0x3;
+pub const SETTINGS: u8 = 0x4;
+pub const PUSH_PROMISE: u8 = 0x5;
+pub const PING: u8 = 0x6;
+pub const GOAWAY: u8 = 0x7;
+pub const WINDOW_UPDATE: u8 = 0x8;
+pub const CONTINUATION: u8 = 0x9;
+
+pub const FLAG_END_STREAM: u8 = 0x1;
+pub const FLAG_ACK: u8 = 0x1;
+pub const FLAG_END_HEADERS: u8 = 0x4;
+pub const FLAG_PADDED: u8 = 0x8;
+pub const FLAG_PRIORITY: u8 = 0x20;
+
+//帧首部长度
+const HEADER_LEN: usize = 9;
+
+pub struct Frame {
+    pub kind: u8,
+    pub flags: u8,
+    pub stream_id: u32,
+    pub payload: Vec<u8>,
+}
+
+impl Frame {
+    pub fn new(kind: u8, flags: u8, stream_id: u32, payload: Vec<u8>) -> Frame {
+        Frame {
+            kind,
+            flags,
+            stream_id,
+            payload,
+        }
+    }
+
+    pub fn has_flag(&self, flag: u8) -> bool {
+        self.flags & flag != 0
+    }
+
+    /*
+     * 读取一帧，连接正常关闭时返回None
+     * max_size为本端通告的SETTINGS_MAX_FRAME_SIZE
+     */
+    pub fn read(stream: &mut dyn Read, max_size: usize) -> Result<Option<Frame>, Http2Error> {
+        let mut header = [0u8; HEADER_LEN];
+        match stream.read_exact(&mut header) {
+            Ok(()) => {}
+            Err(e)
+                if e.kind() == io::ErrorKind::UnexpectedEof
+                    || e.kind() == io::ErrorKind::ConnectionReset =>
+            {
+                return Ok(None)
+            }
+            Err(e) => return Err(e.into()),
+        }
+        let len = u32::from_be_bytes([0, header[0], header[1], header[2]]) as usize;
+        if len > max_size {
+            return Err(Http2Error::new(
+                Http2Error::FRAME_SIZE_ERROR,
+                "Frame larger than SETTINGS_MAX_FRAME_SIZE",
+            ));
+        }
+        let mut payload = vec![0u8; len];
+        stream.read_exact(&mut payload)?;
+        Ok(Some(Frame {
+            kind: header[3],
+            flags: header[4],
+            stream_id: u32::from_be_bytes([header[5], header[6], header[7], header[8]])
+                & 0x7fff_ffff,
+            payload,
+        }))
+    }
+
+    pub fn write(&self, stream: &mut dyn Write) -> io::Result<()> {
+        let len = (self.payload.len() as u32).to_be_bytes();
+        let mut buf = Vec::with_capacity(HEADER_LEN + self.payload.len());
+        buf.extend_from_slice(&len[1..]);
+        buf.push(self.kind);
+        buf.push(self.flags);
+        buf.extend_from_slice(&(self.stream_id & 0x7fff_ffff).to_be_bytes());
+        buf.extend_from_slice(&self.payload);
+        stream.write_all(&buf)
+    }
+
+    //去掉PADDED标志对应的填充，以及HEADERS帧的优先级字段
+    pub fn strip_padding_and_priority(&mut self) -> Result<(), Http2Error> {
+        let mut start = 0;
+        let mut end = self.payload.len();
+        if self.has_flag(FLAG_PADDED) {
+            let pad = *self
+                .payload
+                .first()
+                .ok_or_else(|| Http2Error::protocol("Missing pad length"))?
+                as usize;
+            start = 1;
+            end = end
+                .checked_sub(pad)
+                .filter(|end| *end >= start)
+                .ok_or_else(|| Http2Error::protocol("Padding exceeds payload"))?;
+        }
+        if self.kind == HEADERS && self.has_flag(FLAG_PRIORITY) {
+            start += 5;
+            if start > end {
+                return Err(Http2Error::protocol("Invalid priority field"));
+            }
+        }
+        self.payload = self.payload[start..end].to_vec();
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_roundtrip() {
+        let frame = Frame::new(HEADERS, FLAG_END_HEADERS, 3, vec![0x82, 0x84]);
+        let mut buf = Vec::new();
+        frame.write(&mut buf).unwrap();
+        assert_eq!(buf, vec![0, 0, 2, 1, 4, 0, 0, 0, 3, 0x82, 0x84]);
+        let read = Frame::read(&mut buf.as_slice(), 16384).unwrap().unwrap();
+        assert_eq!(read.kind, HEADERS);
+        assert_eq!(read.stream_id, 3);
+        assert_eq!(read.payload, vec![0x82, 0x84]);
+        assert!(Frame::read(&mut [].as_slice(), 16384).unwrap().is_none());
+        assert!(Frame::read(&mut buf.as_slice(), 1).is_err());
+    }
+
+    #[test]
+    fn test_strip_padding() {
+        let mut frame = Frame::new(
+            HEADERS,
+            FLAG_PADDED | FLAG_PRIORITY,
+            1,
+            vec![2, 0, 0, 0, 0, 16, 0x82, 0, 0],
+        );
+        frame.strip_padding_and_priority().unwrap();
+        assert_eq!(frame.payload, vec![0x82]);
+        let mut frame = Frame::new(DATA, FLAG_PADDED, 1, vec![5, 1]);
+        assert!(frame.strip_padding_and_priority().is_err());
+    }
+}
@@ -0,0 +1,411 @@
+use std::collections::VecDeque;
+
+use super::huffman;
+use crate::error::Http2Error;
+
+pub type Header = (String, String);
+
+//默认的动态表大小（SETTINGS_HEADER_TABLE_SIZE）
+pub const DEFAULT_TABLE_SIZE: usize = 4096;
+
+/*
+ * HPACK静态表（RFC 7541 附录A），索引从1开始
+ */
+const STATIC_TABLE: [(&str, &str); 61] = [
+    (":authority", ""),
+    (":method", "GET"),
+    (":method", "POST"),
+    (":path", "/"),
+    (":path", "/index.html"),
+    (":scheme", "http"),
+    (":scheme", "https"),
+    (":status", "200"),
+    (":status", "204"),
+    (":status", "206"),
+    (":status", "304"),
+    (":status", "400"),
+    (":status", "404"),
+    (":status", "500"),
+    ("accept-charset", ""),
+    ("accept-encoding", "gzip, deflate"),
+    ("accept-language", ""),
+    ("accept-ranges", ""),
+    ("accept", ""),
+    ("access-control-allow-origin", ""),
+    ("age", ""),
+    ("allow", ""),
+    ("authorization", ""),
+    ("cache-control", ""),
+    ("content-disposition", ""),
+    ("content-encoding", ""),
+    ("content-language", ""),
+    ("content-length", ""),
+    ("content-location", ""),
+    ("content-range", ""),
+    ("content-type", ""),
+    ("cookie", ""),
+    ("date", ""),
+    ("etag", ""),
+    ("expect", ""),
+    ("expires", ""),
+    ("from", ""),
+    ("host", ""),
+    ("if-match", ""),
+    ("if-modified-since", ""),
+    ("if-none-match", ""),
+    ("if-range", ""),
+    ("if-unmodified-since", ""),
+    ("last-modified", ""),
+    ("link", ""),
+    ("location", ""),
+    ("max-forwards", ""),
+    ("proxy-authenticate", ""),
+    ("proxy-authorization", ""),
+    ("range", ""),
+    ("referer", ""),
+    ("refresh", ""),
+    ("retry-after", ""),
+    ("server", ""),
+    ("set-cookie", ""),
+    ("strict-transport-security", ""),
+    ("transfer-encoding", ""),
+    ("user-agent", ""),
+    ("vary", ""),
+    ("via", ""),
+    ("www-authenticate", ""),
+];
+
+struct DynamicTable {
+    //新条目在前
+    entries: VecDeque<Header>,
+    size: usize,
+    max_size: usize,
+}
+
+impl DynamicTable {
+    fn new(max_size: usize) -> DynamicTable {
+        DynamicTable {
+            entries: VecDeque::new(),
+            size: 0,
+            max_size,
+        }
+    }
+
+    //条目大小：名称与值的长度加32字节开销
+    fn entry_size(header: &Header) -> usize {
+        header.0.len() + header.1.len() + 32
+    }
+
+    fn insert(&mut self, header: Header) {
+        let size = Self::entry_size(&header);
+        self.evict(self.max_size.saturating_sub(size));
+        //比整个表还大的条目会清空表且不插入
+        if size <= self.max_size {
+            self.size += size;
+            self.entries.push_front(header);
+        }
+    }
+
+    fn set_max_size(&mut self, max_size: usize) {
+        self.max_size = max_size;
+        self.evict(max_size);
+    }
+
+    fn evict(&mut self, limit: usize) {
+        while self.size > limit {
+            match self.entries.pop_back() {
+                Some(header) => self.size -= Self::entry_size(&header),
+                None => break,
+            }
+        }
+    }
+}
+
+pub struct Decoder {
+    table: DynamicTable,
+    //SETTINGS_HEADER_TABLE_SIZE允许的上限
+    max_table_size: usize,
+}
+
+impl Decoder {
+    pub fn new() -> Decoder {
+        Decoder {
+            table: DynamicTable::new(DEFAULT_TABLE_SIZE),
+            max_table_size: DEFAULT_TABLE_SIZE,
+        }
+    }
+
+    //解码一个完整的首部块
+    pub fn decode(&mut self, block: &[u8]) -> Result<Vec<Header>, Http2Error> {
+        let mut headers = Vec::new();
+        let mut pos = 0;
+        while pos < block.len() {
+            let byte = block[pos];
+            if byte & 0x80 != 0 {
+                //已索引的首部
+                let index = decode_integer(block, &mut pos, 7)?;
+                headers.push(self.get(index)?);
+            } else if byte & 0x40 != 0 {
+                //带增量索引的字面量
+                let header = self.decode_literal(block, &mut pos, 6)?;
+                self.table.insert(header.clone());
+                headers.push(header);
+            } else if byte & 0x20 != 0 {
+                //动态表大小更新只能出现在首部块开头
+                if !headers.is_empty() {
+                    return Err(Http2Error::compression(
+                        "Dynamic table size update after header field",
+                    ));
+                }
+                let size = decode_integer(block, &mut pos, 5)?;
+                if size > self.max_table_size {
+                    return Err(Http2Error::compression("Dynamic table size too large"));
+                }
+                self.table.set_max_size(size);
+            } else {
+                //不索引（0000）与永不索引（0001）的字面量
+                headers.push(self.decode_literal(block, &mut pos, 4)?);
+            }
+        }
+        Ok(headers)
+    }
+
+    fn decode_literal(
+        &self,
+        block: &[u8],
+        pos: &mut usize,
+        prefix: u8,
+    ) -> Result<Header, Http2Error> {
+        let index = decode_integer(block, pos, prefix)?;
+        let name = match index {
+            0 => decode_string(block, pos)?,
+            index => self.get(index)?.0,
+        };
+        let value = decode_string(block, pos)?;
+        Ok((name, value))
+    }
+
+    fn get(&self, index: usize) -> Result<Header, Http2Error> {
+        if index == 0 {
+            return Err(Http2Error::compression("Header index 0"));
+        }
+        if index <= STATIC_TABLE.len() {
+            let (name, value) = STATIC_TABLE[index - 1];
+            return Ok((name.to_owned(), value.to_owned()));
+        }
+        match self.table.entries.get(index - STATIC_TABLE.len() - 1) {
+            Some(header) => Ok(header.clone()),
+            None => Err(Http2Error::compression("Header index out of range")),
+        }
+    }
+}
+
+/*
+ * 首部编码器，只使用静态表，不写入动态表，
+ * 因此不受对端SETTINGS_HEADER_TABLE_SIZE影响
+ */
+pub struct Encoder;
+
+impl Encoder {
+    pub fn encode(&self, headers: &[(&str, &str)], out: &mut Vec<u8>) {
+        for (name, value) in headers {
+            let mut name_index = 0;
+            let mut full_index = 0;
+            for (index, (n, v)) in STATIC_TABLE.iter().enumerate() {
+                if n == name {
+                    if name_index == 0 {
+                        name_index = index + 1;
+                    }
+                    if v == value {
+                        full_index = index + 1;
+                        break;
+                    }
+                }
+            }
+            if full_index > 0 {
+                encode_integer(full_index, 7, 0x80, out);
+            } else {
+                //不索引的字面量
+                encode_integer(name_index, 4, 0x00, out);
+                if name_index == 0 {
+                    encode_string(name, out);
+                }
+                encode_string(value, out);
+            }
+        }
+    }
+}
+
+fn decode_integer(block: &[u8], pos: &mut usize, prefix: u8) -> Result<usize, Http2Error> {
+    let max_prefix = (1usize << prefix) - 1;
+    let mut value = (block[*pos] as usize) & max_prefix;
+    *pos += 1;
+    if value < max_prefix {
+        return Ok(value);
+    }
+    let mut shift = 0;
+    loop {
+        let byte = *block
+            .get(*pos)
+            .ok_or_else(|| Http2Error::compression("Truncated integer"))?;
+        *pos += 1;
+        if shift > 28 {
+            return Err(Http2Error::compression("Integer overflow"));
+        }
+        value += ((byte & 0x7f) as usize) << shift;
+        shift += 7;
+        if byte & 0x80 == 0 {
+            return Ok(value);
+        }
+    }
+}
+
+fn decode_string(block: &[u8], pos: &mut usize) -> Result<String, Http2Error> {
+    if *pos >= block.len() {
+        return Err(Http2Error::compression("Truncated string"));
+    }
+    let huffman = block[*pos] & 0x80 != 0;
+    let len = decode_integer(block, pos, 7)?;
+    let end = pos
+        .checked_add(len)
+        .filter(|end| *end <= block.len())
+        .ok_or_else(|| Http2Error::compression("Truncated string"))?;
+    let data = &block[*pos..end];
+    *pos = end;
+    let data = if huffman {
+        huffman::decode(data)?
+    } else {
+        data.to_vec()
+    };
+    Ok(String::from_utf8_lossy(&data).into_owned())
+}
+
+fn encode_integer(mut value: usize, prefix: u8, flags: u8, out: &mut Vec<u8>) {
+    let max_prefix = (1usize << prefix) - 1;
+    if value < max_prefix {
+        out.push(flags | value as u8);
+        return;
+    }
+    out.push(flags | max_prefix as u8);
+    value -= max_prefix;
+    while value >= 0x80 {
+        out.push((value & 0x7f) as u8 | 0x80);
+        value >>= 7;
+    }
+    out.push(value as u8);
+}
+
+//Huffman编码更短时使用Huffman编码
+fn encode_string(value: &str, out: &mut Vec<u8>) {
+    let data = value.as_bytes();
+    let huffman_len = huffman::encoded_len(data);
+    if huffman_len < data.len() {
+        encode_integer(huffman_len, 7, 0x80, out);
+        huffman::encode(data, out);
+    } else {
+        encode_integer(data.len(), 7, 0x00, out);
+        out.extend_from_slice(data);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn unhex(hex: &str) -> Vec<u8> {
+        (0..hex.len())
+            .step_by(2)
+            .map(|i| u8::from_str_radix(&hex[i..i + 2], 16).unwrap())
+            .collect()
+    }
+
+    fn headers(list: &[(&str, &str)]) -> Vec<Header> {
+        list.iter()
+            .map(|(n, v)| (n.to_string(), v.to_string()))
+            .collect()
+    }
+
+    //RFC 7541 C.3 / C.4 请求示例（不使用与使用Huffman编码）
+    #[test]
+    fn test_rfc7541_requests() {
+        for blocks in [
+            [
+                "828684410f7777772e6578616d706c652e636f6d",
+                "828684be58086e6f2d6361636865",
+                "828785bf400a637573746f6d2d6b65790c637573746f6d2d76616c7565",
+            ],
+            [
+                "828684418cf1e3c2e5f23a6ba0ab90f4ff",
+                "828684be5886a8eb10649cbf",
+                "828785bf408825a849e95ba97d7f8925a849e95bb8e8b4bf",
+            ],
+        ] {
+            let mut decoder = Decoder::new();
+            let first = decoder.decode(&unhex(blocks[0])).unwrap();
+            assert_eq!(
+                first,
+                headers(&[
+                    (":method", "GET"),
+                    (":scheme", "http"),
+                    (":path", "/"),
+                    (":authority", "www.example.com"),
+                ])
+            );
+            assert_eq!(decoder.table.size, 57);
+            let second = decoder.decode(&unhex(blocks[1])).unwrap();
+            assert_eq!(second[4], ("cache-control".into(), "no-cache".into()));
+            assert_eq!(decoder.table.size, 110);
+            let third = decoder.decode(&unhex(blocks[2])).unwrap();
+            assert_eq!(
+                third,
+                headers(&[
+                    (":method", "GET"),
+                    (":scheme", "https"),
+                    (":path", "/index.html"),
+                    (":authority", "www.example.com"),
+                    ("custom-key", "custom-value"),
+                ])
+            );
+            assert_eq!(decoder.table.size, 164);
+        }
+    }
+
+    #[test]
+    fn test_table_size_update() {
+        let mut decoder = Decoder::new();
+        decoder
+            .decode(&unhex("828684410f7777772e6578616d706c652e636f6d"))
+            .unwrap();
+        //大小更新为0清空动态表
+        decoder.decode(&unhex("20")).unwrap();
+        assert!(decoder.table.entries.is_empty());
+        assert!(decoder.decode(&unhex("be")).is_err());
+        //首部之后的大小更新是错误的
+        assert!(decoder.decode(&unhex("8220")).is_err());
+    }
+
+    #[test]
+    fn test_encode_roundtrip() {
+        let list = [
+            (":status", "200"),
+            ("content-type", "text/html; charset=utf-8"),
+            ("content-length", "1234567"),
+            ("x-custom", "小站小记"),
+        ];
+        let mut out = Vec::new();
+        Encoder.encode(&list, &mut out);
+        assert_eq!(out[0], 0x88);
+        assert_eq!(Decoder::new().decode(&out).unwrap(), headers(&list));
+    }
+
+    #[test]
+    fn test_integer() {
+        //RFC 7541 C.1.2：1337使用5位前缀
+        let mut out = Vec::new();
+        encode_integer(1337, 5, 0, &mut out);
+        assert_eq!(out, vec![0x1f, 0x9a, 0x0a]);
+        let mut pos = 0;
+        assert_eq!(decode_integer(&out, &mut pos, 5).unwrap(), 1337);
+        assert_eq!(pos, 3);
+    }
+}
@@ -0,0 +1,885 @@
+mod frame;
+mod hpack;
+mod huffman;
+
+use std::{
+    collections::{HashMap, VecDeque},
+    io::{self, Write},
+    time::Instant,
+};
+
+use crate::{
+    context::ServerContext,
+    error::Http2Error,
+    hex,
+    http::{
+        failed_response, log_access, route_get, HttpStatus, RequestHeader, Response, Route, Sent,
+    },
+    log_error, log_info, shutdown,
+    stream::Connection,
+};
+use frame::*;
+use hpack::{Decoder, Encoder, Header};
+
+//客户端连接序言
+pub const PREFACE: &[u8] = b"PRI * HTTP/2.0\r\n\r\nSM\r\n\r\n";
+
+const SETTINGS_HEADER_TABLE_SIZE: u16 = 0x1;
+const SETTINGS_ENABLE_PUSH: u16 = 0x2;
+const SETTINGS_MAX_CONCURRENT_STREAMS: u16 = 0x3;
+const SETTINGS_INITIAL_WINDOW_SIZE: u16 = 0x4;
+const SETTINGS_MAX_FRAME_SIZE: u16 = 0x5;
+
+const REFUSED_STREAM: u32 = 0x7;
+
+const DEFAULT_WINDOW_SIZE: i64 = 65535;
+const MAX_WINDOW_SIZE: i64 = 0x7fff_ffff;
+const DEFAULT_MAX_FRAME_SIZE: usize = 16384;
+const MAX_CONCURRENT_STREAMS: usize = 100;
+
+#[derive(PartialEq)]
+enum StreamState {
+    Open,
+    //客户端已发送END_STREAM，等待响应
+    HalfClosedRemote,
+}
+
+struct Stream {
+    state: StreamState,
+    send_window: i64,
+    headers: Vec<Header>,
+}
+
+//正在接收的首部块（HEADERS之后可能跟随CONTINUATION）
+struct HeaderBlock {
+    stream_id: u32,
+    end_stream: bool,
+    data: Vec<u8>,
+}
+
+struct H2Connection<'a> {
+    stream: &'a mut dyn Connection,
+    ctx: &'a ServerContext,
+    decoder: Decoder,
+    encoder: Encoder,
+    peer_initial_window: i64,
+    peer_max_frame_size: usize,
+    //连接级发送窗口
+    send_window: i64,
+    streams: HashMap<u32, Stream>,
+    //已接收完整、等待响应的流
+    pending: VecDeque<u32>,
+    last_stream_id: u32,
+    header_block: Option<HeaderBlock>,
+    goaway: bool,
+    //发送响应体时读帧发生的连接错误
+    error: Option<Http2Error>,
+}
+
+/*
+ * 以prior-knowledge方式处理HTTP/2连接
+ * 调用前已读取序言的第一行"PRI * HTTP/2.0\r\n"
+ */
+pub fn serve_prior_knowledge(stream: &mut dyn Connection, ctx: &ServerContext) {
+    let mut rest = [0u8; 8];
+    if stream.read_exact(&mut rest).is_err() || rest != PREFACE[16..] {
+        log_error!("Invalid HTTP/2 connection preface");
+        return;
+    }
+    let mut connection = H2Connection::new(stream, ctx);
+    let result = connection.send_settings().and_then(|_| connection.run());
+    connection.finish(result);
+}
+
+/*
+ * 处理h2c升级：已读取的HTTP/1.1请求作为流1，响应101后切换到HTTP/2
+ */
+pub fn serve_upgrade(
+    stream: &mut dyn Connection,
+    ctx: &ServerContext,
+    settings: &str,
+    method: &str,
+    url: &str,
+    request_header: &RequestHeader,
+) {
+    let settings = match base64url_decode(settings) {
+        Some(settings) => settings,
+        None => {
+            log_error!("Invalid HTTP2-Settings header");
+            return;
+        }
+    };
+    let switching =
+        b"HTTP/1.1 101 Switching Protocols\r\nConnection: Upgrade\r\nUpgrade: h2c\r\n\r\n";
+    if let Err(e) = stream.write_all(switching) {
+        log_error!("Response 101 failed. Error reason: {}", e);
+        return;
+    }
+    let mut headers: Vec<Header> = vec![
+        (":method".to_owned(), method.to_uppercase()),
+        (":path".to_owned(), url.to_owned()),
+    ];
+    for (name, value) in request_header.params.iter() {
+        headers.push((name.clone(), value.clone()));
+    }
+    let mut connection = H2Connection::new(stream, ctx);
+    connection.streams.insert(
+        1,
+        Stream {
+            state: StreamState::HalfClosedRemote,
+            send_window: DEFAULT_WINDOW_SIZE,
+            headers,
+        },
+    );
+    connection.last_stream_id = 1;
+    connection.pending.push_back(1);
+    let result = connection
+        .send_settings()
+        .and_then(|_| connection.read_preface())
+        .and_then(|_| connection.apply_settings(&settings))
+        .and_then(|_| connection.run());
+    connection.finish(result);
+}
+
+impl<'a> H2Connection<'a> {
+    fn new(stream: &'a mut dyn Connection, ctx: &'a ServerContext) -> H2Connection<'a> {
+        H2Connection {
+            stream,
+            ctx,
+            decoder: Decoder::new(),
+            encoder: Encoder,
+            peer_initial_window: DEFAULT_WINDOW_SIZE,
+            peer_max_frame_size: DEFAULT_MAX_FRAME_SIZE,
+            send_window: DEFAULT_WINDOW_SIZE,
+            streams: HashMap::new(),
+            pending: VecDeque::new(),
+            last_stream_id: 0,
+            header_block: None,
+            goaway: false,
+            error: None,
+        }
+    }
+
+    fn run(&mut self) -> Result<(), Http2Error> {
+        loop {
+            while let Some(stream_id) = self.pending.pop_front() {
+                self.respond(stream_id)?;
+            }
+            if self.goaway {
+                return Ok(());
+            }
+            //没有进行中的流时为空闲连接，优雅关闭时可直接结束
+            let idle = self.streams.is_empty();
+            if idle {
+                shutdown::idle(true);
+            }
+            let frame = Frame::read(self.stream, DEFAULT_MAX_FRAME_SIZE);
+            if idle {
+                shutdown::idle(false);
+            }
+            match frame? {
+                Some(frame) => self.handle_frame(frame)?,
+                None => return Ok(()),
+            }
+        }
+    }
+
+    //连接结束，出错时发送GOAWAY
+    fn finish(&mut self, result: Result<(), Http2Error>) {
+        let code = match &result {
+            Ok(()) => Http2Error::NO_ERROR,
+            Err(e) => {
+                log_error!("HTTP/2 connection error: {}", e);
+                e.code
+            }
+        };
+        let mut payload = self.last_stream_id.to_be_bytes().to_vec();
+        payload.extend_from_slice(&code.to_be_bytes());
+        if let Err(e) = self.write_frame(Frame::new(GOAWAY, 0, 0, payload)) {
+            log_error!("Failed to send GOAWAY. Error reason: {}", e);
+        }
+    }
+
+    fn read_preface(&mut self) -> Result<(), Http2Error> {
+        let mut preface = [0u8; 24];
+        self.stream.read_exact(&mut preface)?;
+        if preface != PREFACE {
+            return Err(Http2Error::protocol("Invalid connection preface"));
+        }
+        Ok(())
+    }
+
+    fn write_frame(&mut self, frame: Frame) -> Result<(), Http2Error> {
+        frame.write(self.stream)?;
+        self.stream.flush()?;
+        Ok(())
+    }
+
+    fn send_settings(&mut self) -> Result<(), Http2Error> {
+        let mut payload = Vec::new();
+        for (id, value) in [
+            (
+                SETTINGS_MAX_CONCURRENT_STREAMS,
+                MAX_CONCURRENT_STREAMS as u32,
+            ),
+            (SETTINGS_ENABLE_PUSH, 0),
+        ] {
+            payload.extend_from_slice(&id.to_be_bytes());
+            payload.extend_from_slice(&value.to_be_bytes());
+        }
+        self.write_frame(Frame::new(SETTINGS, 0, 0, payload))
+    }
+
+    fn handle_frame(&mut self, mut frame: Frame) -> Result<(), Http2Error> {
+        //首部块未结束时只允许同一流的CONTINUATION
+        if let Some(block) = &self.header_block {
+            if frame.kind != CONTINUATION || frame.stream_id != block.stream_id {
+                return Err(Http2Error::protocol("Expected CONTINUATION frame"));
+            }
+        }
+        match frame.kind {
+            DATA => self.handle_data(frame),
+            HEADERS => {
+                let stream_id = frame.stream_id;
+                if stream_id.is_multiple_of(2) || stream_id <= self.last_stream_id {
+                    return Err(Http2Error::protocol("Invalid stream id"));
+                }
+                self.last_stream_id = stream_id;
+                frame.strip_padding_and_priority()?;
+                self.header_block = Some(HeaderBlock {
+                    stream_id,
+                    end_stream: frame.has_flag(FLAG_END_STREAM),
+                    data: frame.payload,
+                });
+                if frame.flags & FLAG_END_HEADERS != 0 {
+                    self.end_headers()?;
+                }
+                Ok(())
+            }
+            CONTINUATION => match &mut self.header_block {
+                Some(block) => {
+                    block.data.extend_from_slice(&frame.payload);
+                    if frame.has_flag(FLAG_END_HEADERS) {
+                        self.end_headers()?;
+                    }
+                    Ok(())
+                }
+                None => Err(Http2Error::protocol("Unexpected CONTINUATION frame")),
+            },
+            PRIORITY => {
+                if frame.payload.len() != 5 {
+                    return Err(Http2Error::new(
+                        Http2Error::FRAME_SIZE_ERROR,
+                        "Invalid PRIORITY frame",
+                    ));
+                }
+                Ok(())
+            }
+            RST_STREAM => {
+                if frame.stream_id == 0 || frame.payload.len() != 4 {
+                    return Err(Http2Error::protocol("Invalid RST_STREAM frame"));
+                }
+                self.streams.remove(&frame.stream_id);
+                self.pending.retain(|id| *id != frame.stream_id);
+                Ok(())
+            }
+            SETTINGS => {
+                if frame.stream_id != 0 {
+                    return Err(Http2Error::protocol("SETTINGS on a stream"));
+                }
+                if frame.has_flag(FLAG_ACK) {
+                    if !frame.payload.is_empty() {
+                        return Err(Http2Error::new(
+                            Http2Error::FRAME_SIZE_ERROR,
+                            "SETTINGS ACK with payload",
+                        ));
+                    }
+                    return Ok(());
+                }
+                self.apply_settings(&frame.payload)?;
+                self.write_frame(Frame::new(SETTINGS, FLAG_ACK, 0, Vec::new()))
+            }
+            PING => {
+                if frame.stream_id != 0 || frame.payload.len() != 8 {
+                    return Err(Http2Error::protocol("Invalid PING frame"));
+                }
+                if frame.has_flag(FLAG_ACK) {
+                    return Ok(());
+                }
+                self.write_frame(Frame::new(PING, FLAG_ACK, 0, frame.payload))
+            }
+            GOAWAY => {
+                self.goaway = true;
+                Ok(())
+            }
+            WINDOW_UPDATE => self.handle_window_update(frame),
+            PUSH_PROMISE => Err(Http2Error::protocol("Client sent PUSH_PROMISE")),
+            //未知帧类型直接忽略
+            _ => Ok(()),
+        }
+    }
+
+    //首部块接收完成，创建流
+    fn end_headers(&mut self) -> Result<(), Http2Error> {
+        let block = match self.header_block.take() {
+            Some(block) => block,
+            None => return Ok(()),
+        };
+        //即使拒绝该流也必须解码，以保持动态表同步
+        let headers = self.decoder.decode(&block.data)?;
+        if self.streams.len() >= MAX_CONCURRENT_STREAMS {
+            return self.reset_stream(block.stream_id, REFUSED_STREAM);
+        }
+        let state = if block.end_stream {
+            self.pending.push_back(block.stream_id);
+            StreamState::HalfClosedRemote
+        } else {
+            StreamState::Open
+        };
+        self.streams.insert(
+            block.stream_id,
+            Stream {
+                state,
+                send_window: self.peer_initial_window,
+                headers,
+            },
+        );
+        Ok(())
+    }
+
+    fn handle_data(&mut self, mut frame: Frame) -> Result<(), Http2Error> {
+        if frame.stream_id == 0 {
+            return Err(Http2Error::protocol("DATA on stream 0"));
+        }
+        //流量控制按包含填充的完整长度计算，立即归还窗口
+        let len = frame.payload.len() as u32;
+        frame.strip_padding_and_priority()?;
+        if len > 0 {
+            self.write_frame(Frame::new(WINDOW_UPDATE, 0, 0, len.to_be_bytes().to_vec()))?;
+        }
+        let open = matches!(
+            self.streams.get(&frame.stream_id),
+            Some(stream) if stream.state == StreamState::Open
+        );
+        if !open {
+            return self.reset_stream(frame.stream_id, Http2Error::STREAM_CLOSED);
+        }
+        if frame.has_flag(FLAG_END_STREAM) {
+            if let Some(stream) = self.streams.get_mut(&frame.stream_id) {
+                stream.state = StreamState::HalfClosedRemote;
+            }
+            self.pending.push_back(frame.stream_id);
+        } else if len > 0 {
+            self.write_frame(Frame::new(
+                WINDOW_UPDATE,
+                0,
+                frame.stream_id,
+                len.to_be_bytes().to_vec(),
+            ))?;
+        }
+        Ok(())
+    }
+
+    fn handle_window_update(&mut self, frame: Frame) -> Result<(), Http2Error> {
+        if frame.payload.len() != 4 {
+            return Err(Http2Error::new(
+                Http2Error::FRAME_SIZE_ERROR,
+                "Invalid WINDOW_UPDATE frame",
+            ));
+        }
+        let increment = (u32::from_be_bytes([
+            frame.payload[0],
+            frame.payload[1],
+            frame.payload[2],
+            frame.payload[3],
+        ]) & 0x7fff_ffff) as i64;
+        if frame.stream_id == 0 {
+            if increment == 0 {
+                return Err(Http2Error::protocol("WINDOW_UPDATE increment 0"));
+            }
+            self.send_window += increment;
+            if self.send_window > MAX_WINDOW_SIZE {
+                return Err(Http2Error::new(
+                    Http2Error::FLOW_CONTROL_ERROR,
+                    "Connection window overflow",
+                ));
+            }
+            return Ok(());
+        }
+        let overflow = match self.streams.get_mut(&frame.stream_id) {
+            Some(stream) => {
+                stream.send_window += increment;
+                increment == 0 || stream.send_window > MAX_WINDOW_SIZE
+            }
+            //已关闭的流忽略
+            None => false,
+        };
+        if overflow {
+            let code = if increment == 0 {
+                Http2Error::PROTOCOL_ERROR
+            } else {
+                Http2Error::FLOW_CONTROL_ERROR
+            };
+            return self.reset_stream(frame.stream_id, code);
+        }
+        Ok(())
+    }
+
+    fn apply_settings(&mut self, payload: &[u8]) -> Result<(), Http2Error> {
+        if !payload.len().is_multiple_of(6) {
+            return Err(Http2Error::new(
+                Http2Error::FRAME_SIZE_ERROR,
+                "Invalid SETTINGS length",
+            ));
+        }
+        for setting in payload.chunks(6) {
+            let id = u16::from_be_bytes([setting[0], setting[1]]);
+            let value = u32::from_be_bytes([setting[2], setting[3], setting[4], setting[5]]);
+            match id {
+                SETTINGS_ENABLE_PUSH if value > 1 => {
+                    return Err(Http2Error::protocol("Invalid SETTINGS_ENABLE_PUSH"));
+                }
+                SETTINGS_INITIAL_WINDOW_SIZE => {
+                    let value = value as i64;
+                    if value > MAX_WINDOW_SIZE {
+                        return Err(Http2Error::new(
+                            Http2Error::FLOW_CONTROL_ERROR,
+                            "Invalid SETTINGS_INITIAL_WINDOW_SIZE",
+                        ));
+                    }
+                    //调整所有流的发送窗口
+                    let delta = value - self.peer_initial_window;
+                    for stream in self.streams.values_mut() {
+                        stream.send_window += delta;
+                    }
+                    self.peer_initial_window = value;
+                }
+                SETTINGS_MAX_FRAME_SIZE => {
+                    if !(16384..=16_777_215).contains(&value) {
+                        return Err(Http2Error::protocol("Invalid SETTINGS_MAX_FRAME_SIZE"));
+                    }
+                    self.peer_max_frame_size = value as usize;
+                }
+                //编码器不使用动态表，无需处理SETTINGS_HEADER_TABLE_SIZE
+                SETTINGS_HEADER_TABLE_SIZE => {}
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+
+    fn reset_stream(&mut self, stream_id: u32, code: u32) -> Result<(), Http2Error> {
+        self.streams.remove(&stream_id);
+        self.pending.retain(|id| *id != stream_id);
+        self.write_frame(Frame::new(
+            RST_STREAM,
+            0,
+            stream_id,
+            code.to_be_bytes().to_vec(),
+        ))
+    }
+
+    //将流映射到与HTTP/1相同的静态资源处理
+    fn respond(&mut self, stream_id: u32) -> Result<(), Http2Error> {
+        let start = Instant::now();
+        let headers = match self.streams.get_mut(&stream_id) {
+            Some(stream) => std::mem::take(&mut stream.headers),
+            None => return Ok(()),
+        };
+        let mut method = String::new();
+        let mut path = String::new();
+        let mut request_header = RequestHeader {
+            params: HashMap::new(),
+        };
+        for (name, value) in headers {
+            match name.as_str() {
+                ":method" => method = value,
+                ":path" => path = value,
+                ":authority" => {
+                    request_header.params.insert("host".to_owned(), value);
+                }
+                ":scheme" => {}
+                _ => {
+                    request_header.params.insert(name, value);
+                }
+            }
+        }
+        log_info!("HTTP/2 stream {}: {} {}", stream_id, method, path);
+        let client = request_header
+            .client_addr(&self.stream.peer_addr(), &self.ctx.config().trusted_proxies);
+        let request = format!("{} {} HTTP/2.0", method, path);
+        let head = method.eq_ignore_ascii_case("head");
+        if !method.eq_ignore_ascii_case("get") && !head {
+            log_error!("Do not support request type! Request type: {}", method);
+            self.send_headers(stream_id, &[(":status", "405")], true)?;
+            self.streams.remove(&stream_id);
+            let sent = Sent {
+                status: 405,
+                body_bytes: 0,
+                bytes: 0,
+            };
+            log_access(
+                self.ctx,
+                &client,
+                &request,
+                &request_header,
+                Some(sent),
+                start,
+            );
+            return Ok(());
+        }
+        let url = hex::url_decoding(path);
+        let response = match route_get(self.ctx, &request_header, url) {
+            Ok(Route::Response(response)) => response,
+            Ok(Route::EventStream) => {
+                log_error!("Event streams are only served over HTTP/1.x");
+                failed_response(self.ctx, HttpStatus::NotFound)?
+            }
+            Err(e) => {
+                log_error!("The GET request is abnormal. Error reason: {}", e);
+                failed_response(self.ctx, HttpStatus::InternalServerError)?
+            }
+        };
+        //HTTP/2首部经过HPACK压缩，发送字节数只计算响应体
+        let body_bytes = match head {
+            true => 0,
+            false => response.body.len().map_or(0, |len| len as u64),
+        };
+        let sent = Sent {
+            status: response.http_status.code(),
+            body_bytes,
+            bytes: body_bytes,
+        };
+        let result = self.send_response(stream_id, response, head);
+        log_access(
+            self.ctx,
+            &client,
+            &request,
+            &request_header,
+            result.is_ok().then_some(sent),
+            start,
+        );
+        result?;
+        self.streams.remove(&stream_id);
+        Ok(())
+    }
+
+    fn send_response(
+        &mut self,
+        stream_id: u32,
+        response: Response,
+        head: bool,
+    ) -> Result<(), Http2Error> {
+        let status = response.http_status.code().to_string();
+        let params: Vec<(String, String)> = response
+            .params
+            .iter()
+            .map(|(name, value)| (name.to_lowercase(), value.clone()))
+            .collect();
+        let mut headers: Vec<(&str, &str)> = vec![(":status", &status)];
+        headers.extend(params.iter().map(|(n, v)| (n.as_str(), v.as_str())));
+        let empty = head || response.body.len()? == 0;
+        self.send_headers(stream_id, &headers, empty)?;
+        if empty {
+            return Ok(());
+        }
+        let mut writer = DataWriter {
+            connection: self,
+            stream_id,
+        };
+        if let Err(e) = response.body.write_in_connect(&mut writer) {
+            //连接级错误需要终止连接，流被重置时只放弃该响应
+            return match self.error.take() {
+                Some(error) => Err(error),
+                None if self.streams.contains_key(&stream_id) => Err(e.into()),
+                None => Ok(()),
+            };
+        }
+        self.write_frame(Frame::new(DATA, FLAG_END_STREAM, stream_id, Vec::new()))
+    }
+
+    //首部块超过对端帧大小时拆分为CONTINUATION
+    fn send_headers(
+        &mut self,
+        stream_id: u32,
+        headers: &[(&str, &str)],
+        end_stream: bool,
+    ) -> Result<(), Http2Error> {
+        let mut block = Vec::new();
+        self.encoder.encode(headers, &mut block);
+        let mut chunks = block.chunks(self.peer_max_frame_size).peekable();
+        let mut kind = HEADERS;
+        let mut flags = if end_stream { FLAG_END_STREAM } else { 0 };
+        while let Some(chunk) = chunks.next() {
+            if chunks.peek().is_none() {
+                flags |= FLAG_END_HEADERS;
+            }
+            Frame::new(kind, flags, stream_id, chunk.to_vec()).write(self.stream)?;
+            kind = CONTINUATION;
+            flags = 0;
+        }
+        if block.is_empty() {
+            Frame::new(HEADERS, flags | FLAG_END_HEADERS, stream_id, Vec::new())
+                .write(self.stream)?;
+        }
+        self.stream.flush()?;
+        Ok(())
+    }
+}
+
+/*
+ * 将响应体按流量控制窗口与帧大小切分为DATA帧
+ */
+struct DataWriter<'c, 'a> {
+    connection: &'c mut H2Connection<'a>,
+    stream_id: u32,
+}
+
+impl Write for DataWriter<'_, '_> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        loop {
+            let stream_window = match self.connection.streams.get(&self.stream_id) {
+                Some(stream) => stream.send_window,
+                None => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::ConnectionReset,
+                        "Stream reset by peer",
+                    ))
+                }
+            };
+            let window = stream_window.min(self.connection.send_window);
+            if window > 0 {
+                let len = buf
+                    .len()
+                    .min(window as usize)
+                    .min(self.connection.peer_max_frame_size);
+                Frame::new(DATA, 0, self.stream_id, buf[..len].to_vec())
+                    .write(self.connection.stream)?;
+                self.connection.send_window -= len as i64;
+                if let Some(stream) = self.connection.streams.get_mut(&self.stream_id) {
+                    stream.send_window -= len as i64;
+                }
+                return Ok(len);
+            }
+            //窗口耗尽，继续处理对端的帧直到收到WINDOW_UPDATE
+            self.connection.stream.flush()?;
+            let result = match Frame::read(self.connection.stream, DEFAULT_MAX_FRAME_SIZE) {
+                Ok(Some(frame)) => self.connection.handle_frame(frame),
+                Ok(None) => Err(Http2Error::protocol("Connection closed")),
+                Err(e) => Err(e),
+            };
+            if let Err(e) = result {
+                let message = e.message.clone();
+                self.connection.error = Some(e);
+                return Err(io::Error::other(message));
+            }
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.connection.stream.flush()
+    }
+}
+
+//解码HTTP2-Settings首部（base64url，无填充）
+fn base64url_decode(value: &str) -> Option<Vec<u8>> {
+    let mut out = Vec::new();
+    let mut acc: u32 = 0;
+    let mut bits = 0;
+    for c in value.trim().trim_end_matches('=').bytes() {
+        let v = match c {
+            b'A'..=b'Z' => c - b'A',
+            b'a'..=b'z' => c - b'a' + 26,
+            b'0'..=b'9' => c - b'0' + 52,
+            b'-' | b'+' => 62,
+            b'_' | b'/' => 63,
+            _ => return None,
+        };
+        acc = (acc << 6) | v as u32;
+        bits += 6;
+        if bits >= 8 {
+            bits -= 8;
+            out.push((acc >> bits) as u8);
+        }
+    }
+    Some(out)
+}
+
+#[cfg(test)]
+mod test {
+    use std::{
+        io::{Read, Write},
+        net::{TcpListener, TcpStream},
+        thread,
+    };
+
+    use super::{frame::*, hpack::Decoder, *};
+    use crate::http::handle_connect;
+
+    //启动只处理一个连接的服务端
+    fn start_server() -> TcpStream {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            handle_connect(stream, &ServerContext::load());
+        });
+        TcpStream::connect(addr).unwrap()
+    }
+
+    fn send(client: &mut TcpStream, kind: u8, flags: u8, stream_id: u32, payload: Vec<u8>) {
+        Frame::new(kind, flags, stream_id, payload)
+            .write(client)
+            .unwrap();
+    }
+
+    fn get_request(client: &mut TcpStream, stream_id: u32, path: &str) {
+        let mut block = Vec::new();
+        Encoder.encode(
+            &[
+                (":method", "GET"),
+                (":scheme", "http"),
+                (":path", path),
+                (":authority", "localhost"),
+            ],
+            &mut block,
+        );
+        send(
+            client,
+            HEADERS,
+            FLAG_END_HEADERS | FLAG_END_STREAM,
+            stream_id,
+            block,
+        );
+    }
+
+    //读取帧直到指定流结束，返回状态码与响应体，期间收到的ACK帧类型记入acks
+    fn read_response(
+        client: &mut TcpStream,
+        decoder: &mut Decoder,
+        stream_id: u32,
+        acks: &mut Vec<u8>,
+    ) -> (String, Vec<u8>) {
+        let mut status = String::new();
+        let mut body = Vec::new();
+        loop {
+            let frame = Frame::read(client, 1 << 20).unwrap().unwrap();
+            if frame.stream_id == 0 && frame.has_flag(FLAG_ACK) {
+                acks.push(frame.kind);
+            }
+            if frame.stream_id != stream_id {
+                continue;
+            }
+            match frame.kind {
+                HEADERS => {
+                    let headers = decoder.decode(&frame.payload).unwrap();
+                    status = headers[0].1.clone();
+                }
+                DATA => body.extend_from_slice(&frame.payload),
+                kind => panic!("unexpected frame {}", kind),
+            }
+            if frame.has_flag(FLAG_END_STREAM) {
+                return (status, body);
+            }
+        }
+    }
+
+    #[test]
+    fn test_prior_knowledge() {
+        let mut client = start_server();
+        client.write_all(PREFACE).unwrap();
+        send(&mut client, SETTINGS, 0, 0, Vec::new());
+        let settings = Frame::read(&mut client, 1 << 20).unwrap().unwrap();
+        assert_eq!(settings.kind, SETTINGS);
+        get_request(&mut client, 1, "/");
+        get_request(&mut client, 3, "/not-found.html");
+        send(&mut client, PING, 0, 0, vec![1, 2, 3, 4, 5, 6, 7, 8]);
+        let mut decoder = Decoder::new();
+        let mut acks = Vec::new();
+        let (status, body) = read_response(&mut client, &mut decoder, 1, &mut acks);
+        assert_eq!(status, "200");
+        assert!(String::from_utf8(body).unwrap().contains("<!DOCTYPE html>"));
+        let (status, _) = read_response(&mut client, &mut decoder, 3, &mut acks);
+        assert_eq!(status, "404");
+        //PING应答与SETTINGS确认
+        while acks.len() < 2 {
+            let frame = Frame::read(&mut client, 1 << 20).unwrap().unwrap();
+            if frame.has_flag(FLAG_ACK) {
+                acks.push(frame.kind);
+            }
+        }
+        assert!(acks.contains(&PING) && acks.contains(&SETTINGS));
+        send(&mut client, GOAWAY, 0, 0, vec![0; 8]);
+        let goaway = Frame::read(&mut client, 1 << 20).unwrap().unwrap();
+        assert_eq!(goaway.kind, GOAWAY);
+    }
+
+    #[test]
+    fn test_flow_control() {
+        let mut client = start_server();
+        client.write_all(PREFACE).unwrap();
+        //初始窗口只有10字节
+        let mut settings = SETTINGS_INITIAL_WINDOW_SIZE.to_be_bytes().to_vec();
+        settings.extend_from_slice(&10u32.to_be_bytes());
+        send(&mut client, SETTINGS, 0, 0, settings);
+        get_request(&mut client, 1, "/");
+        let mut received = 0;
+        loop {
+            let frame = Frame::read(&mut client, 1 << 20).unwrap().unwrap();
+            if frame.kind == DATA {
+                received += frame.payload.len();
+                assert!(received <= 10);
+                if received == 10 {
+                    break;
+                }
+            }
+        }
+        send(
+            &mut client,
+            WINDOW_UPDATE,
+            0,
+            1,
+            1000u32.to_be_bytes().to_vec(),
+        );
+        let mut decoder = Decoder::new();
+        let (_, rest) = read_response(&mut client, &mut decoder, 1, &mut Vec::new());
+        assert!(!rest.is_empty());
+    }
+
+    #[test]
+    fn test_h2c_upgrade() {
+        let mut client = start_server();
+        client
+            .write_all(
+                b"GET / HTTP/1.1\r\nHost: localhost\r\nConnection: Upgrade, HTTP2-Settings\r\nUpgrade: h2c\r\nHTTP2-Settings: AAMAAABkAAQAAP__\r\n\r\n",
+            )
+            .unwrap();
+        let mut switching = [0u8; 34];
+        client.read_exact(&mut switching).unwrap();
+        assert!(switching.starts_with(b"HTTP/1.1 101 Switching Protocols"));
+        let mut rest = Vec::new();
+        while !rest.ends_with(b"\r\n\r\n") {
+            let mut byte = [0u8; 1];
+            client.read_exact(&mut byte).unwrap();
+            rest.push(byte[0]);
+        }
+        client.write_all(PREFACE).unwrap();
+        send(&mut client, SETTINGS, 0, 0, Vec::new());
+        let mut decoder = Decoder::new();
+        let (status, body) = read_response(&mut client, &mut decoder, 1, &mut Vec::new());
+        assert_eq!(status, "200");
+        assert!(!body.is_empty());
+    }
+
+    #[test]
+    fn test_base64url_decode() {
+        assert_eq!(
+            base64url_decode("AAMAAABkAAQAAP__").unwrap(),
+            vec![0, 3, 0, 0, 0, 100, 0, 4, 0, 0, 255, 255]
+        );
+        assert!(base64url_decode("!!").is_none());
+    }
+}
@@ -0,0 +1,346 @@
+use std::{
+    fs::{self, Permissions},
+    io::{self, ErrorKind},
+    net::{SocketAddr, TcpListener},
+    os::{
+        fd::{AsRawFd, RawFd},
+        unix::{
+            fs::{FileTypeExt, PermissionsExt},
+            net::{UnixListener, UnixStream},
+        },
+    },
+    path::Path,
+};
+
+use crate::{
+    config::{ListenAddr, ListenConfig},
+    log_info,
+    stream::Stream,
+};
+
+//监听套接字：TCP或Unix套接字
+#[derive(Debug)]
+pub enum Listener {
+    Tcp(TcpListener),
+    Unix(UnixListener),
+}
+
+impl Listener {
+    pub fn accept(&self) -> io::Result<Stream> {
+        match self {
+            Listener::Tcp(listener) => listener.accept().map(|(stream, _)| Stream::Tcp(stream)),
+            Listener::Unix(listener) => listener.accept().map(|(stream, _)| Stream::Unix(stream)),
+        }
+    }
+
+    pub fn set_nonblocking(&self, nonblocking: bool) -> io::Result<()> {
+        match self {
+            Listener::Tcp(listener) => listener.set_nonblocking(nonblocking),
+            Listener::Unix(listener) => listener.set_nonblocking(nonblocking),
+        }
+    }
+
+    //监听的地址，未命名的Unix套接字返回None
+    pub fn local_addr(&self) -> Option<ListenAddr> {
+        match self {
+            Listener::Tcp(listener) => listener.local_addr().ok().map(ListenAddr::Tcp),
+            Listener::Unix(listener) => listener
+                .local_addr()
+                .ok()?
+                .as_pathname()
+                .map(|path| ListenAddr::Unix(path.to_owned())),
+        }
+    }
+}
+
+impl AsRawFd for Listener {
+    fn as_raw_fd(&self) -> RawFd {
+        match self {
+            Listener::Tcp(listener) => listener.as_raw_fd(),
+            Listener::Unix(listener) => listener.as_raw_fd(),
+        }
+    }
+}
+
+//按监听地址的类型创建套接字
+pub fn bind(config: &ListenConfig) -> io::Result<Listener> {
+    match &config.addr {
+        ListenAddr::Tcp(addr) => bind_tcp(*addr, config).map(Listener::Tcp),
+        ListenAddr::Unix(path) => bind_unix(path, config).map(Listener::Unix),
+    }
+}
+
+/*
+ * 套接字文件已存在时：能连接说明有其他进程在监听，返回错误；
+ * 连接被拒绝说明是上次异常退出遗留的文件，删除后重新绑定；不是套接字文件时不删除
+ */
+fn bind_unix(path: &Path, config: &ListenConfig) -> io::Result<UnixListener> {
+    match fs::symlink_metadata(path) {
+        Ok(metadata) if metadata.file_type().is_socket() => {
+            //只有连接被拒绝才说明没有进程在监听，其它错误（如权限不足）不能删除
+            match UnixStream::connect(path) {
+                Ok(_) => {
+                    return Err(io::Error::new(
+                        ErrorKind::AddrInUse,
+                        format!("{} is in use by another process", path.display()),
+                    ))
+                }
+                Err(err) if err.kind() == ErrorKind::ConnectionRefused => {
+                    fs::remove_file(path)?;
+                    log_info!("Removed stale socket {}", path.display());
+                }
+                Err(err) => {
+                    return Err(io::Error::new(
+                        err.kind(),
+                        format!("Failed to check socket {}: {}", path.display(), err),
+                    ))
+                }
+            }
+        }
+        Ok(_) => {
+            return Err(io::Error::new(
+                ErrorKind::AlreadyExists,
+                format!("{} exists and is not a socket", path.display()),
+            ))
+        }
+        Err(_) => {}
+    }
+    let listener = UnixListener::bind(path)?;
+    if let Some(mode) = config.mode {
+        fs::set_permissions(path, Permissions::from_mode(mode))?;
+    }
+    //再次调用listen可修改等待队列长度
+    #[cfg(target_os = "linux")]
+    check(unsafe { sys::listen(listener.as_raw_fd(), config.backlog) })?;
+    Ok(listener)
+}
+
+#[cfg(target_os = "linux")]
+mod sys {
+    use std::ffi::c_void;
+
+    pub const AF_INET: i32 = 2;
+    pub const AF_INET6: i32 = 10;
+    pub const SOCK_STREAM: i32 = 1;
+    pub const SOCK_CLOEXEC: i32 = 0o2000000;
+    pub const SOL_SOCKET: i32 = 1;
+    pub const SO_REUSEADDR: i32 = 2;
+    pub const SO_REUSEPORT: i32 = 15;
+    pub const IPPROTO_TCP: i32 = 6;
+    pub const TCP_NODELAY: i32 = 1;
+    pub const IPPROTO_IPV6: i32 = 41;
+    pub const IPV6_V6ONLY: i32 = 26;
+
+    //端口与地址均为网络字节序
+    #[repr(C)]
+    pub struct SockaddrIn {
+        pub sin_family: u16,
+        pub sin_port: u16,
+        pub sin_addr: [u8; 4],
+        pub sin_zero: [u8; 8],
+    }
+
+    #[repr(C)]
+    pub struct SockaddrIn6 {
+        pub sin6_family: u16,
+        pub sin6_port: u16,
+        pub sin6_flowinfo: u32,
+        pub sin6_addr: [u8; 16],
+        pub sin6_scope_id: u32,
+    }
+
+    extern "C" {
+        pub fn socket(domain: i32, ty: i32, protocol: i32) -> i32;
+        pub fn setsockopt(fd: i32, level: i32, name: i32, value: *const c_void, len: u32) -> i32;
+        pub fn bind(fd: i32, addr: *const c_void, len: u32) -> i32;
+        pub fn listen(fd: i32, backlog: i32) -> i32;
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn check(result: i32) -> io::Result<i32> {
+    if result < 0 {
+        Err(io::Error::last_os_error())
+    } else {
+        Ok(result)
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn set_option(fd: i32, level: i32, name: i32, value: bool) -> io::Result<()> {
+    let value = value as i32;
+    check(unsafe {
+        sys::setsockopt(
+            fd,
+            level,
+            name,
+            &value as *const i32 as *const _,
+            std::mem::size_of::<i32>() as u32,
+        )
+    })?;
+    Ok(())
+}
+
+/*
+ * 按监听配置创建套接字：绑定前设置SO_REUSEPORT与IPV6_V6ONLY，再以指定的backlog监听
+ * TCP_NODELAY设置在监听套接字上，由accept得到的连接继承
+ */
+#[cfg(target_os = "linux")]
+fn bind_tcp(addr: SocketAddr, config: &ListenConfig) -> io::Result<TcpListener> {
+    use std::os::fd::{FromRawFd, OwnedFd};
+
+    let domain = match addr {
+        SocketAddr::V4(_) => sys::AF_INET,
+        SocketAddr::V6(_) => sys::AF_INET6,
+    };
+    let fd = check(unsafe { sys::socket(domain, sys::SOCK_STREAM | sys::SOCK_CLOEXEC, 0) })?;
+    //出错返回时自动关闭
+    let socket = unsafe { OwnedFd::from_raw_fd(fd) };
+    set_option(fd, sys::SOL_SOCKET, sys::SO_REUSEADDR, true)?;
+    if config.reuse_port {
+        set_option(fd, sys::SOL_SOCKET, sys::SO_REUSEPORT, true)?;
+    }
+    if config.tcp_nodelay {
+        set_option(fd, sys::IPPROTO_TCP, sys::TCP_NODELAY, true)?;
+    }
+    let result = match addr {
+        SocketAddr::V4(addr) => {
+            let sockaddr = sys::SockaddrIn {
+                sin_family: sys::AF_INET as u16,
+                sin_port: addr.port().to_be(),
+                sin_addr: addr.ip().octets(),
+                sin_zero: [0; 8],
+            };
+            unsafe {
+                sys::bind(
+                    fd,
+                    &sockaddr as *const sys::SockaddrIn as *const _,
+                    std::mem::size_of::<sys::SockaddrIn>() as u32,
+                )
+            }
+        }
+        SocketAddr::V6(addr) => {
+            //不依赖系统的net.ipv6.bindv6only默认值
+            set_option(fd, sys::IPPROTO_IPV6, sys::IPV6_V6ONLY, config.ipv6_only)?;
+            let sockaddr = sys::SockaddrIn6 {
+                sin6_family: sys::AF_INET6 as u16,
+                sin6_port: addr.port().to_be(),
+                sin6_flowinfo: addr.flowinfo(),
+                sin6_addr: addr.ip().octets(),
+                sin6_scope_id: addr.scope_id(),
+            };
+            unsafe {
+                sys::bind(
+                    fd,
+                    &sockaddr as *const sys::SockaddrIn6 as *const _,
+                    std::mem::size_of::<sys::SockaddrIn6>() as u32,
+                )
+            }
+        }
+    };
+    check(result)?;
+    check(unsafe { sys::listen(socket.as_raw_fd(), config.backlog) })?;
+    Ok(TcpListener::from(socket))
+}
+
+//其他平台使用标准库默认的监听参数，无法应用的选项已由Config::check拒绝
+#[cfg(not(target_os = "linux"))]
+fn bind_tcp(addr: SocketAddr, _config: &ListenConfig) -> io::Result<TcpListener> {
+    TcpListener::bind(addr)
+}
+
+#[cfg(test)]
+#[cfg(target_os = "linux")]
+mod test {
+    use std::{env, net::TcpStream, thread, time::Duration};
+
+    use super::*;
+
+    fn tcp(addr: &str) -> ListenConfig {
+        ListenConfig::new(ListenAddr::Tcp(addr.parse().unwrap()))
+    }
+
+    fn port(listener: &Listener) -> u16 {
+        match listener.local_addr() {
+            Some(ListenAddr::Tcp(addr)) => addr.port(),
+            addr => panic!("unexpected address {:?}", addr),
+        }
+    }
+
+    #[test]
+    fn test_bind_options() {
+        let mut config = tcp("127.0.0.1:0");
+        config.reuse_port = true;
+        config.tcp_nodelay = true;
+        config.backlog = 16;
+        let listener = bind(&config).unwrap();
+        //SO_REUSEPORT允许再次绑定同一端口
+        config.addr = listener.local_addr().unwrap();
+        let second = bind(&config).unwrap();
+        assert_eq!(second.local_addr(), Some(config.addr.clone()));
+        let _client = TcpStream::connect(("127.0.0.1", port(&listener))).unwrap();
+        //连接由内核分配给其中一个套接字
+        listener.set_nonblocking(true).unwrap();
+        second.set_nonblocking(true).unwrap();
+        let stream = loop {
+            match listener.accept().or_else(|_| second.accept()) {
+                Ok(stream) => break stream,
+                Err(_) => thread::sleep(Duration::from_millis(5)),
+            }
+        };
+        match stream {
+            Stream::Tcp(stream) => assert!(stream.nodelay().unwrap()),
+            stream => panic!("unexpected stream {:?}", stream),
+        }
+        //未开启SO_REUSEPORT时端口冲突
+        config.reuse_port = false;
+        assert!(bind(&config).is_err());
+    }
+
+    #[test]
+    fn test_dual_stack() {
+        let mut config = tcp("[::]:0");
+        let listener = match bind(&config) {
+            Ok(listener) => listener,
+            //没有IPv6的环境
+            Err(_) => return,
+        };
+        assert!(TcpStream::connect(("127.0.0.1", port(&listener))).is_ok());
+        config.ipv6_only = true;
+        let listener = bind(&config).unwrap();
+        assert!(TcpStream::connect(("127.0.0.1", port(&listener))).is_err());
+        assert!(TcpStream::connect(("::1", port(&listener))).is_ok());
+    }
+
+    #[test]
+    fn test_unix_socket() {
+        let dir = env::temp_dir().join(format!("star-http-unix-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("star-http.sock");
+        let mut config = ListenConfig::new(ListenAddr::Unix(path.clone()));
+        config.mode = Some(0o660);
+        let listener = bind(&config).unwrap();
+        assert_eq!(listener.local_addr(), Some(config.addr.clone()));
+        let mode = fs::metadata(&path).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o660);
+        //正在使用的套接字不会被删除
+        assert_eq!(bind(&config).unwrap_err().kind(), ErrorKind::AddrInUse);
+        let _client = UnixStream::connect(&path).unwrap();
+        let stream = listener.accept().unwrap();
+        assert_eq!(
+            crate::stream::Connection::peer_addr(&stream),
+            format!("unix:{}", path.display())
+        );
+        //进程退出后遗留的套接字文件
+        drop(listener);
+        assert!(path.exists());
+        assert!(bind(&config).is_ok());
+        //不是套接字的文件
+        let file = dir.join("file.sock");
+        fs::write(&file, "").unwrap();
+        config.addr = ListenAddr::Unix(file.clone());
+        assert_eq!(bind(&config).unwrap_err().kind(), ErrorKind::AlreadyExists);
+        assert!(file.exists());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
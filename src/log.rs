@@ -1,63 +1,459 @@
-use std::fmt::Display;
+use std::{
+    backtrace::Backtrace,
+    cell::RefCell,
+    fmt::Display,
+    panic,
+    sync::{
+        atomic::{AtomicI32, AtomicU64, Ordering},
+        Arc, Mutex, OnceLock,
+    },
+};
 
-use crate::time;
+use crate::time::LocalTime;
 
-enum LogLevel {
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum LogLevel {
     Info,
     Error,
 }
 
+impl Display for LogLevel {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            LogLevel::Info => write!(f, "INFO"),
+            LogLevel::Error => write!(f, "ERROR"),
+        }
+    }
+}
+
+//结构化字段的值，JSON输出时数字与布尔值不加引号
+#[derive(Clone, Debug, PartialEq)]
+pub enum FieldValue {
+    Text(String),
+    Number(String),
+    Bool(bool),
+}
+
+impl Display for FieldValue {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            FieldValue::Text(text) => write!(f, "{}", text),
+            FieldValue::Number(number) => write!(f, "{}", number),
+            FieldValue::Bool(b) => write!(f, "{}", b),
+        }
+    }
+}
+
+impl From<&str> for FieldValue {
+    fn from(text: &str) -> Self {
+        FieldValue::Text(text.to_owned())
+    }
+}
+
+impl From<String> for FieldValue {
+    fn from(text: String) -> Self {
+        FieldValue::Text(text)
+    }
+}
+
+impl From<&String> for FieldValue {
+    fn from(text: &String) -> Self {
+        FieldValue::Text(text.clone())
+    }
+}
+
+impl From<bool> for FieldValue {
+    fn from(b: bool) -> Self {
+        FieldValue::Bool(b)
+    }
+}
+
+macro_rules! number_field {
+    ($($ty:ty),*) => {$(
+        impl From<$ty> for FieldValue {
+            fn from(number: $ty) -> Self {
+                FieldValue::Number(number.to_string())
+            }
+        }
+    )*};
+}
+
+number_field!(u8, u16, u32, u64, usize, i32, i64);
+
+//NaN与无穷大不是合法的JSON数字
+impl From<f64> for FieldValue {
+    fn from(number: f64) -> Self {
+        match number.is_finite() {
+            true => FieldValue::Number(number.to_string()),
+            false => FieldValue::Text(number.to_string()),
+        }
+    }
+}
+
+//一条日志：级别、来源模块、消息与结构化字段
 pub struct Log {
-    level: LogLevel,
-    time: String,
-    data: String,
+    pub level: LogLevel,
+    pub target: &'static str,
+    pub message: String,
+    pub fields: Vec<(&'static str, FieldValue)>,
 }
 
 impl Log {
-    pub fn info(data: String) {
-        let log = Log {
-            level: LogLevel::Info,
-            time: time::now(),
-            data,
-        };
-        println!("{}", log)
+    pub fn new(
+        level: LogLevel,
+        target: &'static str,
+        message: String,
+        fields: Vec<(&'static str, FieldValue)>,
+    ) -> Log {
+        Log {
+            level,
+            target,
+            message,
+            fields,
+        }
     }
-    pub fn error(data: String) {
-        let log = Log {
-            level: LogLevel::Error,
-            time: time::now(),
-            data,
-        };
-        eprintln!("{}", log)
+
+    //按当前线程的运行日志设置格式化
+    fn line(&self) -> String {
+        current(|settings| {
+            let timezone = settings.timezone();
+            match settings.formatter() {
+                Some(formatter) => formatter.format(self, timezone),
+                None => TextFormatter.format(self, timezone),
+            }
+        })
     }
-}
 
-impl Display for Log {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    //INFO输出到标准输出，ERROR输出到标准错误
+    pub fn write(self) {
+        let line = self.line();
         match self.level {
-            LogLevel::Info => write!(f, "{} INFO: {}", self.time, self.data),
-            LogLevel::Error => write!(f, "{} ERROR: {}", self.time, self.data),
+            LogLevel::Info => println!("{}", line),
+            LogLevel::Error => eprintln!("{}", line),
+        }
+    }
+}
+
+//日志格式化，可通过LogSettings::set_formatter替换，timezone为所属实例配置的时区（小时）
+pub trait Formatter: Send + Sync {
+    fn format(&self, log: &Log, timezone: i32) -> String;
+}
+
+//文本格式：时间 级别: 消息 key=value
+pub struct TextFormatter;
+
+impl Formatter for TextFormatter {
+    fn format(&self, log: &Log, timezone: i32) -> String {
+        let mut line = format!(
+            "{} {}: {}",
+            LocalTime::now_in(timezone).datetime(),
+            log.level,
+            log.message
+        );
+        for (key, value) in &log.fields {
+            match value {
+                FieldValue::Text(text) if text.is_empty() || text.contains([' ', '"', '=']) => {
+                    line.push_str(&format!(" {}={:?}", key, text))
+                }
+                value => line.push_str(&format!(" {}={}", key, value)),
+            }
+        }
+        line
+    }
+}
+
+//JSON Lines格式，每条日志一行，便于日志采集按字段索引
+pub struct JsonFormatter;
+
+impl Formatter for JsonFormatter {
+    fn format(&self, log: &Log, timezone: i32) -> String {
+        let mut fields = String::new();
+        for (key, value) in &log.fields {
+            if !fields.is_empty() {
+                fields.push(',');
+            }
+            let value = match value {
+                FieldValue::Text(text) => json_string(text),
+                FieldValue::Number(number) => number.clone(),
+                FieldValue::Bool(b) => b.to_string(),
+            };
+            fields.push_str(&format!("{}:{}", json_string(key), value));
+        }
+        format!(
+            "{{\"timestamp\":{},\"level\":\"{}\",\"target\":{},\"message\":{},\"fields\":{{{}}}}}",
+            json_string(&LocalTime::now_in(timezone).iso8601()),
+            log.level,
+            json_string(log.target),
+            json_string(&log.message),
+            fields
+        )
+    }
+}
+
+fn json_string(text: &str) -> String {
+    let mut json = String::with_capacity(text.len() + 2);
+    json.push('"');
+    for c in text.chars() {
+        match c {
+            '"' => json.push_str("\\\""),
+            '\\' => json.push_str("\\\\"),
+            '\n' => json.push_str("\\n"),
+            '\r' => json.push_str("\\r"),
+            '\t' => json.push_str("\\t"),
+            c if (c as u32) < 0x20 => json.push_str(&format!("\\u{:04x}", c as u32)),
+            c => json.push(c),
         }
     }
+    json.push('"');
+    json
+}
+
+/*
+ * 运行日志设置：时区与格式，每个服务器实例一份，重新加载配置时原地更新
+ * 线程处理某个实例的工作时通过enter使用它，其余日志使用set_default设置的进程默认值
+ */
+#[derive(Default)]
+pub struct LogSettings {
+    timezone: AtomicI32,
+    //None为文本格式
+    formatter: Mutex<Option<Arc<dyn Formatter>>>,
+    //格式的版本号，全局唯一，0表示未设置
+    version: AtomicU64,
 }
 
+static NEXT_VERSION: AtomicU64 = AtomicU64::new(1);
+//未设置进程默认值时使用：UTC时间、文本格式
+static PLAIN: LogSettings = LogSettings::new();
+static DEFAULT: OnceLock<Arc<LogSettings>> = OnceLock::new();
+
+thread_local! {
+    //当前线程所服务实例的运行日志设置
+    static SCOPE: RefCell<Option<Arc<LogSettings>>> = const { RefCell::new(None) };
+    //最近取到的格式及其版本号，写日志时只比较版本号，替换格式后才加锁取新的
+    static SNAPSHOT: RefCell<(u64, Option<Arc<dyn Formatter>>)> = const { RefCell::new((0, None)) };
+}
+
+impl LogSettings {
+    pub const fn new() -> LogSettings {
+        LogSettings {
+            timezone: AtomicI32::new(0),
+            formatter: Mutex::new(None),
+            version: AtomicU64::new(0),
+        }
+    }
+
+    pub fn set(&self, timezone: i32, formatter: Arc<dyn Formatter>) {
+        self.timezone.store(timezone, Ordering::Relaxed);
+        self.set_formatter(formatter);
+    }
+
+    pub fn timezone(&self) -> i32 {
+        self.timezone.load(Ordering::Relaxed)
+    }
+
+    pub fn set_formatter(&self, formatter: Arc<dyn Formatter>) {
+        *self.formatter.lock().unwrap_or_else(|e| e.into_inner()) = Some(formatter);
+        let version = NEXT_VERSION.fetch_add(1, Ordering::Relaxed);
+        self.version.store(version, Ordering::Release);
+    }
+
+    fn formatter(&self) -> Option<Arc<dyn Formatter>> {
+        let version = self.version.load(Ordering::Acquire);
+        let latest = || {
+            self.formatter
+                .lock()
+                .unwrap_or_else(|e| e.into_inner())
+                .clone()
+        };
+        //线程退出时线程局部变量已销毁，直接取当前格式
+        SNAPSHOT
+            .try_with(|snapshot| {
+                let mut snapshot = snapshot.borrow_mut();
+                if snapshot.0 != version {
+                    *snapshot = (version, latest());
+                }
+                snapshot.1.clone()
+            })
+            .unwrap_or_else(|_| latest())
+    }
+}
+
+//进程默认的运行日志设置，main设为其服务器实例的设置，只能设置一次
+pub fn set_default(settings: Arc<LogSettings>) {
+    let _ = DEFAULT.set(settings);
+}
+
+//当前线程改用指定的运行日志设置，返回值销毁时恢复之前的设置
+pub fn enter(settings: &Arc<LogSettings>) -> Scope {
+    let previous = SCOPE.with(|scope| scope.replace(Some(Arc::clone(settings))));
+    Scope { previous }
+}
+
+pub struct Scope {
+    previous: Option<Arc<LogSettings>>,
+}
+
+impl Drop for Scope {
+    fn drop(&mut self) {
+        let previous = self.previous.take();
+        let _ = SCOPE.try_with(|scope| *scope.borrow_mut() = previous);
+    }
+}
+
+fn current<R>(f: impl FnOnce(&LogSettings) -> R) -> R {
+    match SCOPE
+        .try_with(|scope| scope.borrow().clone())
+        .ok()
+        .flatten()
+    {
+        Some(settings) => f(&settings),
+        None => f(DEFAULT.get().map_or(&PLAIN, |settings| settings)),
+    }
+}
+
+//panic信息连同调用栈写入错误日志
+pub fn install_panic_hook() {
+    panic::set_hook(Box::new(|info| {
+        let thread = std::thread::current();
+        let message = format!(
+            "Thread {} {}\n{}",
+            thread.name().unwrap_or("<unnamed>"),
+            info,
+            Backtrace::force_capture()
+        );
+        Log::new(LogLevel::Error, "panic", message, Vec::new()).write();
+    }));
+}
+
+/*
+ * 消息前可附带结构化字段，以分号与消息分隔：
+ * log_info!(peer = addr, path = url; "GET {} SUCCESS!", url)
+ */
 #[macro_export]
 macro_rules! log_info {
-    ($fmt:expr) => {$crate::log::Log::info(format!($fmt))};
-    ($fmt:expr,$($arg:tt)*)=>{$crate::log::Log::info(format!($fmt,($($arg)*)))};
+    ($($key:ident = $value:expr),+ ; $($arg:tt)+) => {
+        $crate::log::Log::new(
+            $crate::log::LogLevel::Info,
+            module_path!(),
+            format!($($arg)+),
+            vec![$((stringify!($key), $crate::log::FieldValue::from($value))),+],
+        )
+        .write()
+    };
+    ($fmt:expr) => {$crate::log::Log::new($crate::log::LogLevel::Info, module_path!(), format!($fmt), Vec::new()).write()};
+    ($fmt:expr,$($arg:tt)*)=>{$crate::log::Log::new($crate::log::LogLevel::Info, module_path!(), format!($fmt,$($arg)*), Vec::new()).write()};
 }
 
 #[macro_export]
 macro_rules! log_error {
-    ($fmt:expr) => {$crate::log::Log::error(format!($fmt))};
-    ($fmt:expr,$($arg:tt)*)=>{$crate::log::Log::error(format!($fmt,($($arg)*)))};
+    ($($key:ident = $value:expr),+ ; $($arg:tt)+) => {
+        $crate::log::Log::new(
+            $crate::log::LogLevel::Error,
+            module_path!(),
+            format!($($arg)+),
+            vec![$((stringify!($key), $crate::log::FieldValue::from($value))),+],
+        )
+        .write()
+    };
+    ($fmt:expr) => {$crate::log::Log::new($crate::log::LogLevel::Error, module_path!(), format!($fmt), Vec::new()).write()};
+    ($fmt:expr,$($arg:tt)*)=>{$crate::log::Log::new($crate::log::LogLevel::Error, module_path!(), format!($fmt,$($arg)*), Vec::new()).write()};
 }
 
 #[cfg(test)]
 mod log_test {
+    use super::*;
+
     #[test]
     fn log_info_test() {
         log_info!("hello world");
         log_info!("hello world{}", "!");
+        log_info!(user = "star", count = 3usize; "hello {}", "world");
+        log_error!(ok = false; "failed");
+    }
+
+    fn log() -> Log {
+        Log::new(
+            LogLevel::Info,
+            "star_http::http",
+            String::from("GET \"/a\"\n"),
+            vec![
+                ("path", FieldValue::from("/a b")),
+                ("status", FieldValue::from(200u16)),
+                ("duration_ms", FieldValue::from(1.5)),
+                ("cached", FieldValue::from(true)),
+            ],
+        )
+    }
+
+    #[test]
+    fn test_text_formatter() {
+        let line = TextFormatter.format(&log(), 8);
+        assert!(
+            line.ends_with(
+                " INFO: GET \"/a\"\n path=\"/a b\" status=200 duration_ms=1.5 cached=true"
+            ),
+            "{}",
+            line
+        );
+    }
+
+    #[test]
+    fn test_json_formatter() {
+        let line = JsonFormatter.format(&log(), 8);
+        assert!(line.starts_with("{\"timestamp\":\""), "{}", line);
+        assert!(line.contains("+08:00\",\"level\""), "{}", line);
+        assert!(line.ends_with("\",\"level\":\"INFO\",\"target\":\"star_http::http\",\"message\":\"GET \\\"/a\\\"\\n\",\"fields\":{\"path\":\"/a b\",\"status\":200,\"duration_ms\":1.5,\"cached\":true}}"), "{}", line);
+        assert_eq!(json_string("a\u{1}"), "\"a\\u0001\"");
+    }
+
+    struct Upper;
+
+    impl Formatter for Upper {
+        fn format(&self, log: &Log, timezone: i32) -> String {
+            format!("{} {}", log.message.to_uppercase(), timezone)
+        }
+    }
+
+    #[test]
+    fn test_set_formatter() {
+        let message = || Log::new(LogLevel::Info, "test", String::from("abc"), Vec::new());
+        let settings = Arc::new(LogSettings::new());
+        let scope = enter(&settings);
+        assert!(message().line().ends_with("INFO: abc"));
+        settings.set(8, Arc::new(Upper));
+        assert_eq!(message().line(), "ABC 8");
+        //其他线程取到新的快照
+        let shared = Arc::clone(&settings);
+        let other = std::thread::spawn(move || {
+            let _scope = enter(&shared);
+            message().line()
+        });
+        assert_eq!(other.join().unwrap(), "ABC 8");
+        drop(scope);
+        assert!(!message().line().starts_with("ABC"));
+    }
+
+    //同一进程中的实例各自使用自己的时区与格式
+    #[test]
+    fn test_scopes() {
+        let message = || Log::new(LogLevel::Info, "test", String::from("abc"), Vec::new());
+        let text = Arc::new(LogSettings::new());
+        text.set(-5, Arc::new(TextFormatter));
+        let json = Arc::new(LogSettings::new());
+        json.set(8, Arc::new(JsonFormatter));
+        let _text = enter(&text);
+        assert!(message().line().ends_with(" INFO: abc"));
+        {
+            let _json = enter(&json);
+            assert!(message().line().contains("+08:00"));
+        }
+        //调小时区后立即生效
+        json.set(-5, Arc::new(JsonFormatter));
+        let line = std::thread::spawn(move || {
+            let _json = enter(&json);
+            message().line()
+        });
+        assert!(line.join().unwrap().contains("-05:00"));
+        assert!(message().line().ends_with(" INFO: abc"));
     }
 }
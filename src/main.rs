@@ -1,54 +1,460 @@
+//依赖信号、Unix套接字与描述符传递，仅支持类Unix系统
+#[cfg(not(unix))]
+compile_error!("star-http only supports Unix-like systems");
+
+mod access_log;
+mod cache;
+mod cli;
 mod config;
+mod context;
+mod dev;
 mod error;
+mod glob;
 mod hex;
 mod http;
+mod http2;
+mod listener;
 mod log;
+mod mime;
+mod proxy;
+#[cfg(target_os = "linux")]
+mod reactor;
+#[cfg(target_os = "linux")]
+mod sendfile;
+mod shutdown;
+mod signal;
+mod sse;
+mod stats;
+mod stream;
 mod thread;
 mod time;
+#[cfg(feature = "tls")]
+mod tls;
+mod toml;
+mod upgrade;
 
 use std::{
-    fs,
-    net::{SocketAddrV4, TcpListener},
+    env, fs,
+    os::fd::{AsRawFd, RawFd},
     path::Path,
+    process,
+    sync::Arc,
+    thread::JoinHandle,
+    time::{Duration, Instant},
 };
 
-use config::MyConfig;
-use http::handle_connect;
+use cli::Command;
+use config::{Config, ConfigSource, IoModel, ListenAddr, ListenConfig, MyConfig};
+use context::ServerContext;
+use http::{handle_connect, handle_redirect, reject, Redirect};
+use listener::Listener;
+use proxy::ProxiedStream;
+use stream::{Connection, Stream};
 use thread::ThreadPool;
 
 fn main() {
-    let config = MyConfig::new();
+    log::install_panic_hook();
+    let source = match cli::parse(env::args().skip(1)) {
+        Ok(Command::Run(source)) => source,
+        Ok(Command::Check(source)) => process::exit(check_config(&source)),
+        Ok(Command::Dump(source)) => process::exit(dump_config(&source)),
+        Ok(Command::Version) => {
+            println!("star-http {}", env!("CARGO_PKG_VERSION"));
+            return;
+        }
+        Ok(Command::Help) => {
+            print!("{}", cli::USAGE);
+            return;
+        }
+        Err(e) => {
+            eprintln!("{}\n\n{}", e, cli::USAGE);
+            process::exit(2);
+        }
+    };
+    let config = match MyConfig::load(&source) {
+        Ok(config) => config,
+        Err(e) => {
+            log_error!("Invalid configuration:\n{}", e);
+            process::exit(1);
+        }
+    };
+    let ctx = ServerContext::new(config.clone(), source);
+    //不属于某个连接的日志（启动、信号、线程池）也使用本实例的时区与格式
+    log::set_default(Arc::clone(&ctx.log));
     let path = Path::new(&config.static_resource_path);
     if !path.exists() {
         if let Err(e) = fs::create_dir_all(path) {
             panic!("Initialization failed. Error:{}", e)
         }
     }
-    let socket_addr = SocketAddrV4::new(config.ip, config.port);
-    match TcpListener::bind(socket_addr) {
-        Ok(listener) => {
-            //创建线程池
-            match ThreadPool::new(config.thread_pool_size) {
-                Ok(pool) => {
-                    for stream in listener.incoming() {
-                        match stream {
-                            Ok(stream) => {
-                                log_info!("Connect Incoming!");
-                                pool.exec(|| handle_connect(stream));
-                            }
-                            Err(e) => {
-                                log_error!("Connect Incoming Error:{}", e)
-                            }
-                        }
+    if config.dev {
+        dev::watch(Arc::clone(&ctx));
+    }
+    if config.config_watch {
+        ctx.watch_config();
+    }
+    if let Err(e) = signal::install(&[
+        signal::SIGTERM,
+        signal::SIGINT,
+        signal::SIGHUP,
+        signal::SIGUSR2,
+    ]) {
+        panic!("Failed to install signal handlers. Error:{}", e)
+    }
+    let mut listeners = Vec::new();
+    for listen in &config.listen {
+        match upgrade::listen(listen) {
+            Ok(listener) => listeners.push((listen.clone(), listener)),
+            Err(e) => {
+                log_error!("Failed to listen on {}! Err:{}", listen.addr, e);
+                process::exit(1);
+            }
+        }
+    }
+    //创建线程池，所有监听共用
+    let pool = match ThreadPool::new(
+        config.min_threads,
+        config.max_threads,
+        Duration::from_secs(config.thread_idle_timeout),
+        config.queue_capacity,
+        Arc::clone(&ctx.stats),
+    ) {
+        Ok(pool) => Arc::new(pool),
+        Err(e) => panic!("{}", e),
+    };
+    let mut acceptors = Vec::new();
+    //升级时传递给新进程的监听套接字
+    let mut listen_fds: Vec<(String, RawFd)> = listeners
+        .iter()
+        .map(|(listen, listener)| (listen.addr.to_string(), listener.as_raw_fd()))
+        .collect();
+    //HTTPS监听
+    let tls_port = match &config.tls {
+        Some(tls) => {
+            acceptors.extend(start_tls_listener(
+                &ctx,
+                &config,
+                tls,
+                Arc::clone(&pool),
+                &mut listen_fds,
+            ));
+            tls.redirect.then_some(tls.port)
+        }
+        None => None,
+    };
+    for (listen, listener) in listeners {
+        log_info!("Listening on {}", listen.addr);
+        let redirect = tls_port.map(|tls_port| Redirect {
+            tls_port,
+            default_host: listen.host(),
+        });
+        acceptors.push(start_listener(
+            listener,
+            &ctx,
+            Arc::clone(&pool),
+            config.io_model,
+            redirect,
+            listen.proxy_protocol,
+        ));
+    }
+    upgrade::close_unused();
+    upgrade::notify_ready();
+    let mut upgraded = false;
+    //等待退出信号
+    loop {
+        match signal::wait() {
+            Ok(signal::SIGTERM) | Ok(signal::SIGINT) => break,
+            Ok(signal::SIGHUP) => {
+                if let Err(e) = ctx.reload() {
+                    log_error!("Config reload rejected, keep the old one. Error:{}", e);
+                }
+            }
+            //平滑升级：新进程接管监听套接字后，本进程优雅退出
+            Ok(signal::SIGUSR2) => match upgrade::reexec(&listen_fds) {
+                Ok(()) => {
+                    upgraded = true;
+                    break;
+                }
+                Err(e) => log_error!("Upgrade failed, keep serving. Error:{}", e),
+            },
+            Ok(_) => {}
+            Err(e) => {
+                log_error!("Failed to wait for signals. Error:{}", e);
+                break;
+            }
+        }
+    }
+    //升级后的新进程继续使用套接字文件，systemd传入的套接字由systemd管理
+    if !upgraded && !upgrade::socket_activated() {
+        remove_unix_sockets(&config.listen);
+    }
+    let clean = graceful_shutdown(
+        &ctx,
+        acceptors,
+        &pool,
+        Duration::from_secs(ctx.config().shutdown_grace_period),
+    );
+    //0：正常退出；1：宽限期内未处理完，强制退出
+    process::exit(if clean { 0 } else { 1 });
+}
+
+/*
+ * 停止接收新连接，等待处理中的请求在宽限期内完成，返回是否全部正常结束
+ */
+fn graceful_shutdown(
+    ctx: &ServerContext,
+    acceptors: Vec<JoinHandle<()>>,
+    pool: &ThreadPool,
+    grace: Duration,
+) -> bool {
+    let deadline = Instant::now() + grace;
+    ctx.begin_shutdown();
+    for acceptor in acceptors {
+        while !acceptor.is_finished() && Instant::now() < deadline {
+            std::thread::sleep(Duration::from_millis(20));
+        }
+        if !acceptor.is_finished() {
+            log_error!("Grace period expired, forcing shutdown");
+            return false;
+        }
+        let _ = acceptor.join();
+    }
+    if !pool.shutdown(deadline) {
+        log_error!("Grace period expired, forcing shutdown");
+        return false;
+    }
+    log_info!("Server stopped");
+    true
+}
+
+fn remove_unix_sockets(listen: &[ListenConfig]) {
+    for listen in listen {
+        if let ListenAddr::Unix(path) = &listen.addr {
+            if let Err(e) = fs::remove_file(path) {
+                log_error!("Failed to remove {}. Error:{}", path.display(), e);
+            }
+        }
+    }
+}
+
+fn dump_config(source: &ConfigSource) -> i32 {
+    match Config::load(source) {
+        Ok(config) => {
+            print!("{}", config.dump());
+            0
+        }
+        Err(e) => {
+            eprintln!("{}", e);
+            1
+        }
+    }
+}
+
+fn check_config(source: &ConfigSource) -> i32 {
+    match MyConfig::load(source) {
+        Ok(_) => {
+            println!("The configuration is valid");
+            0
+        }
+        Err(e) => {
+            eprintln!("{}", e);
+            1
+        }
+    }
+}
+
+//明文监听，每个监听地址一个接收线程
+fn start_listener(
+    listener: Listener,
+    ctx: &Arc<ServerContext>,
+    pool: Arc<ThreadPool>,
+    io_model: IoModel,
+    redirect: Option<Redirect>,
+    proxy_protocol: bool,
+) -> JoinHandle<()> {
+    let ctx = Arc::clone(ctx);
+    std::thread::spawn(move || {
+        let _log = ctx.log_scope();
+        if io_model == IoModel::Event {
+            run_reactor(listener, pool, ctx, redirect, proxy_protocol);
+            return;
+        }
+        accept_loop(&listener, &ctx, |stream| {
+            let peer = stream.peer_addr();
+            log_info!(peer = &peer; "Connect Incoming! {}", peer);
+            let conn_ctx = Arc::clone(&ctx);
+            let redirect = redirect.clone();
+            //排队前登记，关闭开始时仍在排队的请求也会被处理
+            let tracker = ctx.shutdown.track(&stream);
+            let result = pool.try_exec(stream, move |stream| {
+                let _log = conn_ctx.log_scope();
+                tracker.start();
+                let stream = match ProxiedStream::accept(stream, proxy_protocol) {
+                    Ok(stream) => stream,
+                    Err(e) => {
+                        log_error!("Invalid PROXY protocol header! Err:{}", e);
+                        return;
                     }
+                };
+                match redirect {
+                    Some(redirect) => handle_redirect(stream, &conn_ctx, &redirect),
+                    None => handle_connect(stream, &conn_ctx),
+                }
+            });
+            if let Err(stream) = result {
+                reject(stream, &ctx);
+            }
+        });
+    })
+}
+
+//接收连接直到开始关闭
+fn accept_loop(listener: &Listener, ctx: &ServerContext, mut on_accept: impl FnMut(Stream)) {
+    if let Err(e) = listener.set_nonblocking(true) {
+        log_error!("Failed to set the listener non-blocking! Err:{}", e);
+        return;
+    }
+    let fds = [listener.as_raw_fd(), ctx.shutdown.notify_fd()];
+    loop {
+        match signal::poll_readable(&fds, None) {
+            Ok(ready) if ready[1] => return,
+            Ok(_) => {}
+            Err(e) => {
+                log_error!("Failed to poll the listener! Err:{}", e);
+                return;
+            }
+        }
+        loop {
+            match listener.accept() {
+                Ok(stream) => match stream.set_nonblocking(false) {
+                    Ok(()) => on_accept(stream),
+                    Err(e) => log_error!("Connect Incoming Error:{}", e),
+                },
+                Err(e) if e.kind() == std::io::ErrorKind::WouldBlock => break,
+                Err(e) => {
+                    log_error!("Connect Incoming Error:{}", e);
+                    break;
                 }
-                Err(e) => panic!("{}", e),
             }
         }
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn run_reactor(
+    listener: Listener,
+    pool: Arc<ThreadPool>,
+    ctx: Arc<ServerContext>,
+    redirect: Option<Redirect>,
+    proxy_protocol: bool,
+) {
+    let result = reactor::Reactor::new(listener, pool, ctx, redirect, proxy_protocol)
+        .and_then(|mut r| r.run());
+    if let Err(e) = result {
+        log_error!("Event loop failed! Err:{}", e);
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+fn run_reactor(
+    _listener: Listener,
+    _pool: Arc<ThreadPool>,
+    _ctx: Arc<ServerContext>,
+    _redirect: Option<Redirect>,
+    _proxy_protocol: bool,
+) {
+    unreachable!("io_model = \"event\" is only supported on Linux")
+}
+
+/*
+ * HTTPS监听：在每个明文监听的IP上监听tls_port，监听选项与明文监听相同
+ */
+#[cfg(feature = "tls")]
+fn start_tls_listener(
+    ctx: &Arc<ServerContext>,
+    config: &MyConfig,
+    tls: &config::TlsConfig,
+    pool: Arc<ThreadPool>,
+    listen_fds: &mut Vec<(String, RawFd)>,
+) -> Vec<JoinHandle<()>> {
+    let acceptor = match tls::TlsAcceptor::new(tls) {
+        Ok(acceptor) => Arc::new(acceptor),
         Err(e) => {
-            log_error!("Failed to listen to the IP port! Err:{}", e);
+            log_error!("TLS initialization failed. Error:{}", e);
+            process::exit(1);
+        }
+    };
+    let mut tls_listen: Vec<ListenConfig> = Vec::new();
+    for listen in &config.listen {
+        let mut addr = match listen.addr {
+            ListenAddr::Tcp(addr) => addr,
+            ListenAddr::Unix(_) => continue,
+        };
+        addr.set_port(tls.port);
+        if tls_listen.iter().all(|l| l.addr != ListenAddr::Tcp(addr)) {
+            tls_listen.push(ListenConfig {
+                addr: ListenAddr::Tcp(addr),
+                ..listen.clone()
+            });
         }
     }
+    let mut acceptors = Vec::new();
+    for listen in tls_listen {
+        let listener = match upgrade::listen(&listen) {
+            Ok(listener) => listener,
+            Err(e) => {
+                log_error!("Failed to listen on {} (TLS)! Err:{}", listen.addr, e);
+                process::exit(1);
+            }
+        };
+        log_info!("Listening on {} (TLS)", listen.addr);
+        listen_fds.push((listen.addr.to_string(), listener.as_raw_fd()));
+        let tls_ctx = Arc::clone(ctx);
+        let acceptor = Arc::clone(&acceptor);
+        let pool = Arc::clone(&pool);
+        let proxy_protocol = listen.proxy_protocol;
+        acceptors.push(std::thread::spawn(move || {
+            let _log = tls_ctx.log_scope();
+            accept_loop(&listener, &tls_ctx, |stream| {
+                log_info!("TLS Connect Incoming! {}", stream.peer_addr());
+                let acceptor = Arc::clone(&acceptor);
+                let ctx = Arc::clone(&tls_ctx);
+                let tracker = ctx.shutdown.track(&stream);
+                let result = pool.try_exec(stream, move |stream| {
+                    let _log = ctx.log_scope();
+                    tracker.start();
+                    //PROXY协议头在TLS握手之前
+                    let stream = match ProxiedStream::accept(stream, proxy_protocol) {
+                        Ok(stream) => stream,
+                        Err(e) => {
+                            log_error!("Invalid PROXY protocol header! Err:{}", e);
+                            return;
+                        }
+                    };
+                    match acceptor.accept(stream) {
+                        Ok(stream) => handle_connect(stream, &ctx),
+                        Err(e) => log_error!("TLS Connect Error:{}", e),
+                    }
+                });
+                //队列已满时直接关闭，发送503需要先完成TLS握手
+                if result.is_err() {
+                    log_error!("Job queue is full, TLS connection dropped!");
+                }
+            });
+        }));
+    }
+    acceptors
+}
+
+#[cfg(not(feature = "tls"))]
+fn start_tls_listener(
+    _ctx: &Arc<ServerContext>,
+    _config: &MyConfig,
+    _tls: &config::TlsConfig,
+    _pool: Arc<ThreadPool>,
+    _listen_fds: &mut Vec<(String, RawFd)>,
+) -> Vec<JoinHandle<()>> {
+    unreachable!("tls_port requires the tls feature")
 }
 
 #[cfg(test)]
@@ -62,7 +468,7 @@ mod test {
         for node in path.split("/") {
             current_path.push(node);
         }
-        println!("{}", current_path.display().to_string());
+        println!("{}", current_path.display());
         Ok(())
     }
 }
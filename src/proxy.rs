@@ -0,0 +1,438 @@
+use std::{
+    io::{self, Read, Write},
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr},
+    os::fd::RawFd,
+};
+
+use crate::{error::HttpError, stream::Connection};
+
+//PROXY协议v2的签名
+const V2_SIGNATURE: &[u8; 12] = b"\r\n\r\n\0\r\nQUIT\n";
+//v1头部的最大长度（含CRLF）
+const V1_MAX_LEN: usize = 107;
+
+//可信代理：IP网段或Unix套接字（unix:）
+#[derive(Clone, Debug, PartialEq)]
+pub enum Cidr {
+    Ip(IpAddr, u8),
+    Unix,
+}
+
+impl Cidr {
+    //1.2.3.0/24、::1、unix:
+    pub fn parse(text: &str) -> Result<Cidr, String> {
+        if text == "unix:" {
+            return Ok(Cidr::Unix);
+        }
+        let (ip, prefix) = match text.split_once('/') {
+            Some((ip, prefix)) => (ip, Some(prefix)),
+            None => (text, None),
+        };
+        let ip = ip
+            .parse::<IpAddr>()
+            .map_err(|_| format!("\"{}\" is not an IP address or CIDR", text))?
+            .to_canonical();
+        let max = if ip.is_ipv4() { 32 } else { 128 };
+        let prefix = match prefix {
+            Some(prefix) => prefix
+                .parse::<u8>()
+                .ok()
+                .filter(|prefix| *prefix <= max)
+                .ok_or_else(|| format!("\"{}\": the prefix length must be 0..={}", text, max))?,
+            None => max,
+        };
+        Ok(Cidr::Ip(ip, prefix))
+    }
+
+    fn contains(&self, ip: IpAddr) -> bool {
+        match (self, ip.to_canonical()) {
+            (Cidr::Ip(IpAddr::V4(net), prefix), IpAddr::V4(ip)) => {
+                let mask = u32::MAX.checked_shl(32 - *prefix as u32).unwrap_or(0);
+                u32::from(*net) & mask == u32::from(ip) & mask
+            }
+            (Cidr::Ip(IpAddr::V6(net), prefix), IpAddr::V6(ip)) => {
+                let mask = u128::MAX.checked_shl(128 - *prefix as u32).unwrap_or(0);
+                u128::from(*net) & mask == u128::from(ip) & mask
+            }
+            _ => false,
+        }
+    }
+}
+
+//PROXY协议头的解析结果
+#[derive(Debug, PartialEq)]
+pub enum Header {
+    //至少还需要的字节数
+    Incomplete(usize),
+    //头部长度与客户端地址，LOCAL命令或未知协议时没有地址
+    Complete(usize, Option<SocketAddr>),
+}
+
+/*
+ * 解析连接开头的PROXY协议头（v1文本或v2二进制），不是PROXY协议头时返回错误
+ * 两种格式都至少有12个字节，可以先读取12个字节再判断格式
+ */
+pub fn parse(buf: &[u8]) -> Result<Header, String> {
+    let prefix = &buf[..buf.len().min(12)];
+    let v2 = V2_SIGNATURE.starts_with(prefix);
+    if !v2 && !prefix.starts_with(b"PROXY ") && !b"PROXY ".starts_with(prefix) {
+        return Err("not a PROXY protocol header".to_owned());
+    }
+    if buf.len() < 12 {
+        return Ok(Header::Incomplete(12 - buf.len()));
+    }
+    if v2 {
+        parse_v2(buf)
+    } else {
+        parse_v1(buf)
+    }
+}
+
+fn parse_v1(buf: &[u8]) -> Result<Header, String> {
+    let end = match buf.windows(2).position(|w| w == b"\r\n") {
+        Some(end) => end,
+        None if buf.len() < V1_MAX_LEN => return Ok(Header::Incomplete(1)),
+        None => return Err("the v1 header is too long".to_owned()),
+    };
+    let line = std::str::from_utf8(&buf[..end]).map_err(|e| e.to_string())?;
+    let parts: Vec<&str> = line.split(' ').collect();
+    let client = match parts.as_slice() {
+        ["PROXY", "UNKNOWN", ..] => None,
+        ["PROXY", family @ ("TCP4" | "TCP6"), src, _dst, sport, _dport] => {
+            let ip = match (*family, src.parse::<IpAddr>()) {
+                ("TCP4", Ok(ip @ IpAddr::V4(_))) | ("TCP6", Ok(ip @ IpAddr::V6(_))) => ip,
+                _ => return Err(format!("invalid source address \"{}\"", src)),
+            };
+            let port = sport
+                .parse::<u16>()
+                .map_err(|_| format!("invalid source port \"{}\"", sport))?;
+            Some(SocketAddr::new(ip, port))
+        }
+        _ => return Err(format!("invalid v1 header \"{}\"", line)),
+    };
+    Ok(Header::Complete(end + 2, client))
+}
+
+fn parse_v2(buf: &[u8]) -> Result<Header, String> {
+    if buf.len() < 16 {
+        return Ok(Header::Incomplete(16 - buf.len()));
+    }
+    let len = 16 + u16::from_be_bytes([buf[14], buf[15]]) as usize;
+    if buf.len() < len {
+        return Ok(Header::Incomplete(len - buf.len()));
+    }
+    let (version, command) = (buf[12] >> 4, buf[12] & 0x0f);
+    if version != 2 {
+        return Err(format!("unsupported version {}", version));
+    }
+    let addrs = &buf[16..len];
+    let client = match (command, buf[13] >> 4) {
+        //LOCAL：代理自身的连接（如健康检查）
+        (0, _) => None,
+        //TCP或UDP over IPv4
+        (1, 1) if addrs.len() >= 12 => {
+            let ip = Ipv4Addr::from([addrs[0], addrs[1], addrs[2], addrs[3]]);
+            Some(SocketAddr::new(
+                ip.into(),
+                u16::from_be_bytes([addrs[8], addrs[9]]),
+            ))
+        }
+        //TCP或UDP over IPv6
+        (1, 2) if addrs.len() >= 36 => {
+            let mut ip = [0u8; 16];
+            ip.copy_from_slice(&addrs[..16]);
+            Some(SocketAddr::new(
+                Ipv6Addr::from(ip).into(),
+                u16::from_be_bytes([addrs[32], addrs[33]]),
+            ))
+        }
+        (1, 1 | 2) => return Err("the v2 address block is too short".to_owned()),
+        //未指定或Unix套接字，使用连接本身的地址
+        (1, _) => None,
+        (command, _) => return Err(format!("unsupported command {}", command)),
+    };
+    Ok(Header::Complete(len, client))
+}
+
+/*
+ * 开启PROXY协议时由代理转发的连接，客户端地址取自PROXY协议头
+ */
+pub struct ProxiedStream<S> {
+    stream: S,
+    client: Option<SocketAddr>,
+}
+
+impl<S: Connection> ProxiedStream<S> {
+    //开启时先读取PROXY协议头，只读取头部本身，之后的数据留给请求处理
+    pub fn accept(mut stream: S, enabled: bool) -> Result<ProxiedStream<S>, HttpError> {
+        let mut client = None;
+        if enabled {
+            let mut buf = Vec::new();
+            client = loop {
+                match parse(&buf) {
+                    Ok(Header::Incomplete(more)) => {
+                        let len = buf.len();
+                        buf.resize(len + more, 0);
+                        stream.read_exact(&mut buf[len..])?;
+                    }
+                    Ok(Header::Complete(_, client)) => break client,
+                    Err(message) => {
+                        return Err(HttpError {
+                            kind: "proxy".to_string(),
+                            message,
+                        })
+                    }
+                }
+            };
+        }
+        Ok(ProxiedStream { stream, client })
+    }
+}
+
+impl<S: Connection> Read for ProxiedStream<S> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.stream.read(buf)
+    }
+}
+
+impl<S: Connection> Write for ProxiedStream<S> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.stream.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.stream.flush()
+    }
+}
+
+impl<S: Connection> Connection for ProxiedStream<S> {
+    fn shutdown(&mut self) -> io::Result<()> {
+        self.stream.shutdown()
+    }
+
+    fn is_secure(&self) -> bool {
+        self.stream.is_secure()
+    }
+
+    fn raw_fd(&self) -> Option<RawFd> {
+        self.stream.raw_fd()
+    }
+
+    fn peer_addr(&self) -> String {
+        match self.client {
+            Some(client) => client.to_string(),
+            None => self.stream.peer_addr(),
+        }
+    }
+}
+
+//IP、IP:端口、[IPv6]或[IPv6]:端口中的IP
+fn parse_ip(text: &str) -> Option<IpAddr> {
+    let text = text.trim();
+    let ip = match text.parse::<SocketAddr>() {
+        Ok(addr) => addr.ip(),
+        Err(_) => text
+            .strip_prefix('[')
+            .and_then(|v6| v6.strip_suffix(']'))
+            .unwrap_or(text)
+            .parse()
+            .ok()?,
+    };
+    Some(ip.to_canonical())
+}
+
+fn is_trusted(addr: &str, trusted: &[Cidr]) -> bool {
+    match parse_ip(addr) {
+        Some(ip) => trusted.iter().any(|cidr| cidr.contains(ip)),
+        None => addr.starts_with("unix:") && trusted.contains(&Cidr::Unix),
+    }
+}
+
+//Forwarded头中各节点的for参数
+fn forwarded_for(forwarded: &str) -> Vec<&str> {
+    forwarded
+        .split(',')
+        .filter_map(|element| {
+            element.split(';').find_map(|pair| {
+                let (key, value) = pair.split_once('=')?;
+                key.trim()
+                    .eq_ignore_ascii_case("for")
+                    .then(|| value.trim().trim_matches('"'))
+            })
+        })
+        .collect()
+}
+
+/*
+ * 请求的客户端地址：连接的对端是可信代理时，按Forwarded（优先）或X-Forwarded-For
+ * 从右向左取第一个不可信的地址；遇到无法解析的地址时停止，使用最后一个可信的地址
+ * 返回不含端口的IP，Unix套接字等非IP地址原样返回
+ */
+pub fn client_addr(
+    peer: &str,
+    forwarded: Option<&str>,
+    x_forwarded_for: Option<&str>,
+    trusted: &[Cidr],
+) -> String {
+    let mut client = parse_ip(peer).map_or_else(|| peer.to_owned(), |ip| ip.to_string());
+    if !is_trusted(peer, trusted) {
+        return client;
+    }
+    let chain = match forwarded.map(forwarded_for) {
+        Some(chain) if !chain.is_empty() => chain,
+        _ => match x_forwarded_for {
+            Some(addrs) => addrs.split(',').collect(),
+            None => return client,
+        },
+    };
+    for addr in chain.iter().rev() {
+        match parse_ip(addr) {
+            Some(ip) => {
+                client = ip.to_string();
+                if !trusted.iter().any(|cidr| cidr.contains(ip)) {
+                    break;
+                }
+            }
+            None => break,
+        }
+    }
+    client
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::Cursor;
+
+    use super::*;
+
+    fn addr(text: &str) -> Option<SocketAddr> {
+        Some(text.parse().unwrap())
+    }
+
+    #[test]
+    fn test_parse_v1() {
+        let header = b"PROXY TCP4 192.168.0.1 192.168.0.11 56324 443\r\nGET / HTTP/1.1\r\n";
+        assert_eq!(
+            parse(header),
+            Ok(Header::Complete(47, addr("192.168.0.1:56324")))
+        );
+        assert_eq!(
+            parse(b"PROXY TCP6 2001:db8::1 2001:db8::2 4711 80\r\n"),
+            Ok(Header::Complete(44, addr("[2001:db8::1]:4711")))
+        );
+        assert_eq!(parse(b"PROXY UNKNOWN\r\n"), Ok(Header::Complete(15, None)));
+        //数据不完整时先读满12个字节，之后逐字节读取直到CRLF
+        assert_eq!(parse(b""), Ok(Header::Incomplete(12)));
+        assert_eq!(parse(b"PRO"), Ok(Header::Incomplete(9)));
+        assert_eq!(parse(b"PROXY TCP4 1"), Ok(Header::Incomplete(1)));
+        assert!(parse(b"PROXY TCP4 ::1 ::1 1 2\r\n").is_err());
+        assert!(parse(b"PROXY TCP4 1.2.3.4 5.6.7.8 x 80\r\n").is_err());
+        assert!(parse(&[b'P'; 200]).is_err());
+        assert!(parse(b"GET / HTTP/1.1\r\n\r\n").is_err());
+    }
+
+    #[test]
+    fn test_parse_v2() {
+        let mut header = V2_SIGNATURE.to_vec();
+        header.extend_from_slice(&[0x21, 0x11, 0, 12, 10, 0, 0, 1, 10, 0, 0, 2, 0x1f, 0x90]);
+        assert_eq!(parse(&header[..14]), Ok(Header::Incomplete(2)));
+        assert_eq!(parse(&header), Ok(Header::Incomplete(2)));
+        header.extend_from_slice(&[0, 80]);
+        assert_eq!(
+            parse(&header),
+            Ok(Header::Complete(28, addr("10.0.0.1:8080")))
+        );
+        //IPv6，附带TLV
+        let mut header = V2_SIGNATURE.to_vec();
+        header.extend_from_slice(&[0x21, 0x21, 0, 40]);
+        header.extend_from_slice(&Ipv6Addr::LOCALHOST.octets());
+        header.extend_from_slice(&[0; 16]);
+        header.extend_from_slice(&[0x12, 0x34, 0x01, 0xbb, 0x04, 0, 1, 0]);
+        assert_eq!(parse(&header), Ok(Header::Complete(56, addr("[::1]:4660"))));
+        //LOCAL命令
+        let mut header = V2_SIGNATURE.to_vec();
+        header.extend_from_slice(&[0x20, 0x00, 0, 0]);
+        assert_eq!(parse(&header), Ok(Header::Complete(16, None)));
+        header[12] = 0x31;
+        assert!(parse(&header).is_err());
+    }
+
+    //只读取头部，之后的数据仍可读
+    #[test]
+    fn test_accept() {
+        struct Mock(Cursor<Vec<u8>>);
+        impl Read for Mock {
+            fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+                self.0.read(buf)
+            }
+        }
+        impl Write for Mock {
+            fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+                Ok(buf.len())
+            }
+            fn flush(&mut self) -> io::Result<()> {
+                Ok(())
+            }
+        }
+        impl Connection for Mock {
+            fn shutdown(&mut self) -> io::Result<()> {
+                Ok(())
+            }
+        }
+        let data = b"PROXY TCP4 1.2.3.4 5.6.7.8 1000 80\r\nGET /".to_vec();
+        let mut stream = ProxiedStream::accept(Mock(Cursor::new(data)), true).unwrap();
+        assert_eq!(stream.peer_addr(), "1.2.3.4:1000");
+        let mut rest = String::new();
+        stream.read_to_string(&mut rest).unwrap();
+        assert_eq!(rest, "GET /");
+        let stream = ProxiedStream::accept(Mock(Cursor::new(b"GET /".to_vec())), false).unwrap();
+        assert_eq!(stream.peer_addr(), "-");
+        assert!(ProxiedStream::accept(Mock(Cursor::new(b"GET /".to_vec())), true).is_err());
+        assert!(ProxiedStream::accept(Mock(Cursor::new(b"PROXY ".to_vec())), true).is_err());
+    }
+
+    #[test]
+    fn test_client_addr() {
+        let trusted: Vec<Cidr> = ["10.0.0.0/8", "::1", "unix:"]
+            .iter()
+            .map(|cidr| Cidr::parse(cidr).unwrap())
+            .collect();
+        let xff = Some("203.0.113.7, 198.51.100.1, 10.1.2.3");
+        //不可信的对端不采用X-Forwarded-For
+        assert_eq!(
+            client_addr("192.0.2.1:5000", None, xff, &trusted),
+            "192.0.2.1"
+        );
+        //跳过可信代理，取最右边不可信的地址
+        assert_eq!(
+            client_addr("10.0.0.1:5000", None, xff, &trusted),
+            "198.51.100.1"
+        );
+        assert_eq!(
+            client_addr("[::ffff:10.0.0.1]:5000", None, Some("10.0.0.2"), &trusted),
+            "10.0.0.2"
+        );
+        assert_eq!(
+            client_addr("[::1]:5000", None, Some("unknown, 10.0.0.2"), &trusted),
+            "10.0.0.2"
+        );
+        let forwarded = Some("for=192.0.2.60;proto=http, for=\"[2001:db8::17]:4711\"");
+        assert_eq!(
+            client_addr("unix:/run/a.sock", forwarded, xff, &trusted),
+            "2001:db8::17"
+        );
+        assert_eq!(
+            client_addr("unix:/run/a.sock", None, None, &[]),
+            "unix:/run/a.sock"
+        );
+        assert_eq!(
+            Cidr::parse("10.0.0.0/8"),
+            Ok(Cidr::Ip([10, 0, 0, 0].into(), 8))
+        );
+        assert!(Cidr::parse("10.0.0.0/33").is_err());
+        assert!(Cidr::parse("example.com").is_err());
+        assert!(Cidr::parse("0.0.0.0/0")
+            .unwrap()
+            .contains([1, 2, 3, 4].into()));
+    }
+}
@@ -0,0 +1,866 @@
+use std::{
+    collections::{HashMap, VecDeque},
+    fs::File,
+    io::{self, Cursor, ErrorKind, Read, Write},
+    net::{Shutdown as Direction, SocketAddr},
+    os::{
+        fd::{AsRawFd, RawFd},
+        unix::{fs::FileExt, net::UnixStream},
+    },
+    sync::{
+        mpsc::{self, Receiver, Sender},
+        Arc, Mutex,
+    },
+    time::{Duration, Instant},
+};
+
+use crate::{
+    context::ServerContext,
+    dev,
+    http::{handle_connect, handle_redirect, reject, Redirect},
+    listener::Listener,
+    log_error, log_info,
+    proxy::{self, Header},
+    sendfile,
+    stream::{Connection, Stream},
+    thread::ThreadPool,
+};
+
+/*
+ * epoll的最小FFI封装（仅Linux）
+ */
+mod sys {
+    use std::{io, os::fd::RawFd};
+
+    pub const EPOLL_CLOEXEC: i32 = 0o2000000;
+    pub const EPOLL_CTL_ADD: i32 = 1;
+    pub const EPOLL_CTL_DEL: i32 = 2;
+    pub const EPOLL_CTL_MOD: i32 = 3;
+    pub const EPOLLIN: u32 = 0x001;
+    pub const EPOLLOUT: u32 = 0x004;
+    pub const EPOLLERR: u32 = 0x008;
+    pub const EPOLLHUP: u32 = 0x010;
+    pub const EPOLLONESHOT: u32 = 1 << 30;
+
+    //x86_64下内核的epoll_event是紧凑布局
+    #[repr(C)]
+    #[cfg_attr(target_arch = "x86_64", repr(packed))]
+    #[derive(Clone, Copy)]
+    pub struct EpollEvent {
+        pub events: u32,
+        pub data: u64,
+    }
+
+    extern "C" {
+        fn epoll_create1(flags: i32) -> i32;
+        fn epoll_ctl(epfd: i32, op: i32, fd: i32, event: *mut EpollEvent) -> i32;
+        fn epoll_wait(epfd: i32, events: *mut EpollEvent, maxevents: i32, timeout: i32) -> i32;
+        fn close(fd: i32) -> i32;
+    }
+
+    pub struct Epoll {
+        fd: RawFd,
+    }
+
+    impl Epoll {
+        pub fn new() -> io::Result<Epoll> {
+            let fd = unsafe { epoll_create1(EPOLL_CLOEXEC) };
+            if fd < 0 {
+                return Err(io::Error::last_os_error());
+            }
+            Ok(Epoll { fd })
+        }
+
+        pub fn ctl(&self, op: i32, fd: RawFd, events: u32, token: u64) -> io::Result<()> {
+            let mut event = EpollEvent {
+                events,
+                data: token,
+            };
+            if unsafe { epoll_ctl(self.fd, op, fd, &mut event) } < 0 {
+                return Err(io::Error::last_os_error());
+            }
+            Ok(())
+        }
+
+        pub fn wait(&self, events: &mut [EpollEvent], timeout_ms: i32) -> io::Result<usize> {
+            let n = unsafe {
+                epoll_wait(
+                    self.fd,
+                    events.as_mut_ptr(),
+                    events.len() as i32,
+                    timeout_ms,
+                )
+            };
+            if n < 0 {
+                let e = io::Error::last_os_error();
+                if e.kind() == io::ErrorKind::Interrupted {
+                    return Ok(0);
+                }
+                return Err(e);
+            }
+            Ok(n as usize)
+        }
+    }
+
+    impl Drop for Epoll {
+        fn drop(&mut self) {
+            unsafe {
+                close(self.fd);
+            }
+        }
+    }
+}
+
+use sys::{Epoll, EpollEvent};
+
+const LISTENER: u64 = 0;
+const WAKER: u64 = 1;
+const SHUTDOWN: u64 = 2;
+//请求头最大长度
+const MAX_HEAD_SIZE: usize = 64 * 1024;
+//读取请求头的超时时间
+const READ_TIMEOUT: Duration = Duration::from_secs(30);
+//不支持sendfile时每次读出的文件块大小
+const FILE_BLOCK: u64 = 64 * 1024;
+
+enum State {
+    //读取请求头，以及已查找过请求头结束标记的长度
+    Reading(Vec<u8>, usize),
+    //线程池处理中
+    Processing,
+    //写出响应，以及首个片段已写出的长度
+    Writing(VecDeque<Chunk>, usize),
+}
+
+//响应片段：内存中的数据，或由事件循环直接从文件发送的区间
+enum Chunk {
+    Bytes(Vec<u8>),
+    File(File, u64, u64),
+}
+
+struct Conn {
+    stream: Stream,
+    state: State,
+    accepted: Instant,
+    //尚未读取PROXY协议头
+    proxy_pending: bool,
+    //PROXY协议头中的客户端地址
+    client: Option<SocketAddr>,
+}
+
+/*
+ * 线程池处理用的连接：请求来自已读取的缓冲，响应头等数据写入内存，
+ * 文件内容只记录区间，由事件循环非阻塞写出，避免大文件整个读入内存
+ */
+struct BufferedConnection {
+    input: Cursor<Vec<u8>>,
+    output: Vec<Chunk>,
+    peer: String,
+}
+
+impl Read for BufferedConnection {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.input.read(buf)
+    }
+}
+
+impl Write for BufferedConnection {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self.output.last_mut() {
+            Some(Chunk::Bytes(bytes)) => bytes.extend_from_slice(buf),
+            _ => self.output.push(Chunk::Bytes(buf.to_vec())),
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Connection for BufferedConnection {
+    fn shutdown(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+
+    fn defer_file(&mut self, file: &File, offset: u64, len: u64) -> io::Result<bool> {
+        self.output
+            .push(Chunk::File(file.try_clone()?, offset, len));
+        Ok(true)
+    }
+
+    fn peer_addr(&self) -> String {
+        self.peer.clone()
+    }
+}
+
+/*
+ * 需要长连接的请求（SSE、HTTP/2、协议升级）交还给阻塞模型，
+ * 先返回已读取的请求头再继续读取套接字
+ */
+struct PrefixedStream {
+    prefix: Cursor<Vec<u8>>,
+    stream: Stream,
+    peer: String,
+}
+
+impl Read for PrefixedStream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if (self.prefix.position() as usize) < self.prefix.get_ref().len() {
+            return self.prefix.read(buf);
+        }
+        self.stream.read(buf)
+    }
+}
+
+impl Write for PrefixedStream {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.stream.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.stream.flush()
+    }
+}
+
+impl Connection for PrefixedStream {
+    fn shutdown(&mut self) -> io::Result<()> {
+        self.stream.shutdown_direction(Direction::Both)
+    }
+
+    fn raw_fd(&self) -> Option<RawFd> {
+        Some(self.stream.as_raw_fd())
+    }
+
+    fn peer_addr(&self) -> String {
+        self.peer.clone()
+    }
+}
+
+pub struct Reactor {
+    epoll: Epoll,
+    listener: Listener,
+    pool: Arc<ThreadPool>,
+    ctx: Arc<ServerContext>,
+    conns: HashMap<u64, Conn>,
+    next_token: u64,
+    waker: UnixStream,
+    wake_sender: Arc<Mutex<UnixStream>>,
+    done_sender: Sender<(u64, Vec<Chunk>)>,
+    done_receiver: Receiver<(u64, Vec<Chunk>)>,
+    redirect: Option<Redirect>,
+    //连接开头带有PROXY协议头
+    proxy_protocol: bool,
+    //开始关闭后不再接收连接，等待已有连接处理完毕
+    draining: bool,
+}
+
+impl Reactor {
+    pub fn new(
+        listener: Listener,
+        pool: Arc<ThreadPool>,
+        ctx: Arc<ServerContext>,
+        redirect: Option<Redirect>,
+        proxy_protocol: bool,
+    ) -> io::Result<Reactor> {
+        let epoll = Epoll::new()?;
+        listener.set_nonblocking(true)?;
+        epoll.ctl(
+            sys::EPOLL_CTL_ADD,
+            listener.as_raw_fd(),
+            sys::EPOLLIN,
+            LISTENER,
+        )?;
+        //工作线程处理完成后通过该套接字唤醒事件循环
+        let (waker, wake_sender) = UnixStream::pair()?;
+        waker.set_nonblocking(true)?;
+        wake_sender.set_nonblocking(true)?;
+        epoll.ctl(sys::EPOLL_CTL_ADD, waker.as_raw_fd(), sys::EPOLLIN, WAKER)?;
+        epoll.ctl(
+            sys::EPOLL_CTL_ADD,
+            ctx.shutdown.notify_fd(),
+            sys::EPOLLIN,
+            SHUTDOWN,
+        )?;
+        let (done_sender, done_receiver) = mpsc::channel();
+        Ok(Reactor {
+            epoll,
+            listener,
+            pool,
+            ctx,
+            conns: HashMap::new(),
+            next_token: SHUTDOWN + 1,
+            waker,
+            wake_sender: Arc::new(Mutex::new(wake_sender)),
+            done_sender,
+            done_receiver,
+            redirect,
+            proxy_protocol,
+            draining: false,
+        })
+    }
+
+    pub fn run(&mut self) -> io::Result<()> {
+        log_info!("Event-driven I/O model started");
+        let mut events = vec![EpollEvent { events: 0, data: 0 }; 256];
+        loop {
+            self.poll(&mut events, 1000)?;
+            if self.draining && self.conns.is_empty() {
+                return Ok(());
+            }
+        }
+    }
+
+    //等待并处理一轮事件，返回事件数
+    fn poll(&mut self, events: &mut [EpollEvent], timeout_ms: i32) -> io::Result<usize> {
+        let n = self.epoll.wait(events, timeout_ms)?;
+        for event in events.iter().take(n) {
+            let token = event.data;
+            let flags = event.events;
+            match token {
+                LISTENER => self.accept(),
+                WAKER => self.complete(),
+                SHUTDOWN => self.drain()?,
+                token => self.ready(token, flags),
+            }
+        }
+        self.reap_timeouts();
+        Ok(n)
+    }
+
+    //停止接收新连接，关闭还没有收到请求数据的空闲连接，已开始发送请求的继续处理
+    fn drain(&mut self) -> io::Result<()> {
+        if self.draining {
+            return Ok(());
+        }
+        self.draining = true;
+        self.epoll
+            .ctl(sys::EPOLL_CTL_DEL, self.listener.as_raw_fd(), 0, LISTENER)?;
+        self.epoll.ctl(
+            sys::EPOLL_CTL_DEL,
+            self.ctx.shutdown.notify_fd(),
+            0,
+            SHUTDOWN,
+        )?;
+        let reading: Vec<u64> = self
+            .conns
+            .iter()
+            .filter(|(_, conn)| matches!(&conn.state, State::Reading(buf, _) if buf.is_empty()))
+            .map(|(token, _)| *token)
+            .collect();
+        for token in reading {
+            self.close(token);
+        }
+        Ok(())
+    }
+
+    fn accept(&mut self) {
+        loop {
+            match self.listener.accept() {
+                Ok(stream) => {
+                    let peer = stream.peer_addr();
+                    log_info!(peer = &peer; "Connect Incoming! {}", peer);
+                    if let Err(e) = self.register(stream) {
+                        log_error!("Failed to register connection. Error:{}", e);
+                    }
+                }
+                Err(e) if e.kind() == ErrorKind::WouldBlock => return,
+                Err(e) => {
+                    log_error!("Connect Incoming Error:{}", e);
+                    return;
+                }
+            }
+        }
+    }
+
+    fn register(&mut self, stream: Stream) -> io::Result<()> {
+        stream.set_nonblocking(true)?;
+        let token = self.next_token;
+        self.next_token += 1;
+        self.epoll
+            .ctl(sys::EPOLL_CTL_ADD, stream.as_raw_fd(), sys::EPOLLIN, token)?;
+        self.conns.insert(
+            token,
+            Conn {
+                stream,
+                state: State::Reading(Vec::new(), 0),
+                accepted: Instant::now(),
+                proxy_pending: self.proxy_protocol,
+                client: None,
+            },
+        );
+        Ok(())
+    }
+
+    fn ready(&mut self, token: u64, flags: u32) {
+        let conn = match self.conns.get_mut(&token) {
+            Some(conn) => conn,
+            None => return,
+        };
+        let result = match &mut conn.state {
+            State::Reading(buf, scanned) => read_head(
+                &mut conn.stream,
+                buf,
+                scanned,
+                &mut conn.proxy_pending,
+                &mut conn.client,
+            ),
+            State::Writing(out, pos) => write_out(&mut conn.stream, out, pos).map(|done| {
+                if done {
+                    Step::Close
+                } else {
+                    Step::Wait
+                }
+            }),
+            State::Processing => Ok(Step::Wait),
+        };
+        let step = match result {
+            Ok(step) => step,
+            Err(_) => Step::Close,
+        };
+        let step = match step {
+            Step::Wait if flags & (sys::EPOLLERR | sys::EPOLLHUP) != 0 => match conn.state {
+                State::Processing => Step::Wait,
+                _ => Step::Close,
+            },
+            step => step,
+        };
+        match step {
+            Step::Wait => {}
+            Step::Close => self.close(token),
+            Step::Dispatch => self.dispatch(token),
+        }
+    }
+
+    //请求头读取完毕，交给线程池处理
+    fn dispatch(&mut self, token: u64) {
+        let conn = match self.conns.get_mut(&token) {
+            Some(conn) => conn,
+            None => return,
+        };
+        let head = match std::mem::replace(&mut conn.state, State::Processing) {
+            State::Reading(buf, _) => buf,
+            _ => return,
+        };
+        let peer = conn
+            .client
+            .map_or_else(|| conn.stream.peer_addr(), |client| client.to_string());
+        let fd = conn.stream.as_raw_fd();
+        if needs_stream(&self.ctx, &head) {
+            //长连接请求移出事件循环，由工作线程阻塞处理
+            let conn = self.deregister(token);
+            if let Some(conn) = conn {
+                if conn.stream.set_nonblocking(false).is_ok() {
+                    let stream = PrefixedStream {
+                        prefix: Cursor::new(head),
+                        stream: conn.stream,
+                        peer,
+                    };
+                    let ctx = Arc::clone(&self.ctx);
+                    let redirect = self.redirect.clone();
+                    let tracker = ctx.shutdown.track(&stream.stream);
+                    let result = self.pool.try_exec(stream, move |stream| {
+                        let _log = ctx.log_scope();
+                        tracker.start();
+                        match redirect {
+                            Some(redirect) => handle_redirect(stream, &ctx, &redirect),
+                            None => handle_connect(stream, &ctx),
+                        }
+                    });
+                    if let Err(stream) = result {
+                        reject(stream, &self.ctx);
+                    }
+                }
+            }
+            return;
+        }
+        /*
+         * 处理期间不再关注可读事件，否则客户端继续发送数据或半关闭时水平触发会让事件循环空转；
+         * EPOLLHUP/EPOLLERR总会上报，EPOLLONESHOT保证只上报一次，start_writing时重新注册
+         */
+        if let Err(e) = self
+            .epoll
+            .ctl(sys::EPOLL_CTL_MOD, fd, sys::EPOLLONESHOT, token)
+        {
+            log_error!("Failed to disarm connection. Error:{}", e);
+            self.close(token);
+            return;
+        }
+        let done_sender = self.done_sender.clone();
+        let wake_sender = Arc::clone(&self.wake_sender);
+        let redirect = self.redirect.clone();
+        let ctx = Arc::clone(&self.ctx);
+        let result = self.pool.try_exec(head, move |head| {
+            let _log = ctx.log_scope();
+            let mut connection = BufferedConnection {
+                input: Cursor::new(head),
+                output: Vec::new(),
+                peer,
+            };
+            match redirect {
+                Some(redirect) => handle_redirect(&mut connection, &ctx, &redirect),
+                None => handle_connect(&mut connection, &ctx),
+            }
+            if done_sender.send((token, connection.output)).is_ok() {
+                if let Ok(mut wake_sender) = wake_sender.lock() {
+                    let _ = wake_sender.write(&[1]);
+                }
+            }
+        });
+        //队列已满，由事件循环直接写出503
+        if result.is_err() {
+            let mut connection = BufferedConnection {
+                input: Cursor::new(Vec::new()),
+                output: Vec::new(),
+                peer: String::from("-"),
+            };
+            reject(&mut connection, &self.ctx);
+            self.start_writing(token, connection.output);
+        }
+    }
+
+    //取回工作线程生成的响应并开始写出
+    fn complete(&mut self) {
+        let mut buf = [0u8; 256];
+        while let Ok(n) = self.waker.read(&mut buf) {
+            if n == 0 {
+                break;
+            }
+        }
+        while let Ok((token, output)) = self.done_receiver.try_recv() {
+            self.start_writing(token, output);
+        }
+    }
+
+    fn start_writing(&mut self, token: u64, output: Vec<Chunk>) {
+        let conn = match self.conns.get_mut(&token) {
+            Some(conn) => conn,
+            None => return,
+        };
+        conn.state = State::Writing(output.into(), 0);
+        let fd = conn.stream.as_raw_fd();
+        if self
+            .epoll
+            .ctl(sys::EPOLL_CTL_MOD, fd, sys::EPOLLOUT, token)
+            .is_err()
+        {
+            self.close(token);
+            return;
+        }
+        self.ready(token, 0);
+    }
+
+    fn reap_timeouts(&mut self) {
+        let expired: Vec<u64> = self
+            .conns
+            .iter()
+            .filter(|(_, conn)| {
+                matches!(conn.state, State::Reading(..)) && conn.accepted.elapsed() > READ_TIMEOUT
+            })
+            .map(|(token, _)| *token)
+            .collect();
+        for token in expired {
+            log_error!("Request header read timeout, closing connection");
+            self.close(token);
+        }
+    }
+
+    fn deregister(&mut self, token: u64) -> Option<Conn> {
+        let conn = self.conns.remove(&token)?;
+        let fd: RawFd = conn.stream.as_raw_fd();
+        if let Err(e) = self.epoll.ctl(sys::EPOLL_CTL_DEL, fd, 0, token) {
+            log_error!("Failed to deregister connection. Error:{}", e);
+        }
+        Some(conn)
+    }
+
+    fn close(&mut self, token: u64) {
+        if let Some(conn) = self.deregister(token) {
+            let _ = conn.stream.shutdown_direction(Direction::Both);
+        }
+    }
+}
+
+enum Step {
+    Wait,
+    Dispatch,
+    Close,
+}
+
+//开启PROXY协议时先从缓冲中取出PROXY协议头，其中的CRLF不算作请求头的结束
+fn read_head(
+    stream: &mut Stream,
+    buf: &mut Vec<u8>,
+    scanned: &mut usize,
+    proxy_pending: &mut bool,
+    client: &mut Option<SocketAddr>,
+) -> io::Result<Step> {
+    let mut chunk = [0u8; 4096];
+    loop {
+        match stream.read(&mut chunk) {
+            Ok(0) => return Ok(Step::Close),
+            Ok(n) => {
+                buf.extend_from_slice(&chunk[..n]);
+                if *proxy_pending {
+                    match proxy::parse(buf) {
+                        Ok(Header::Incomplete(_)) => continue,
+                        Ok(Header::Complete(len, addr)) => {
+                            buf.drain(..len);
+                            *client = addr;
+                            *proxy_pending = false;
+                        }
+                        Err(e) => {
+                            log_error!("Invalid PROXY protocol header! Err:{}", e);
+                            return Ok(Step::Close);
+                        }
+                    }
+                }
+                if head_complete(buf, scanned) {
+                    return Ok(Step::Dispatch);
+                }
+                if buf.len() > MAX_HEAD_SIZE {
+                    log_error!("Request header too large, closing connection");
+                    return Ok(Step::Close);
+                }
+            }
+            Err(e) if e.kind() == ErrorKind::WouldBlock => return Ok(Step::Wait),
+            Err(e) if e.kind() == ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+//依次写出响应片段，返回是否已写完
+fn write_out(stream: &mut Stream, out: &mut VecDeque<Chunk>, pos: &mut usize) -> io::Result<bool> {
+    while let Some(chunk) = out.front_mut() {
+        let done = match chunk {
+            Chunk::Bytes(bytes) => write_bytes(stream, bytes, pos)?,
+            Chunk::File(file, offset, len) => match write_file(stream, file, offset, len) {
+                //目标不支持sendfile时读出一块，作为内存数据写出
+                Err(e) if e.kind() == ErrorKind::Unsupported => {
+                    let mut block = vec![0u8; (*len).min(FILE_BLOCK) as usize];
+                    let n = file.read_at(&mut block, *offset)?;
+                    if n == 0 {
+                        return Err(ErrorKind::UnexpectedEof.into());
+                    }
+                    block.truncate(n);
+                    *offset += n as u64;
+                    *len -= n as u64;
+                    if *len == 0 {
+                        out.pop_front();
+                    }
+                    out.push_front(Chunk::Bytes(block));
+                    continue;
+                }
+                result => result?,
+            },
+        };
+        if !done {
+            return Ok(false);
+        }
+        out.pop_front();
+        *pos = 0;
+    }
+    Ok(true)
+}
+
+fn write_bytes(stream: &mut Stream, bytes: &[u8], pos: &mut usize) -> io::Result<bool> {
+    while *pos < bytes.len() {
+        match stream.write(&bytes[*pos..]) {
+            Ok(0) => return Err(ErrorKind::WriteZero.into()),
+            Ok(n) => *pos += n,
+            Err(e) if e.kind() == ErrorKind::WouldBlock => return Ok(false),
+            Err(e) if e.kind() == ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(true)
+}
+
+//非阻塞sendfile，offset与len随发送推进
+fn write_file(stream: &Stream, file: &File, offset: &mut u64, len: &mut u64) -> io::Result<bool> {
+    let end = *offset + *len;
+    let result = sendfile::send_file_from(stream.as_raw_fd(), file, offset, end);
+    *len = end - *offset;
+    match result {
+        Ok(()) => Ok(true),
+        Err(e) if e.kind() == ErrorKind::WouldBlock => Ok(false),
+        Err(e) => Err(e),
+    }
+}
+
+//只查找新读入的数据（向前多取3字节以覆盖跨越两次读取的结束标记）
+fn head_complete(buf: &[u8], scanned: &mut usize) -> bool {
+    let from = scanned.saturating_sub(3);
+    *scanned = buf.len();
+    buf[from..].windows(4).any(|w| w == b"\r\n\r\n")
+}
+
+/*
+ * 判断请求是否需要保持套接字（不能在内存中生成完整响应）
+ */
+fn needs_stream(ctx: &ServerContext, head: &[u8]) -> bool {
+    let head = String::from_utf8_lossy(head);
+    let mut lines = head.split("\r\n");
+    let first_line = lines.next().unwrap_or_default();
+    if first_line.starts_with("PRI ") {
+        return true;
+    }
+    let mut parts = first_line.split_whitespace();
+    let method = parts.next().unwrap_or_default();
+    let url = parts.next().unwrap_or_default();
+    if !method.eq_ignore_ascii_case("get") {
+        return true;
+    }
+    let config = ctx.config();
+    if config.sse_path.as_deref() == Some(url) || (config.dev && url == dev::LIVE_RELOAD_PATH) {
+        return true;
+    }
+    lines.any(|line| {
+        line.split(':')
+            .next()
+            .is_some_and(|name| name.trim().eq_ignore_ascii_case("upgrade"))
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use std::{
+        env, fs,
+        io::{Read, Write},
+        net::{TcpListener, TcpStream},
+        sync::Arc,
+        thread,
+        time::Duration,
+    };
+
+    use super::*;
+    use crate::http::{FileRange, ResponseBody};
+
+    #[test]
+    fn test_needs_stream() {
+        let ctx = ServerContext::load();
+        assert!(needs_stream(&ctx, b"PRI * HTTP/2.0\r\n\r\n"));
+        assert!(needs_stream(
+            &ctx,
+            b"GET / HTTP/1.1\r\nUpgrade: h2c\r\nHTTP2-Settings: \r\n\r\n"
+        ));
+        assert!(!needs_stream(&ctx, b"GET / HTTP/1.1\r\nHost: a\r\n\r\n"));
+        let mut scanned = 0;
+        assert!(!head_complete(b"GET / HTTP/1.1\r\n", &mut scanned));
+        assert_eq!(scanned, 16);
+        assert!(head_complete(b"GET / HTTP/1.1\r\n\r\n", &mut scanned));
+    }
+
+    //慢客户端不占用工作线程：一个工作线程也能服务其它请求
+    #[test]
+    fn test_slow_client() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let ctx = ServerContext::load();
+        let pool = Arc::new(
+            ThreadPool::new(1, 1, Duration::from_secs(60), 16, Arc::clone(&ctx.stats)).unwrap(),
+        );
+        thread::spawn(move || {
+            Reactor::new(Listener::Tcp(listener), pool, ctx, None, false)
+                .unwrap()
+                .run()
+        });
+        let mut slow = TcpStream::connect(addr).unwrap();
+        slow.write_all(b"GET / HTTP/1.1\r\n").unwrap();
+        let mut fast = TcpStream::connect(addr).unwrap();
+        fast.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+        fast.write_all(b"GET / HTTP/1.1\r\nHost: a\r\n\r\n")
+            .unwrap();
+        let mut response = String::new();
+        fast.read_to_string(&mut response).unwrap();
+        assert!(response.starts_with("HTTP/1.0 200 OK"));
+        slow.write_all(b"\r\n").unwrap();
+        let mut response = String::new();
+        slow.read_to_string(&mut response).unwrap();
+        assert!(response.starts_with("HTTP/1.0 200 OK"));
+    }
+
+    //文件内容不复制到内存，由事件循环经sendfile分多次写出
+    #[test]
+    fn test_file_chunk() {
+        let path = env::temp_dir().join(format!("star-http-chunk-{}", std::process::id()));
+        let data: Vec<u8> = (0..1_000_000u32).map(|i| i as u8).collect();
+        fs::write(&path, &data).unwrap();
+        let mut connection = BufferedConnection {
+            input: Cursor::new(Vec::new()),
+            output: Vec::new(),
+            peer: String::from("-"),
+        };
+        connection.write_all(b"head").unwrap();
+        FileRange {
+            file: Arc::new(File::open(&path).unwrap()),
+            offset: 10,
+            len: 900_000,
+        }
+        .send_to(&mut connection)
+        .unwrap();
+        assert!(matches!(connection.output[1], Chunk::File(_, 10, 900_000)));
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let mut client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let mut stream = Stream::Tcp(listener.accept().unwrap().0);
+        stream.set_nonblocking(true).unwrap();
+        let reader = thread::spawn(move || {
+            let mut received = Vec::new();
+            client.read_to_end(&mut received).unwrap();
+            received
+        });
+        let mut out = VecDeque::from(connection.output);
+        let mut pos = 0;
+        while !write_out(&mut stream, &mut out, &mut pos).unwrap() {
+            thread::sleep(Duration::from_millis(1));
+        }
+        drop(stream);
+        let received = reader.join().unwrap();
+        assert_eq!(&received[..4], b"head");
+        assert!(received[4..] == data[10..900_010]);
+        fs::remove_file(path).unwrap();
+    }
+
+    //处理期间客户端半关闭或继续发送数据时事件循环应阻塞等待，而不是空转
+    #[test]
+    fn test_half_close_while_processing() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let ctx = ServerContext::load();
+        let pool = Arc::new(
+            ThreadPool::new(1, 1, Duration::from_secs(60), 16, Arc::clone(&ctx.stats)).unwrap(),
+        );
+        //占住唯一的工作线程，使请求停留在处理中
+        let (release, blocked) = mpsc::channel::<()>();
+        assert!(pool
+            .try_exec(blocked, |blocked| {
+                let _ = blocked.recv_timeout(Duration::from_secs(5));
+            })
+            .is_ok());
+        let mut reactor = Reactor::new(Listener::Tcp(listener), pool, ctx, None, false).unwrap();
+        let mut events = vec![EpollEvent { events: 0, data: 0 }; 16];
+        let mut client = TcpStream::connect(addr).unwrap();
+        client.write_all(b"GET / HTTP/1.1\r\n\r\n").unwrap();
+        for _ in 0..50 {
+            if reactor
+                .conns
+                .values()
+                .any(|conn| matches!(conn.state, State::Processing))
+            {
+                break;
+            }
+            reactor.poll(&mut events, 100).unwrap();
+        }
+        assert!(reactor
+            .conns
+            .values()
+            .any(|conn| matches!(conn.state, State::Processing)));
+        client.write_all(b"GET / HTTP/1.1\r\n\r\n").unwrap();
+        client.shutdown(Direction::Write).unwrap();
+        for _ in 0..3 {
+            assert_eq!(reactor.poll(&mut events, 100).unwrap(), 0);
+        }
+        release.send(()).unwrap();
+    }
+}
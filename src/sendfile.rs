@@ -0,0 +1,96 @@
+use std::{
+    fs::File,
+    io::{self, ErrorKind},
+    os::fd::{AsRawFd, RawFd},
+};
+
+extern "C" {
+    fn sendfile(out_fd: i32, in_fd: i32, offset: *mut i64, count: usize) -> isize;
+}
+
+const EINVAL: i32 = 22;
+const ENOSYS: i32 = 38;
+//单次sendfile最大传输量
+const CHUNK: u64 = 1 << 30;
+
+/*
+ * 通过sendfile(2)把文件的[offset, offset+len)区间直接写入套接字，处理部分写入
+ * 目标不支持sendfile且尚未发送任何数据时返回Unsupported，由调用方回退为缓冲复制
+ */
+pub fn send_file(out_fd: RawFd, file: &File, offset: u64, len: u64) -> io::Result<()> {
+    let mut pos = offset;
+    send_file_from(out_fd, file, &mut pos, offset + len)
+}
+
+//从pos发送到end并推进pos，非阻塞套接字返回WouldBlock时pos为已发送到的位置
+pub fn send_file_from(out_fd: RawFd, file: &File, pos: &mut u64, end: u64) -> io::Result<()> {
+    let start = *pos;
+    while *pos < end {
+        let count = (end - *pos).min(CHUNK) as usize;
+        let mut offset = *pos as i64;
+        let n = unsafe { sendfile(out_fd, file.as_raw_fd(), &mut offset, count) };
+        *pos = offset as u64;
+        if n < 0 {
+            let e = io::Error::last_os_error();
+            match e.raw_os_error() {
+                _ if e.kind() == ErrorKind::Interrupted => continue,
+                Some(EINVAL) | Some(ENOSYS) if *pos == start => {
+                    return Err(ErrorKind::Unsupported.into())
+                }
+                _ => return Err(e),
+            }
+        }
+        if n == 0 {
+            //文件在发送过程中被截断
+            return Err(ErrorKind::UnexpectedEof.into());
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use std::{
+        env, fs,
+        io::Read,
+        net::{TcpListener, TcpStream},
+        thread,
+    };
+
+    use super::*;
+
+    fn temp_file(name: &str, data: &[u8]) -> (std::path::PathBuf, File) {
+        let path = env::temp_dir().join(format!("star-http-{}-{}", name, std::process::id()));
+        fs::write(&path, data).unwrap();
+        let file = File::open(&path).unwrap();
+        (path, file)
+    }
+
+    //连接两端，返回写端与读端读到的全部数据
+    fn drain() -> (TcpStream, thread::JoinHandle<Vec<u8>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let writer = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let (mut reader, _) = listener.accept().unwrap();
+        let handle = thread::spawn(move || {
+            let mut data = Vec::new();
+            reader.read_to_end(&mut data).unwrap();
+            data
+        });
+        (writer, handle)
+    }
+
+    #[test]
+    fn test_send_file_range() {
+        let data: Vec<u8> = (0..200_000u32).map(|i| i as u8).collect();
+        let (path, file) = temp_file("range", &data);
+        let (writer, handle) = drain();
+        send_file(writer.as_raw_fd(), &file, 1000, 150_000).unwrap();
+        drop(writer);
+        assert_eq!(handle.join().unwrap(), &data[1000..151_000]);
+        let (writer, handle) = drain();
+        assert!(send_file(writer.as_raw_fd(), &file, 199_990, 20).is_err());
+        drop(writer);
+        assert_eq!(handle.join().unwrap(), &data[199_990..]);
+        fs::remove_file(path).unwrap();
+    }
+}
@@ -0,0 +1,222 @@
+use std::{
+    cell::RefCell,
+    collections::BTreeMap,
+    io::Write,
+    net::Shutdown as Direction,
+    os::{
+        fd::{AsRawFd, RawFd},
+        unix::net::UnixStream,
+    },
+    sync::{
+        atomic::{AtomicBool, AtomicU64, Ordering},
+        Arc, Mutex, OnceLock,
+    },
+};
+
+use crate::{log_error, log_info, stream::Stream};
+
+/*
+ * 优雅关闭：停止接收新连接，关闭空闲连接，排队中与处理中的请求继续完成
+ */
+pub struct Shutdown {
+    started: AtomicBool,
+    next_id: AtomicU64,
+    //接收后尚未结束的连接
+    conns: Mutex<BTreeMap<u64, Tracked>>,
+    //关闭开始后变为可读，用于唤醒阻塞在poll/epoll中的接收循环
+    notifier: OnceLock<(UnixStream, UnixStream)>,
+}
+
+struct Tracked {
+    stream: Stream,
+    //是否在两次请求之间等待，只有空闲的连接在关闭时被中断
+    idle: bool,
+}
+
+thread_local! {
+    //当前线程正在处理的连接
+    static CURRENT: RefCell<Option<(Arc<Shutdown>, u64)>> = const { RefCell::new(None) };
+}
+
+impl Shutdown {
+    pub const fn new() -> Shutdown {
+        Shutdown {
+            started: AtomicBool::new(false),
+            next_id: AtomicU64::new(0),
+            conns: Mutex::new(BTreeMap::new()),
+            notifier: OnceLock::new(),
+        }
+    }
+
+    fn notifier(&self) -> &(UnixStream, UnixStream) {
+        self.notifier.get_or_init(|| match UnixStream::pair() {
+            Ok(pair) => pair,
+            Err(e) => panic!("Failed to create shutdown notifier. Error:{}", e),
+        })
+    }
+
+    //关闭开始后可读的描述符
+    pub fn notify_fd(&self) -> RawFd {
+        self.notifier().0.as_raw_fd()
+    }
+
+    pub fn is_started(&self) -> bool {
+        self.started.load(Ordering::Relaxed)
+    }
+
+    /*
+     * 接收连接后、放入任务队列前登记，返回的Tracker随任务移交工作线程，释放时注销
+     * 已登记的连接即使关闭开始时仍在排队，其请求也会被处理
+     */
+    pub fn track(self: &Arc<Self>, stream: &Stream) -> Tracker {
+        let id = match stream.try_clone() {
+            Ok(stream) => {
+                let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+                let mut conns = self.conns.lock().unwrap_or_else(|e| e.into_inner());
+                conns.insert(
+                    id,
+                    Tracked {
+                        stream,
+                        idle: false,
+                    },
+                );
+                Some(id)
+            }
+            Err(_) => None,
+        };
+        Tracker {
+            owner: Arc::clone(self),
+            id,
+        }
+    }
+
+    fn set_idle(&self, id: u64, idle: bool) {
+        let mut conns = self.conns.lock().unwrap_or_else(|e| e.into_inner());
+        if let Some(conn) = conns.get_mut(&id) {
+            conn.idle = idle;
+            if idle && self.is_started() {
+                let _ = conn.stream.shutdown_direction(Direction::Read);
+            }
+        }
+    }
+
+    /*
+     * 开始关闭：唤醒接收循环，并关闭空闲连接的读方向，阻塞等待下一个请求的读取随即返回，
+     * 排队中与正在读写请求的连接不受影响，它们之后进入空闲状态时再关闭
+     */
+    pub fn begin(&self) {
+        if self.started.swap(true, Ordering::Relaxed) {
+            return;
+        }
+        log_info!("Graceful shutdown started");
+        if let Err(e) = (&self.notifier().1).write_all(&[1]) {
+            log_error!("Failed to notify shutdown. Error:{}", e);
+        }
+        let conns = self.conns.lock().unwrap_or_else(|e| e.into_inner());
+        for conn in conns.values().filter(|conn| conn.idle) {
+            let _ = conn.stream.shutdown_direction(Direction::Read);
+        }
+    }
+}
+
+pub struct Tracker {
+    owner: Arc<Shutdown>,
+    id: Option<u64>,
+}
+
+impl Tracker {
+    //工作线程开始处理该连接，之后可通过idle标记空闲状态
+    pub fn start(&self) {
+        if let Some(id) = self.id {
+            CURRENT.with(|current| *current.borrow_mut() = Some((Arc::clone(&self.owner), id)));
+        }
+    }
+}
+
+impl Drop for Tracker {
+    fn drop(&mut self) {
+        if let Some(id) = self.id {
+            let _ = CURRENT.try_with(|current| {
+                let mut current = current.borrow_mut();
+                if current.as_ref().is_some_and(|(_, current)| *current == id) {
+                    *current = None;
+                }
+            });
+            let mut conns = self.owner.conns.lock().unwrap_or_else(|e| e.into_inner());
+            conns.remove(&id);
+        }
+    }
+}
+
+/*
+ * 标记当前线程处理的连接是否在等待下一个请求（如没有活动流的HTTP/2连接），
+ * 关闭已开始时标记为空闲的连接立即关闭读方向
+ */
+pub fn idle(idle: bool) {
+    let current = CURRENT.with(|current| current.borrow().clone());
+    if let Some((owner, id)) = current {
+        owner.set_idle(id, idle);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{
+        io::{Read, Write},
+        net::{TcpListener, TcpStream},
+        time::Duration,
+    };
+
+    use super::*;
+    use crate::signal;
+
+    fn connect(listener: &TcpListener) -> (TcpStream, Stream) {
+        let client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let stream = listener.accept().unwrap().0;
+        stream
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+        (client, Stream::from(stream))
+    }
+
+    #[test]
+    fn test_begin_closes_idle() {
+        let shutdown = Arc::new(Shutdown::new());
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let (_client, mut stream) = connect(&listener);
+        let tracker = shutdown.track(&stream);
+        tracker.start();
+        idle(true);
+        let fd = [shutdown.notify_fd()];
+        assert_eq!(
+            signal::poll_readable(&fd, Some(Duration::ZERO)).unwrap(),
+            vec![false]
+        );
+        shutdown.begin();
+        assert!(shutdown.is_started());
+        assert_eq!(signal::poll_readable(&fd, None).unwrap(), vec![true]);
+        //读方向已关闭，阻塞的读取立即返回
+        assert_eq!(stream.read(&mut [0u8; 1]).unwrap(), 0);
+        drop(tracker);
+        assert!(shutdown.conns.lock().unwrap().is_empty());
+        CURRENT.with(|current| assert!(current.borrow().is_none()));
+    }
+
+    //关闭开始时仍在排队的连接照常读取请求，处理完进入空闲后才关闭
+    #[test]
+    fn test_queued_connection_drained() {
+        let shutdown = Arc::new(Shutdown::new());
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let (mut client, mut stream) = connect(&listener);
+        let tracker = shutdown.track(&stream);
+        shutdown.begin();
+        client.write_all(b"GET / HTTP/1.1\r\n\r\n").unwrap();
+        tracker.start();
+        let mut request = [0u8; 18];
+        stream.read_exact(&mut request).unwrap();
+        assert_eq!(&request, b"GET / HTTP/1.1\r\n\r\n");
+        assert!(!shutdown.conns.lock().unwrap()[&0].idle);
+        idle(true);
+        assert_eq!(stream.read(&mut [0u8; 1]).unwrap(), 0);
+    }
+}
@@ -0,0 +1,149 @@
+use std::{
+    io::{self, Read},
+    os::{
+        fd::{AsRawFd, RawFd},
+        unix::net::UnixStream,
+    },
+    sync::{
+        atomic::{AtomicI32, Ordering},
+        Mutex, OnceLock,
+    },
+    time::Duration,
+};
+
+pub const SIGHUP: i32 = 1;
+pub const SIGINT: i32 = 2;
+pub const SIGUSR2: i32 = 12;
+pub const SIGTERM: i32 = 15;
+
+const POLLIN: i16 = 0x001;
+
+#[repr(C)]
+struct PollFd {
+    fd: i32,
+    events: i16,
+    revents: i16,
+}
+
+extern "C" {
+    fn signal(signum: i32, handler: usize) -> usize;
+    fn write(fd: i32, buf: *const u8, count: usize) -> isize;
+    fn poll(fds: *mut PollFd, nfds: std::ffi::c_ulong, timeout: i32) -> i32;
+}
+
+//信号处理函数写入的管道（self-pipe）
+static WRITE_FD: AtomicI32 = AtomicI32::new(-1);
+static PIPE: OnceLock<(Mutex<UnixStream>, UnixStream)> = OnceLock::new();
+
+extern "C" fn handle_signal(signum: i32) {
+    let fd = WRITE_FD.load(Ordering::Relaxed);
+    if fd >= 0 {
+        let byte = signum as u8;
+        //write是异步信号安全的，管道满时丢弃即可
+        unsafe {
+            write(fd, &byte, 1);
+        }
+    }
+}
+
+/*
+ * 安装信号处理，收到的信号通过wait()在普通线程中依次取出
+ */
+pub fn install(signums: &[i32]) -> io::Result<()> {
+    if PIPE.get().is_none() {
+        let (reader, writer) = UnixStream::pair()?;
+        writer.set_nonblocking(true)?;
+        WRITE_FD.store(writer.as_raw_fd(), Ordering::Relaxed);
+        let _ = PIPE.set((Mutex::new(reader), writer));
+    }
+    for signum in signums {
+        let handler: extern "C" fn(i32) = handle_signal;
+        //SIG_ERR
+        if unsafe { signal(*signum, handler as usize) } == usize::MAX {
+            return Err(io::Error::last_os_error());
+        }
+    }
+    Ok(())
+}
+
+//阻塞等待下一个信号
+pub fn wait() -> io::Result<i32> {
+    let (reader, _) = PIPE
+        .get()
+        .ok_or_else(|| io::Error::other("Signal handling is not installed"))?;
+    let mut reader = reader.lock().unwrap_or_else(|e| e.into_inner());
+    let mut byte = [0u8; 1];
+    loop {
+        match reader.read(&mut byte) {
+            Ok(0) => return Err(io::ErrorKind::UnexpectedEof.into()),
+            Ok(_) => return Ok(byte[0] as i32),
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+/*
+ * 等待任一描述符可读，返回各描述符是否可读
+ * timeout为None时一直等待
+ */
+pub fn poll_readable(fds: &[RawFd], timeout: Option<Duration>) -> io::Result<Vec<bool>> {
+    let mut poll_fds: Vec<PollFd> = fds
+        .iter()
+        .map(|fd| PollFd {
+            fd: *fd,
+            events: POLLIN,
+            revents: 0,
+        })
+        .collect();
+    let timeout = timeout.map_or(-1, |t| t.as_millis().min(i32::MAX as u128) as i32);
+    loop {
+        let n = unsafe {
+            poll(
+                poll_fds.as_mut_ptr(),
+                poll_fds.len() as std::ffi::c_ulong,
+                timeout,
+            )
+        };
+        if n >= 0 {
+            break;
+        }
+        let e = io::Error::last_os_error();
+        if e.kind() != io::ErrorKind::Interrupted {
+            return Err(e);
+        }
+    }
+    Ok(poll_fds.iter().map(|p| p.revents != 0).collect())
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::Write;
+
+    use super::*;
+
+    extern "C" {
+        fn raise(signum: i32) -> i32;
+    }
+
+    #[test]
+    fn test_signal_wait() {
+        install(&[SIGINT]).unwrap();
+        unsafe {
+            raise(SIGINT);
+        }
+        assert_eq!(wait().unwrap(), SIGINT);
+    }
+
+    #[test]
+    fn test_poll_readable() {
+        let (a, mut b) = UnixStream::pair().unwrap();
+        let fds = [a.as_raw_fd()];
+        assert_eq!(
+            poll_readable(&fds, Some(Duration::from_millis(10))).unwrap(),
+            vec![false]
+        );
+        b.write_all(b"x").unwrap();
+        assert_eq!(poll_readable(&fds, None).unwrap(), vec![true]);
+    }
+}
@@ -0,0 +1,162 @@
+use std::{
+    collections::VecDeque,
+    fmt::Display,
+    sync::{
+        mpsc::{self, Receiver, RecvTimeoutError, Sender},
+        Arc, Mutex,
+    },
+    time::Duration,
+};
+
+//断线重连时可补发的历史事件数量
+const HISTORY_SIZE: usize = 64;
+
+pub struct SseEvent {
+    pub id: u64,
+    pub event: Option<String>,
+    pub data: String,
+}
+
+impl Display for SseEvent {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "id: {}", self.id)?;
+        if let Some(event) = &self.event {
+            writeln!(f, "event: {}", event)?;
+        }
+        //多行数据需要拆成多个data字段
+        for line in self.data.split('\n') {
+            writeln!(f, "data: {}", line.trim_end_matches('\r'))?;
+        }
+        writeln!(f)
+    }
+}
+
+struct Channel {
+    next_id: u64,
+    history: VecDeque<Arc<SseEvent>>,
+    subscribers: Vec<Sender<Arc<SseEvent>>>,
+}
+
+/*
+ * 事件广播器，服务器其他模块通过publish推送事件，SSE连接通过subscribe接收
+ */
+pub struct Broadcaster {
+    channel: Mutex<Channel>,
+}
+
+impl Broadcaster {
+    pub const fn new() -> Broadcaster {
+        Broadcaster {
+            channel: Mutex::new(Channel {
+                next_id: 1,
+                history: VecDeque::new(),
+                subscribers: Vec::new(),
+            }),
+        }
+    }
+
+    //推送事件，返回事件ID
+    pub fn publish(&self, event: Option<&str>, data: &str) -> u64 {
+        let mut channel = self.channel.lock().unwrap_or_else(|e| e.into_inner());
+        let id = channel.next_id;
+        channel.next_id += 1;
+        let event = Arc::new(SseEvent {
+            id,
+            event: event.map(|e| e.to_owned()),
+            data: data.to_owned(),
+        });
+        if channel.history.len() == HISTORY_SIZE {
+            channel.history.pop_front();
+        }
+        channel.history.push_back(Arc::clone(&event));
+        //发送失败说明连接已断开，顺便清理
+        channel
+            .subscribers
+            .retain(|subscriber| subscriber.send(Arc::clone(&event)).is_ok());
+        id
+    }
+
+    //订阅事件，last_event_id之后的历史事件会先行补发
+    pub fn subscribe(&self, last_event_id: Option<u64>) -> Subscription {
+        let mut channel = self.channel.lock().unwrap_or_else(|e| e.into_inner());
+        let backlog = match last_event_id {
+            Some(last_event_id) => channel
+                .history
+                .iter()
+                .filter(|event| event.id > last_event_id)
+                .cloned()
+                .collect(),
+            None => VecDeque::new(),
+        };
+        let (sender, receiver) = mpsc::channel();
+        channel.subscribers.push(sender);
+        Subscription { backlog, receiver }
+    }
+
+    //断开所有订阅（服务器关闭时结束SSE连接）
+    pub fn close(&self) {
+        let mut channel = self.channel.lock().unwrap_or_else(|e| e.into_inner());
+        channel.subscribers.clear();
+    }
+}
+
+pub struct Subscription {
+    backlog: VecDeque<Arc<SseEvent>>,
+    receiver: Receiver<Arc<SseEvent>>,
+}
+
+impl Subscription {
+    /*
+     * 等待下一个事件，超时返回None（用于发送心跳）
+     * 广播器不再存在时返回Err
+     */
+    pub fn next(&mut self, timeout: Duration) -> Result<Option<Arc<SseEvent>>, RecvTimeoutError> {
+        if let Some(event) = self.backlog.pop_front() {
+            return Ok(Some(event));
+        }
+        match self.receiver.recv_timeout(timeout) {
+            Ok(event) => Ok(Some(event)),
+            Err(RecvTimeoutError::Timeout) => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::Duration;
+
+    use super::Broadcaster;
+
+    #[test]
+    fn test_event_frame() {
+        let broadcaster = Broadcaster::new();
+        let mut subscription = broadcaster.subscribe(None);
+        broadcaster.publish(Some("reload"), "line1\nline2");
+        let event = subscription
+            .next(Duration::from_millis(10))
+            .unwrap()
+            .unwrap();
+        assert_eq!(
+            event.to_string(),
+            "id: 1\nevent: reload\ndata: line1\ndata: line2\n\n"
+        );
+        assert!(subscription
+            .next(Duration::from_millis(10))
+            .unwrap()
+            .is_none());
+    }
+
+    #[test]
+    fn test_last_event_id() {
+        let broadcaster = Broadcaster::new();
+        for i in 0..5 {
+            broadcaster.publish(None, &i.to_string());
+        }
+        let mut subscription = broadcaster.subscribe(Some(3));
+        let timeout = Duration::from_millis(10);
+        assert_eq!(subscription.next(timeout).unwrap().unwrap().id, 4);
+        assert_eq!(subscription.next(timeout).unwrap().unwrap().id, 5);
+        assert!(subscription.next(timeout).unwrap().is_none());
+    }
+}
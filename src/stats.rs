@@ -0,0 +1,63 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+
+/*
+ * 服务器运行统计，各工作线程共享
+ */
+pub struct Stats {
+    pub cache_hits: AtomicU64,
+    pub cache_misses: AtomicU64,
+    pub open_file_hits: AtomicU64,
+    pub open_file_misses: AtomicU64,
+    //线程池中等待执行的任务数
+    pub queue_depth: AtomicU64,
+    //因队列已满被拒绝的连接数
+    pub queue_rejected: AtomicU64,
+}
+
+impl Stats {
+    pub const fn new() -> Stats {
+        Stats {
+            cache_hits: AtomicU64::new(0),
+            cache_misses: AtomicU64::new(0),
+            open_file_hits: AtomicU64::new(0),
+            open_file_misses: AtomicU64::new(0),
+            queue_depth: AtomicU64::new(0),
+            queue_rejected: AtomicU64::new(0),
+        }
+    }
+
+    pub fn incr(counter: &AtomicU64) {
+        counter.fetch_add(1, Ordering::Relaxed);
+    }
+
+    //JSON格式的统计快照
+    pub fn to_json(&self) -> String {
+        format!(
+            "{{\"cache_hits\":{},\"cache_misses\":{},\"open_file_hits\":{},\"open_file_misses\":{},\"queue_depth\":{},\"queue_rejected\":{}}}",
+            self.cache_hits.load(Ordering::Relaxed),
+            self.cache_misses.load(Ordering::Relaxed),
+            self.open_file_hits.load(Ordering::Relaxed),
+            self.open_file_misses.load(Ordering::Relaxed),
+            self.queue_depth.load(Ordering::Relaxed),
+            self.queue_rejected.load(Ordering::Relaxed),
+        )
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_to_json() {
+        let stats = Stats::new();
+        Stats::incr(&stats.cache_hits);
+        Stats::incr(&stats.cache_hits);
+        Stats::incr(&stats.cache_misses);
+        Stats::incr(&stats.open_file_misses);
+        assert_eq!(
+            stats.to_json(),
+            "{\"cache_hits\":2,\"cache_misses\":1,\"open_file_hits\":0,\"open_file_misses\":1,\"queue_depth\":0,\"queue_rejected\":0}"
+        );
+    }
+}
@@ -0,0 +1,184 @@
+use std::{
+    fs::File,
+    io::{self, Read, Write},
+    net::{Shutdown, TcpStream},
+    os::{
+        fd::{AsRawFd, RawFd},
+        unix::net::UnixStream,
+    },
+};
+
+/*
+ * 客户端连接抽象，明文TCP、Unix套接字与TLS连接共用同一套请求处理流程
+ */
+pub trait Connection: Read + Write {
+    //关闭连接
+    fn shutdown(&mut self) -> io::Result<()>;
+
+    //是否为加密连接
+    fn is_secure(&self) -> bool {
+        false
+    }
+
+    //可直接写入的套接字描述符（用于sendfile），加密或缓冲连接返回None
+    fn raw_fd(&self) -> Option<RawFd> {
+        None
+    }
+
+    //交由连接稍后发送文件区间（事件循环中非阻塞sendfile），返回false时由调用方直接写出
+    fn defer_file(&mut self, _file: &File, _offset: u64, _len: u64) -> io::Result<bool> {
+        Ok(false)
+    }
+
+    //客户端地址，用于日志
+    fn peer_addr(&self) -> String {
+        String::from("-")
+    }
+}
+
+impl Connection for TcpStream {
+    fn shutdown(&mut self) -> io::Result<()> {
+        TcpStream::shutdown(self, Shutdown::Both)
+    }
+
+    fn raw_fd(&self) -> Option<RawFd> {
+        Some(self.as_raw_fd())
+    }
+
+    fn peer_addr(&self) -> String {
+        TcpStream::peer_addr(self).map_or_else(|_| String::from("-"), |addr| addr.to_string())
+    }
+}
+
+impl Connection for UnixStream {
+    fn shutdown(&mut self) -> io::Result<()> {
+        UnixStream::shutdown(self, Shutdown::Both)
+    }
+
+    fn raw_fd(&self) -> Option<RawFd> {
+        Some(self.as_raw_fd())
+    }
+
+    //客户端一般是未命名套接字，显示监听的路径
+    fn peer_addr(&self) -> String {
+        match self.local_addr() {
+            Ok(addr) => match addr.as_pathname() {
+                Some(path) => format!("unix:{}", path.display()),
+                None => String::from("unix:"),
+            },
+            Err(_) => String::from("unix:"),
+        }
+    }
+}
+
+impl<C: Connection + ?Sized> Connection for &mut C {
+    fn shutdown(&mut self) -> io::Result<()> {
+        (**self).shutdown()
+    }
+
+    fn is_secure(&self) -> bool {
+        (**self).is_secure()
+    }
+
+    fn raw_fd(&self) -> Option<RawFd> {
+        (**self).raw_fd()
+    }
+
+    fn defer_file(&mut self, file: &File, offset: u64, len: u64) -> io::Result<bool> {
+        (**self).defer_file(file, offset, len)
+    }
+
+    fn peer_addr(&self) -> String {
+        (**self).peer_addr()
+    }
+}
+
+//监听套接字接收到的连接
+#[derive(Debug)]
+pub enum Stream {
+    Tcp(TcpStream),
+    Unix(UnixStream),
+}
+
+impl Stream {
+    pub fn try_clone(&self) -> io::Result<Stream> {
+        match self {
+            Stream::Tcp(stream) => stream.try_clone().map(Stream::Tcp),
+            Stream::Unix(stream) => stream.try_clone().map(Stream::Unix),
+        }
+    }
+
+    pub fn set_nonblocking(&self, nonblocking: bool) -> io::Result<()> {
+        match self {
+            Stream::Tcp(stream) => stream.set_nonblocking(nonblocking),
+            Stream::Unix(stream) => stream.set_nonblocking(nonblocking),
+        }
+    }
+
+    //关闭读、写或两个方向
+    pub fn shutdown_direction(&self, how: Shutdown) -> io::Result<()> {
+        match self {
+            Stream::Tcp(stream) => stream.shutdown(how),
+            Stream::Unix(stream) => stream.shutdown(how),
+        }
+    }
+
+    fn connection(&self) -> &dyn Connection {
+        match self {
+            Stream::Tcp(stream) => stream,
+            Stream::Unix(stream) => stream,
+        }
+    }
+
+    fn connection_mut(&mut self) -> &mut dyn Connection {
+        match self {
+            Stream::Tcp(stream) => stream,
+            Stream::Unix(stream) => stream,
+        }
+    }
+}
+
+impl From<TcpStream> for Stream {
+    fn from(stream: TcpStream) -> Stream {
+        Stream::Tcp(stream)
+    }
+}
+
+impl AsRawFd for Stream {
+    fn as_raw_fd(&self) -> RawFd {
+        match self {
+            Stream::Tcp(stream) => stream.as_raw_fd(),
+            Stream::Unix(stream) => stream.as_raw_fd(),
+        }
+    }
+}
+
+impl Read for Stream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.connection_mut().read(buf)
+    }
+}
+
+impl Write for Stream {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.connection_mut().write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.connection_mut().flush()
+    }
+}
+
+impl Connection for Stream {
+    fn shutdown(&mut self) -> io::Result<()> {
+        self.connection_mut().shutdown()
+    }
+
+    fn raw_fd(&self) -> Option<RawFd> {
+        self.connection().raw_fd()
+    }
+
+    fn peer_addr(&self) -> String {
+        self.connection().peer_addr()
+    }
+}
@@ -1,83 +1,432 @@
 use std::{
-    sync::{mpsc, Arc, Mutex},
+    panic::{self, AssertUnwindSafe},
+    sync::{
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        mpsc, Arc, Mutex, MutexGuard,
+    },
     thread,
+    time::{Duration, Instant},
 };
 
-use crate::{error::ThreadError, log_info};
+use crate::{error::ThreadError, log_error, log_info, stats::Stats};
 
-struct Worker {
-    id: usize,
-    work: Option<thread::JoinHandle<()>>,
+type Job = Box<dyn FnOnce() + Send + 'static>;
+
+enum Message {
+    NewJob(Job),
+    Terminate,
 }
 
-impl Worker {
-    fn new(id: usize, receiver: Arc<Mutex<mpsc::Receiver<Message>>>) -> Worker {
-        let work = thread::spawn(move || loop {
-            let message = receiver.lock().unwrap().recv().unwrap();
+//线程池各工作线程共享的状态
+struct Shared {
+    receiver: Mutex<mpsc::Receiver<Message>>,
+    //等待执行的任务数
+    depth: AtomicUsize,
+    //存活的工作线程数
+    threads: AtomicUsize,
+    //空闲（等待任务）的工作线程数
+    idle: AtomicUsize,
+    next_id: AtomicUsize,
+    shutdown: AtomicBool,
+    workers: Mutex<Vec<thread::JoinHandle<()>>>,
+    min_threads: usize,
+    max_threads: usize,
+    idle_timeout: Duration,
+    stats: Arc<Stats>,
+}
+
+//获取锁，其他线程panic导致的锁中毒不影响使用
+fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+impl Shared {
+    //启动一个工作线程，调用前已计入threads
+    fn spawn_worker(self: &Arc<Shared>) -> Result<(), ThreadError> {
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        let shared = Arc::clone(self);
+        let spawned = thread::Builder::new()
+            .name(format!("worker-{}", id))
+            .spawn(move || {
+                let _guard = WorkerGuard {
+                    shared: Arc::clone(&shared),
+                };
+                shared.run();
+            });
+        match spawned {
+            Ok(work) => {
+                let mut workers = lock(&self.workers);
+                workers.retain(|work| !work.is_finished());
+                workers.push(work);
+                Ok(())
+            }
+            Err(e) => {
+                self.threads.fetch_sub(1, Ordering::Relaxed);
+                Err(ThreadError {
+                    kind: "thread_pool".to_string(),
+                    message: format!("Failed to spawn worker thread: {}", e),
+                })
+            }
+        }
+    }
+
+    //线程数未达上限时占用一个名额，返回是否成功
+    fn reserve_thread(&self) -> bool {
+        self.threads
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |threads| {
+                (threads < self.max_threads).then_some(threads + 1)
+            })
+            .is_ok()
+    }
+
+    //空闲超时后线程数多于下限时释放一个名额，返回是否可以退出
+    fn release_thread(&self) -> bool {
+        self.threads
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |threads| {
+                (threads > self.min_threads).then_some(threads - 1)
+            })
+            .is_ok()
+    }
+
+    fn run(&self) {
+        loop {
+            self.idle.fetch_add(1, Ordering::Relaxed);
+            let message = lock(&self.receiver).recv_timeout(self.idle_timeout);
+            self.idle.fetch_sub(1, Ordering::Relaxed);
             match message {
-                Message::NewJob(job) => job(),
-                Message::Terminate => break,
+                Ok(Message::NewJob(job)) => {
+                    self.depth.fetch_sub(1, Ordering::Relaxed);
+                    self.stats.queue_depth.fetch_sub(1, Ordering::Relaxed);
+                    //任务panic不影响工作线程继续处理后续任务
+                    if panic::catch_unwind(AssertUnwindSafe(job)).is_err() {
+                        log_error!("Job panicked in worker {}", current_name());
+                    }
+                }
+                Ok(Message::Terminate) | Err(mpsc::RecvTimeoutError::Disconnected) => {
+                    self.threads.fetch_sub(1, Ordering::Relaxed);
+                    break;
+                }
+                Err(mpsc::RecvTimeoutError::Timeout) => {
+                    if self.release_thread() {
+                        log_info!("Idle worker {} exited", current_name());
+                        break;
+                    }
+                }
             }
-        });
-        Worker {
-            id,
-            work: Option::Some(work),
         }
     }
 }
 
-type Job = Box<dyn FnOnce() + Send + 'static>;
+fn current_name() -> String {
+    thread::current().name().unwrap_or_default().to_owned()
+}
 
-enum Message {
-    NewJob(Job),
-    Terminate,
+/*
+ * 工作线程意外panic退出时，补充一个新的工作线程
+ * 任务内的panic已被捕获，任务之外的panic（如panic载荷析构时再次panic）仍会使线程退出
+ */
+struct WorkerGuard {
+    shared: Arc<Shared>,
+}
+
+impl Drop for WorkerGuard {
+    fn drop(&mut self) {
+        if !thread::panicking() {
+            return;
+        }
+        log_error!("Worker {} panicked, replacing it", current_name());
+        if self.shared.shutdown.load(Ordering::Relaxed) {
+            self.shared.threads.fetch_sub(1, Ordering::Relaxed);
+            return;
+        }
+        //沿用退出线程的名额
+        if let Err(e) = self.shared.spawn_worker() {
+            log_error!("{}", e);
+        }
+    }
 }
 
 pub struct ThreadPool {
-    workers: Vec<Worker>,
     sender: mpsc::Sender<Message>,
+    shared: Arc<Shared>,
+    //队列容量
+    capacity: usize,
 }
 
 impl ThreadPool {
-    pub fn new(size: usize) -> Result<ThreadPool, ThreadError> {
-        if size <= 0 {
+    /*
+     * min_threads个工作线程常驻，任务排队时增加到max_threads个，
+     * 多出的线程空闲idle_timeout后退出
+     */
+    pub fn new(
+        min_threads: usize,
+        max_threads: usize,
+        idle_timeout: Duration,
+        capacity: usize,
+        stats: Arc<Stats>,
+    ) -> Result<ThreadPool, ThreadError> {
+        if min_threads == 0 || min_threads > max_threads {
+            return Err(ThreadError {
+                kind: "thread_pool".to_string(),
+                message: "The thread pool size must satisfy 0 < min <= max".to_string(),
+            });
+        }
+        if capacity == 0 {
             return Err(ThreadError {
                 kind: "thread_pool".to_string(),
-                message: "The thread pool size must be greater than 0".to_string(),
+                message: "The job queue capacity must be greater than 0".to_string(),
             });
         }
         let (sender, receiver) = mpsc::channel();
-
-        let receiver = Arc::new(Mutex::new(receiver));
-
-        let mut workers: Vec<Worker> = Vec::with_capacity(size);
-        for id in 0..size {
-            workers.push(Worker::new(id, Arc::clone(&receiver)))
+        let shared = Arc::new(Shared {
+            receiver: Mutex::new(receiver),
+            depth: AtomicUsize::new(0),
+            threads: AtomicUsize::new(0),
+            idle: AtomicUsize::new(0),
+            next_id: AtomicUsize::new(0),
+            shutdown: AtomicBool::new(false),
+            workers: Mutex::new(Vec::with_capacity(max_threads)),
+            min_threads,
+            max_threads,
+            idle_timeout,
+            stats,
+        });
+        for _ in 0..min_threads {
+            shared.threads.fetch_add(1, Ordering::Relaxed);
+            shared.spawn_worker()?;
         }
-        Ok(ThreadPool { workers, sender })
+        Ok(ThreadPool {
+            sender,
+            shared,
+            capacity,
+        })
     }
 
-    pub fn exec<F>(&self, f: F)
+    /*
+     * 队列未满时提交任务，task为任务处理的数据（如客户端连接）
+     * 队列已满时不执行并返回Err(task)，由调用方决定如何处理
+     */
+    pub fn try_exec<T, F>(&self, task: T, f: F) -> Result<(), T>
     where
-        F: FnOnce() + Send + 'static,
+        T: Send + 'static,
+        F: FnOnce(T) + Send + 'static,
     {
-        let job = Box::new(f);
+        let shared = &self.shared;
+        //先占用队列位置，保证多个提交方并发时也不超过容量
+        if shared.depth.fetch_add(1, Ordering::Relaxed) >= self.capacity {
+            shared.depth.fetch_sub(1, Ordering::Relaxed);
+            Stats::incr(&shared.stats.queue_rejected);
+            return Err(task);
+        }
+        shared.stats.queue_depth.fetch_add(1, Ordering::Relaxed);
+        let job = Box::new(move || f(task));
         self.sender.send(Message::NewJob(job)).unwrap();
+        //排队任务多于空闲线程时扩容
+        if shared.depth.load(Ordering::Relaxed) > shared.idle.load(Ordering::Relaxed)
+            && shared.reserve_thread()
+        {
+            if let Err(e) = shared.spawn_worker() {
+                log_error!("{}", e);
+            }
+        }
+        Ok(())
+    }
+
+    //当前工作线程数
+    #[cfg(test)]
+    pub fn threads(&self) -> usize {
+        self.shared.threads.load(Ordering::Relaxed)
+    }
+
+    //通知所有工作线程在处理完已排队的任务后退出
+    fn terminate(&self) {
+        self.shared.shutdown.store(true, Ordering::Relaxed);
+        log_info!("Sending terminate message to all workers.");
+        for _ in 0..self.shared.max_threads {
+            let _ = self.sender.send(Message::Terminate);
+        }
+    }
+
+    /*
+     * 等待已排队及处理中的任务完成，超过deadline返回false
+     */
+    pub fn shutdown(&self, deadline: Instant) -> bool {
+        self.terminate();
+        loop {
+            if lock(&self.shared.workers)
+                .iter()
+                .all(|work| work.is_finished())
+            {
+                break;
+            }
+            if Instant::now() >= deadline {
+                return false;
+            }
+            thread::sleep(Duration::from_millis(20));
+        }
+        self.join();
+        true
+    }
+
+    fn join(&self) {
+        log_info!("Shutting down all workers.");
+        let workers = std::mem::take(&mut *lock(&self.shared.workers));
+        for work in workers {
+            log_info!(
+                "Shutting down worker {}",
+                work.thread().name().unwrap_or_default()
+            );
+            let _ = work.join();
+        }
     }
 }
 
 impl Drop for ThreadPool {
     fn drop(&mut self) {
-        log_info!("Sending terminate message to all workers.");
-        for _ in &self.workers {
-            self.sender.send(Message::Terminate).unwrap();
+        if !self.shared.shutdown.load(Ordering::Relaxed) {
+            self.terminate();
+            self.join();
         }
-        log_info!("Shutting down all workers.");
-        for worker in &mut self.workers {
-            log_info!("Shutting down worker {}", worker.id);
-            if let Some(work) = worker.work.take() {
-                work.join().unwrap();
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::mpsc;
+
+    use super::*;
+
+    #[test]
+    fn test_try_exec_bounded() {
+        let pool =
+            ThreadPool::new(1, 1, Duration::from_secs(60), 2, Arc::new(Stats::new())).unwrap();
+        let (block_sender, block_receiver) = mpsc::channel::<()>();
+        let (started_sender, started_receiver) = mpsc::channel();
+        //占住唯一的工作线程
+        let blocker = move |()| {
+            started_sender.send(()).unwrap();
+            let _ = block_receiver.recv();
+        };
+        assert!(pool.try_exec((), blocker).is_ok());
+        started_receiver.recv().unwrap();
+        let (done_sender, done_receiver) = mpsc::channel();
+        for i in 0..2 {
+            let done_sender = done_sender.clone();
+            assert!(pool
+                .try_exec(i, move |i| done_sender.send(i).unwrap())
+                .is_ok());
+        }
+        assert_eq!(pool.try_exec(9, |_| {}), Err(9));
+        drop(block_sender);
+        assert_eq!(done_receiver.recv_timeout(Duration::from_secs(5)), Ok(0));
+        assert_eq!(done_receiver.recv_timeout(Duration::from_secs(5)), Ok(1));
+        assert!(ThreadPool::new(1, 1, Duration::from_secs(1), 0, Arc::new(Stats::new())).is_err());
+        assert!(ThreadPool::new(2, 1, Duration::from_secs(1), 1, Arc::new(Stats::new())).is_err());
+        assert!(ThreadPool::new(0, 1, Duration::from_secs(1), 1, Arc::new(Stats::new())).is_err());
+    }
+
+    fn wait_for(mut condition: impl FnMut() -> bool) -> bool {
+        let start = Instant::now();
+        while start.elapsed() < Duration::from_secs(5) {
+            if condition() {
+                return true;
             }
+            thread::sleep(Duration::from_millis(10));
+        }
+        false
+    }
+
+    #[test]
+    fn test_grow_and_shrink() {
+        let pool =
+            ThreadPool::new(1, 3, Duration::from_millis(100), 16, Arc::new(Stats::new())).unwrap();
+        let (block_sender, block_receiver) = mpsc::channel::<()>();
+        let block_receiver = Arc::new(Mutex::new(block_receiver));
+        let (name_sender, name_receiver) = mpsc::channel();
+        for _ in 0..3 {
+            let block_receiver = Arc::clone(&block_receiver);
+            let name_sender = name_sender.clone();
+            let job = move |()| {
+                name_sender.send(current_name()).unwrap();
+                let _ = lock(&block_receiver).recv();
+            };
+            assert!(pool.try_exec((), job).is_ok());
+        }
+        //三个任务同时执行
+        for _ in 0..3 {
+            let name = name_receiver.recv_timeout(Duration::from_secs(5)).unwrap();
+            assert!(name.starts_with("worker-"));
+        }
+        assert_eq!(pool.threads(), 3);
+        drop(block_sender);
+        //空闲后收缩到下限
+        assert!(wait_for(|| pool.threads() == 1));
+    }
+
+    #[test]
+    fn test_shutdown() {
+        let pool =
+            ThreadPool::new(2, 2, Duration::from_secs(60), 16, Arc::new(Stats::new())).unwrap();
+        let (done_sender, done_receiver) = mpsc::channel();
+        for i in 0..4 {
+            let done_sender = done_sender.clone();
+            let job = move |i| {
+                thread::sleep(Duration::from_millis(20));
+                done_sender.send(i).unwrap();
+            };
+            assert!(pool.try_exec(i, job).is_ok());
+        }
+        //已排队的任务在退出前完成
+        assert!(pool.shutdown(Instant::now() + Duration::from_secs(5)));
+        assert_eq!(done_receiver.try_iter().count(), 4);
+        let pool =
+            ThreadPool::new(1, 1, Duration::from_secs(60), 16, Arc::new(Stats::new())).unwrap();
+        let (block_sender, block_receiver) = mpsc::channel::<()>();
+        assert!(pool
+            .try_exec((), move |()| {
+                let _ = block_receiver.recv();
+            })
+            .is_ok());
+        assert!(!pool.shutdown(Instant::now() + Duration::from_millis(50)));
+        drop(block_sender);
+    }
+
+    #[test]
+    fn test_job_panic() {
+        let pool =
+            ThreadPool::new(1, 1, Duration::from_secs(60), 16, Arc::new(Stats::new())).unwrap();
+        assert!(pool.try_exec((), |()| panic!("injected panic")).is_ok());
+        let (done_sender, done_receiver) = mpsc::channel();
+        assert!(pool
+            .try_exec((), move |()| done_sender.send(()).unwrap())
+            .is_ok());
+        assert!(done_receiver.recv_timeout(Duration::from_secs(5)).is_ok());
+        assert_eq!(pool.threads(), 1);
+    }
+
+    //析构时panic的载荷在catch_unwind之外再次panic
+    struct Bomb;
+
+    impl Drop for Bomb {
+        fn drop(&mut self) {
+            panic!("payload dropped");
         }
     }
+
+    #[test]
+    fn test_replace_panicked_worker() {
+        let pool =
+            ThreadPool::new(1, 1, Duration::from_secs(60), 16, Arc::new(Stats::new())).unwrap();
+        assert!(pool.try_exec((), |()| panic::panic_any(Bomb)).is_ok());
+        let (name_sender, name_receiver) = mpsc::channel();
+        assert!(pool
+            .try_exec((), move |()| name_sender.send(current_name()).unwrap())
+            .is_ok());
+        //原工作线程退出，由新线程执行后续任务
+        assert_eq!(
+            name_receiver.recv_timeout(Duration::from_secs(5)),
+            Ok(String::from("worker-1"))
+        );
+        assert_eq!(pool.threads(), 1);
+    }
 }
@@ -1,121 +1,120 @@
-use std::{sync::RwLock, time};
+use std::time;
 
-use crate::config::MyConfig;
-
-#[derive(Debug)]
-struct Date {
-    year: i32,
-    month: i32,
-    day: i32,
-    hour: i32,
-    minute: i32,
-    second: i32,
-    timestamp: i32,
+//指定时区的本地时间，用于运行日志与访问日志
+#[derive(Debug, PartialEq)]
+pub struct LocalTime {
+    year: i64,
+    month: u32,
+    day: u32,
+    hour: u32,
+    minute: u32,
+    second: u32,
+    //时区（小时）
+    offset: i32,
 }
 
-impl Date {
-    fn update(&mut self) {
-        let time_sec = time::SystemTime::now()
+const MONTH_NAMES: [&str; 12] = [
+    "Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec",
+];
+
+impl LocalTime {
+    //指定时区（小时）的当前时间，每次重新计算，时区可随配置重新加载改变
+    pub fn now_in(offset: i32) -> LocalTime {
+        let secs = time::SystemTime::now()
             .duration_since(time::SystemTime::UNIX_EPOCH)
-            .unwrap()
-            .as_secs() as i32
-            + MyConfig::new().timezone * 60 * 60;
-        let mut temp;
-        //计算年
-        loop {
-            if (self.year % 4 == 0 && self.year % 100 != 0) || self.year % 400 == 0 {
-                temp = 366 * 24 * 60 * 60;
-            } else {
-                temp = 365 * 24 * 60 * 60;
-            }
-            if self.timestamp + temp > time_sec {
-                break;
-            }
-            self.timestamp += temp;
-            self.year += 1;
-        }
-        //计算月份
-        let month_days;
-        if (self.year % 4 == 0 && self.year % 100 != 0) || self.year % 400 == 0 {
-            month_days = LEAP_MONTH_DAYS;
-        } else {
-            month_days = MONTH_DAYS;
-        }
-        for days in month_days {
-            temp = days * 24 * 60 * 60;
-            if self.timestamp + temp > time_sec {
-                break;
-            }
-            self.timestamp += temp;
-            self.month += 1;
-        }
-        for days in MONTH_DAYS {
-            temp = days * 24 * 60 * 60;
-            if self.timestamp + temp > time_sec {
-                break;
-            }
-            self.timestamp += temp;
-            self.month += 1;
-        }
-        //计算天数
-        while self.timestamp + 24 * 60 * 60 < time_sec {
-            self.timestamp += 24 * 60 * 60;
-            self.day += 1;
-        }
-        //计算小时
-        while self.timestamp + 60 * 60 <= time_sec {
-            self.timestamp += 60 * 60;
-            self.hour += 1;
-        }
-        //计算分钟
-        while self.timestamp + 60 <= time_sec {
-            self.timestamp += 60;
-            self.minute += 1;
+            .map_or(0, |d| d.as_secs() as i64);
+        LocalTime::from_unix(secs, offset)
+    }
+
+    //由公历日数换算年月日（Howard Hinnant的civil_from_days算法）
+    fn from_unix(secs: i64, offset: i32) -> LocalTime {
+        let secs = secs + offset as i64 * 3600;
+        let (days, rest) = (secs.div_euclid(86400), secs.rem_euclid(86400));
+        let z = days + 719468;
+        let era = z.div_euclid(146097);
+        let doe = z - era * 146097;
+        let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+        let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+        let mp = (5 * doy + 2) / 153;
+        let day = (doy - (153 * mp + 2) / 5 + 1) as u32;
+        let month = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+        LocalTime {
+            year: yoe + era * 400 + (month <= 2) as i64,
+            month,
+            day,
+            hour: (rest / 3600) as u32,
+            minute: (rest % 3600 / 60) as u32,
+            second: (rest % 60) as u32,
+            offset,
         }
-        //计算秒
-        self.second = time_sec - self.timestamp;
     }
-}
 
-impl ToString for Date {
-    fn to_string(&self) -> String {
+    //运行日志的时间：2026-10-19 16:13:56
+    pub fn datetime(&self) -> String {
         format!(
             "{}-{:02}-{:02} {:02}:{:02}:{:02}",
             self.year, self.month, self.day, self.hour, self.minute, self.second
         )
     }
-}
 
-static MONTH_DAYS: [i32; 12] = [31, 28, 31, 30, 31, 30, 31, 31, 30, 31, 30, 31];
-static LEAP_MONTH_DAYS: [i32; 12] = [31, 29, 31, 30, 31, 30, 31, 31, 30, 31, 30, 31];
-static DATE: RwLock<Date> = RwLock::new(Date {
-    year: 1970,
-    month: 1,
-    day: 1,
-    hour: 0,
-    minute: 0,
-    second: 0,
-    timestamp: 0,
-});
+    //Common Log Format：19/Oct/2026:16:13:56 +0800
+    pub fn clf(&self) -> String {
+        format!(
+            "{:02}/{}/{}:{:02}:{:02}:{:02} {}{:02}00",
+            self.day,
+            MONTH_NAMES[self.month as usize - 1],
+            self.year,
+            self.hour,
+            self.minute,
+            self.second,
+            if self.offset < 0 { '-' } else { '+' },
+            self.offset.abs()
+        )
+    }
 
-pub fn now() -> String {
-    if let Ok(mut date) = DATE.try_write() {
-        date.update();
+    //ISO 8601：2026-10-19T16:13:56+08:00
+    pub fn iso8601(&self) -> String {
+        format!(
+            "{}-{:02}-{:02}T{:02}:{:02}:{:02}{}{:02}:00",
+            self.year,
+            self.month,
+            self.day,
+            self.hour,
+            self.minute,
+            self.second,
+            if self.offset < 0 { '-' } else { '+' },
+            self.offset.abs()
+        )
     }
-    DATE.read().unwrap().to_string()
 }
 
 #[cfg(test)]
 mod time_test {
     use std::{thread, time::Duration};
 
-    use crate::time::{now, DATE};
+    use crate::time::LocalTime;
+
+    #[test]
+    fn test_local_time() {
+        let time = LocalTime::from_unix(1_792_397_636, 8);
+        assert_eq!(time.clf(), "19/Oct/2026:16:13:56 +0800");
+        assert_eq!(time.iso8601(), "2026-10-19T16:13:56+08:00");
+        assert_eq!(time.datetime(), "2026-10-19 16:13:56");
+        //每次由时间戳重新计算，时区调小时不受之前结果影响
+        assert_eq!(
+            LocalTime::from_unix(1_792_397_636, -5).datetime(),
+            "2026-10-19 03:13:56"
+        );
+        assert_eq!(
+            LocalTime::from_unix(951_782_400, -5).clf(),
+            "28/Feb/2000:19:00:00 -0500"
+        );
+    }
 
     #[test]
     fn get_now() {
-        loop {
-            println!("{}", now());
-            println!("DATE:{:?}", DATE);
+        for _ in 0..3 {
+            println!("{}", LocalTime::now_in(8).datetime());
             thread::sleep(Duration::from_secs(1));
         }
     }
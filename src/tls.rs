@@ -0,0 +1,113 @@
+use std::{
+    collections::HashMap,
+    io::{self, Read, Write},
+    sync::Arc,
+};
+
+use rustls::{
+    crypto::ring::{default_provider, sign::any_supported_type},
+    pki_types::{pem::PemObject, CertificateDer, PrivateKeyDer},
+    server::{ClientHello, ResolvesServerCert},
+    sign::CertifiedKey,
+    ServerConfig, ServerConnection, StreamOwned,
+};
+
+use crate::{config::TlsConfig, error::HttpError, stream::Connection};
+
+//TLS连接，S为底层连接（如经过PROXY协议的连接）
+pub type TlsStream<S> = StreamOwned<ServerConnection, S>;
+
+impl<S: Connection> Connection for TlsStream<S> {
+    fn shutdown(&mut self) -> io::Result<()> {
+        //先通知对端关闭TLS会话
+        self.conn.send_close_notify();
+        self.flush()?;
+        self.sock.shutdown()
+    }
+
+    fn is_secure(&self) -> bool {
+        true
+    }
+
+    fn peer_addr(&self) -> String {
+        self.sock.peer_addr()
+    }
+}
+
+/*
+ * 按SNI主机名选择证书，未匹配时使用默认证书
+ */
+#[derive(Debug)]
+struct SniResolver {
+    default: Arc<CertifiedKey>,
+    hosts: HashMap<String, Arc<CertifiedKey>>,
+}
+
+impl ResolvesServerCert for SniResolver {
+    fn resolve(&self, client_hello: ClientHello<'_>) -> Option<Arc<CertifiedKey>> {
+        let cert = client_hello
+            .server_name()
+            .and_then(|name| self.hosts.get(&name.to_lowercase()))
+            .unwrap_or(&self.default);
+        Some(Arc::clone(cert))
+    }
+}
+
+pub struct TlsAcceptor {
+    config: Arc<ServerConfig>,
+}
+
+impl TlsAcceptor {
+    pub fn new(tls: &TlsConfig) -> Result<TlsAcceptor, HttpError> {
+        let mut hosts = HashMap::new();
+        //主机名不区分大小写，与resolve中的查找保持一致
+        for sni in &tls.sni {
+            hosts.insert(
+                sni.host.to_lowercase(),
+                load_certified_key(&sni.cert_path, &sni.key_path)?,
+            );
+        }
+        let resolver = SniResolver {
+            default: load_certified_key(&tls.cert_path, &tls.key_path)?,
+            hosts,
+        };
+        let mut config = ServerConfig::builder_with_provider(Arc::new(default_provider()))
+            .with_safe_default_protocol_versions()?
+            .with_no_client_auth()
+            .with_cert_resolver(Arc::new(resolver));
+        //通过ALPN协商HTTP/2
+        if tls.http2 {
+            config.alpn_protocols = vec![b"h2".to_vec(), b"http/1.1".to_vec()];
+        }
+        Ok(TlsAcceptor {
+            config: Arc::new(config),
+        })
+    }
+
+    //握手在首次读写时进行
+    pub fn accept<S: Read + Write>(&self, stream: S) -> Result<TlsStream<S>, HttpError> {
+        let connection = ServerConnection::new(Arc::clone(&self.config))?;
+        Ok(StreamOwned::new(connection, stream))
+    }
+}
+
+fn load_certified_key(cert_path: &str, key_path: &str) -> Result<Arc<CertifiedKey>, HttpError> {
+    let certs = CertificateDer::pem_file_iter(cert_path)
+        .and_then(|certs| certs.collect::<Result<Vec<_>, _>>())
+        .map_err(|e| HttpError {
+            kind: "tls".to_string(),
+            message: format!("Failed to read certificate {}: {}", cert_path, e),
+        })?;
+    if certs.is_empty() {
+        return Err(HttpError {
+            kind: "tls".to_string(),
+            message: format!("No certificate found in {}", cert_path),
+        });
+    }
+    let key = PrivateKeyDer::from_pem_file(key_path).map_err(|e| HttpError {
+        kind: "tls".to_string(),
+        message: format!("Failed to read private key {}: {}", key_path, e),
+    })?;
+    let key = any_supported_type(&key)?;
+    Ok(Arc::new(CertifiedKey::new(certs, key)))
+}
@@ -0,0 +1,488 @@
+/*
+ * TOML 1.0解析（https://toml.io/en/v1.0.0）
+ */
+mod parser;
+
+use std::{
+    collections::{BTreeMap, HashMap},
+    fmt::Display,
+};
+
+use crate::error::TomlError;
+
+pub type Table = BTreeMap<String, Value>;
+
+//解析结果：根表及各键首次定义所在的行号（键路径以“.”连接，如server.port）
+pub struct Document {
+    pub table: Table,
+    pub lines: HashMap<String, usize>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    String(String),
+    Integer(i64),
+    Float(f64),
+    Boolean(bool),
+    Datetime(Datetime),
+    Array(Vec<Value>),
+    Table(Table),
+}
+
+impl Value {
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            Value::String(_) => "string",
+            Value::Integer(_) => "integer",
+            Value::Float(_) => "float",
+            Value::Boolean(_) => "boolean",
+            Value::Datetime(_) => "datetime",
+            Value::Array(_) => "array",
+            Value::Table(_) => "table",
+        }
+    }
+}
+
+//按TOML语法输出，表输出为内联表
+impl Display for Value {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Value::String(text) => write_string(f, text),
+            Value::Integer(n) => write!(f, "{}", n),
+            Value::Float(n) if n.is_nan() => write!(f, "nan"),
+            Value::Float(n) if n.is_infinite() => {
+                write!(f, "{}inf", if *n < 0.0 { "-" } else { "" })
+            }
+            Value::Float(n) => write!(f, "{:?}", n),
+            Value::Boolean(b) => write!(f, "{}", b),
+            Value::Datetime(datetime) => write!(f, "{}", datetime),
+            Value::Array(items) => {
+                write!(f, "[")?;
+                for (i, item) in items.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "{}", item)?;
+                }
+                write!(f, "]")
+            }
+            Value::Table(table) => {
+                write!(f, "{{")?;
+                for (i, (key, value)) in table.iter().enumerate() {
+                    write!(f, "{}", if i > 0 { ", " } else { " " })?;
+                    if !key.is_empty() && key.bytes().all(parser::is_bare_key) {
+                        write!(f, "{}", key)?;
+                    } else {
+                        write_string(f, key)?;
+                    }
+                    write!(f, " = {}", value)?;
+                }
+                write!(f, "{}}}", if table.is_empty() { "" } else { " " })
+            }
+        }
+    }
+}
+
+fn write_string(f: &mut std::fmt::Formatter<'_>, text: &str) -> std::fmt::Result {
+    write!(f, "\"")?;
+    for c in text.chars() {
+        match c {
+            '"' => write!(f, "\\\"")?,
+            '\\' => write!(f, "\\\\")?,
+            '\n' => write!(f, "\\n")?,
+            '\r' => write!(f, "\\r")?,
+            '\t' => write!(f, "\\t")?,
+            c if c.is_control() => write!(f, "\\u{:04X}", c as u32)?,
+            c => write!(f, "{}", c)?,
+        }
+    }
+    write!(f, "\"")
+}
+
+/*
+ * 日期时间：带偏移的日期时间、本地日期时间、本地日期、本地时间
+ * 有offset时date与time必然存在
+ */
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Datetime {
+    pub date: Option<Date>,
+    pub time: Option<Time>,
+    pub offset: Option<Offset>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Date {
+    pub year: u16,
+    pub month: u8,
+    pub day: u8,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Time {
+    pub hour: u8,
+    pub minute: u8,
+    pub second: u8,
+    pub nanosecond: u32,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Offset {
+    Z,
+    //相对UTC的分钟数
+    Minutes(i16),
+}
+
+impl Display for Datetime {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if let Some(date) = &self.date {
+            write!(f, "{:04}-{:02}-{:02}", date.year, date.month, date.day)?;
+            if self.time.is_some() {
+                write!(f, "T")?;
+            }
+        }
+        if let Some(time) = &self.time {
+            write!(f, "{:02}:{:02}:{:02}", time.hour, time.minute, time.second)?;
+            if time.nanosecond > 0 {
+                let fraction = format!("{:09}", time.nanosecond);
+                write!(f, ".{}", fraction.trim_end_matches('0'))?;
+            }
+        }
+        match self.offset {
+            Some(Offset::Z) => write!(f, "Z"),
+            Some(Offset::Minutes(minutes)) => {
+                let sign = if minutes < 0 { '-' } else { '+' };
+                let minutes = minutes.unsigned_abs();
+                write!(f, "{}{:02}:{:02}", sign, minutes / 60, minutes % 60)
+            }
+            None => Ok(()),
+        }
+    }
+}
+
+/*
+ * 解析TOML文档
+ */
+pub fn parse(text: &str) -> Result<Document, TomlError> {
+    parser::Parser::new(text).parse()
+}
+
+#[cfg(test)]
+mod test {
+    use std::{fs, path::Path};
+
+    use super::*;
+
+    #[test]
+    fn test_parse_document() {
+        let document = parse(
+            "title = \"a = b\" # 注释\n\
+             [server]\n\
+             port = 8_080\n\
+             http2 = true\n\
+             ratio = 0.5e1\n\
+             hosts = [\"a\", 'b', ]\n\
+             limits = { max = 0x10, dir.name = '''x'''}\n\
+             started = 1979-05-27 07:32:00.5-07:00\n\
+             [[server.route]]\n\
+             path = \"/\\u00e9\"\n\
+             [[server.route]]\n",
+        )
+        .unwrap();
+        assert_eq!(document.lines["server.port"], 3);
+        assert_eq!(document.lines["server.limits"], 7);
+        assert_eq!(document.lines["server.route.path"], 10);
+        let table = document.table;
+        assert_eq!(table["title"], Value::String("a = b".to_owned()));
+        let server = match &table["server"] {
+            Value::Table(server) => server,
+            _ => panic!("server is not a table"),
+        };
+        assert_eq!(server["port"], Value::Integer(8080));
+        assert_eq!(server["http2"], Value::Boolean(true));
+        assert_eq!(server["ratio"], Value::Float(5.0));
+        assert_eq!(
+            server["hosts"],
+            Value::Array(vec![
+                Value::String("a".to_owned()),
+                Value::String("b".to_owned())
+            ])
+        );
+        let mut dir = Table::new();
+        dir.insert("name".to_owned(), Value::String("x".to_owned()));
+        let mut limits = Table::new();
+        limits.insert("max".to_owned(), Value::Integer(16));
+        limits.insert("dir".to_owned(), Value::Table(dir));
+        assert_eq!(server["limits"], Value::Table(limits));
+        match &server["started"] {
+            Value::Datetime(datetime) => {
+                assert_eq!(datetime.to_string(), "1979-05-27T07:32:00.5-07:00")
+            }
+            _ => panic!("started is not a datetime"),
+        }
+        match &server["route"] {
+            Value::Array(routes) => {
+                assert_eq!(routes.len(), 2);
+                assert_eq!(
+                    routes[0],
+                    Value::Table(Table::from([(
+                        "path".to_owned(),
+                        Value::String("/é".to_owned())
+                    )]))
+                );
+            }
+            _ => panic!("route is not an array"),
+        }
+    }
+
+    #[test]
+    fn test_display() {
+        let text = "a = [\"x\\\"y\\n\", 1, -0.5, inf, true, 1979-05-27T07:32:00Z]\nb = { c = { \"d e\" = 'f' } }\n";
+        let table = parse(text).unwrap().table;
+        assert_eq!(
+            table["a"].to_string(),
+            "[\"x\\\"y\\n\", 1, -0.5, inf, true, 1979-05-27T07:32:00Z]"
+        );
+        assert_eq!(table["b"].to_string(), "{ c = { \"d e\" = \"f\" } }");
+        //输出的结果可以重新解析
+        let output: String = table
+            .iter()
+            .map(|(key, value)| format!("{} = {}\n", key, value))
+            .collect();
+        assert_eq!(parse(&output).unwrap().table, table);
+    }
+
+    #[test]
+    fn test_error_position() {
+        let error = parse("a = 1\nb = \"x\ny = 2").err().unwrap();
+        assert_eq!((error.line, error.column), (2, 7));
+        let error = parse("a = 1\n[t]\n[t]\n").err().unwrap();
+        assert_eq!((error.line, error.column), (3, 2));
+        let error = parse("[a]\nb = 1\n[a.b]").err().unwrap();
+        assert_eq!(error.line, 3);
+        assert!(parse("a = 0_1").is_err());
+        assert!(parse("a = [1,,]").is_err());
+        assert!(parse("a = {b = 1,}").is_err());
+    }
+
+    /*
+     * toml-test测试集（https://github.com/BurntSushi/toml-test，TOML 1.0版本）
+     * 位于tests/toml-test：valid下的文档需解析出与同名.json一致的结果，invalid下的文档必须报错
+     */
+    #[test]
+    fn test_toml_test_suite() {
+        let root = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/toml-test");
+        let mut failures = Vec::new();
+        let mut count = 0;
+        for path in fixtures(&root.join("valid")) {
+            count += 1;
+            let text = fs::read_to_string(&path).unwrap();
+            let expected = fs::read_to_string(path.with_extension("json")).unwrap();
+            let expected = json::parse(&expected).unwrap();
+            match parse(&text).map(|document| document.table) {
+                Ok(table) if json::matches(&expected, &Value::Table(table.clone())) => {}
+                Ok(table) => failures.push(format!("{}: got {:?}", path.display(), table)),
+                Err(e) => failures.push(format!("{}: {}", path.display(), e)),
+            }
+        }
+        for path in fixtures(&root.join("invalid")) {
+            count += 1;
+            //非UTF-8编码在读取时即被拒绝
+            if let Ok(text) = String::from_utf8(fs::read(&path).unwrap()) {
+                if let Ok(document) = parse(&text) {
+                    failures.push(format!("{}: accepted {:?}", path.display(), document.table));
+                }
+            }
+        }
+        assert!(count > 250, "toml-test fixtures not found");
+        assert!(failures.is_empty(), "{}", failures.join("\n"));
+    }
+
+    fn fixtures(dir: &Path) -> Vec<std::path::PathBuf> {
+        let mut files = Vec::new();
+        let mut dirs = vec![dir.to_path_buf()];
+        while let Some(dir) = dirs.pop() {
+            for entry in fs::read_dir(dir).unwrap().flatten() {
+                let path = entry.path();
+                if path.is_dir() {
+                    dirs.push(path);
+                } else if path.extension().is_some_and(|e| e == "toml") {
+                    files.push(path);
+                }
+            }
+        }
+        files.sort();
+        files
+    }
+
+    //toml-test期望结果的JSON格式：标量为{"type":..., "value":...}
+    mod json {
+        use std::collections::BTreeMap;
+
+        use super::super::{parse as parse_toml, Value};
+
+        #[derive(Debug)]
+        pub enum Json {
+            String(String),
+            Array(Vec<Json>),
+            Object(BTreeMap<String, Json>),
+        }
+
+        pub fn parse(text: &str) -> Option<Json> {
+            let mut chars = text.chars().peekable();
+            let json = parse_value(&mut chars)?;
+            skip_ws(&mut chars);
+            chars.peek().is_none().then_some(json)
+        }
+
+        type Chars<'a> = std::iter::Peekable<std::str::Chars<'a>>;
+
+        fn skip_ws(chars: &mut Chars) {
+            while chars.peek().is_some_and(|c| c.is_whitespace()) {
+                chars.next();
+            }
+        }
+
+        fn parse_value(chars: &mut Chars) -> Option<Json> {
+            skip_ws(chars);
+            match chars.next()? {
+                '"' => parse_string(chars).map(Json::String),
+                '[' => {
+                    let mut items = Vec::new();
+                    loop {
+                        skip_ws(chars);
+                        if chars.peek() == Some(&']') {
+                            chars.next();
+                            return Some(Json::Array(items));
+                        }
+                        items.push(parse_value(chars)?);
+                        skip_ws(chars);
+                        if chars.peek() == Some(&',') {
+                            chars.next();
+                        }
+                    }
+                }
+                '{' => {
+                    let mut object = BTreeMap::new();
+                    loop {
+                        skip_ws(chars);
+                        match chars.next()? {
+                            '}' => return Some(Json::Object(object)),
+                            ',' => continue,
+                            '"' => {
+                                let key = parse_string(chars)?;
+                                skip_ws(chars);
+                                if chars.next()? != ':' {
+                                    return None;
+                                }
+                                object.insert(key, parse_value(chars)?);
+                            }
+                            _ => return None,
+                        }
+                    }
+                }
+                _ => None,
+            }
+        }
+
+        fn parse_string(chars: &mut Chars) -> Option<String> {
+            let mut s = String::new();
+            loop {
+                match chars.next()? {
+                    '"' => return Some(s),
+                    '\\' => match chars.next()? {
+                        'n' => s.push('\n'),
+                        't' => s.push('\t'),
+                        'r' => s.push('\r'),
+                        'b' => s.push('\u{8}'),
+                        'f' => s.push('\u{c}'),
+                        'u' => {
+                            let mut code = hex4(chars)?;
+                            //UTF-16代理对
+                            if (0xD800..0xDC00).contains(&code) {
+                                if chars.next()? != '\\' || chars.next()? != 'u' {
+                                    return None;
+                                }
+                                let low = hex4(chars)?;
+                                code = 0x10000 + ((code - 0xD800) << 10) + (low - 0xDC00);
+                            }
+                            s.push(char::from_u32(code)?);
+                        }
+                        c => s.push(c),
+                    },
+                    c => s.push(c),
+                }
+            }
+        }
+
+        fn hex4(chars: &mut Chars) -> Option<u32> {
+            let hex: String = (0..4).filter_map(|_| chars.next()).collect();
+            u32::from_str_radix(&hex, 16).ok()
+        }
+
+        //比较期望结果与解析结果，浮点数按数值、日期时间按解析后的值比较
+        pub fn matches(expected: &Json, actual: &Value) -> bool {
+            match (expected, actual) {
+                (Json::Array(expected), Value::Array(actual)) => {
+                    expected.len() == actual.len()
+                        && expected.iter().zip(actual).all(|(e, a)| matches(e, a))
+                }
+                (Json::Object(object), _) if is_scalar(object) => {
+                    let (kind, value) = match (&object["type"], &object["value"]) {
+                        (Json::String(kind), Json::String(value)) => (kind, value),
+                        _ => return false,
+                    };
+                    scalar_matches(kind, value, actual)
+                }
+                (Json::Object(expected), Value::Table(actual)) => {
+                    expected.len() == actual.len()
+                        && expected
+                            .iter()
+                            .all(|(k, e)| actual.get(k).is_some_and(|a| matches(e, a)))
+                }
+                _ => false,
+            }
+        }
+
+        fn is_scalar(object: &BTreeMap<String, Json>) -> bool {
+            object.len() == 2
+                && matches!(object.get("type"), Some(Json::String(_)))
+                && matches!(object.get("value"), Some(Json::String(_)))
+        }
+
+        fn scalar_matches(kind: &str, value: &str, actual: &Value) -> bool {
+            match (kind, actual) {
+                ("string", Value::String(s)) => s == value,
+                ("integer", Value::Integer(i)) => value.parse() == Ok(*i),
+                ("bool", Value::Boolean(b)) => value.parse() == Ok(*b),
+                ("float", Value::Float(f)) => match value.trim_start_matches(['+', '-']) {
+                    "nan" => f.is_nan(),
+                    "inf" => f.is_infinite() && value.starts_with('-') == (*f < 0.0),
+                    _ => value.parse() == Ok(*f),
+                },
+                (
+                    "datetime" | "datetime-local" | "date-local" | "time-local",
+                    Value::Datetime(d),
+                ) => {
+                    //借助TOML解析器解析期望值
+                    match parse_toml(&format!("v = {}", value))
+                        .ok()
+                        .and_then(|document| document.table.get("v").cloned())
+                    {
+                        Some(Value::Datetime(expected)) => {
+                            expected == *d
+                                && kind
+                                    == match (d.date, d.time, d.offset) {
+                                        (_, _, Some(_)) => "datetime",
+                                        (Some(_), Some(_), None) => "datetime-local",
+                                        (Some(_), None, None) => "date-local",
+                                        _ => "time-local",
+                                    }
+                        }
+                        _ => false,
+                    }
+                }
+                _ => false,
+            }
+        }
+    }
+}
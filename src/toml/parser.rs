@@ -0,0 +1,743 @@
+use std::collections::{btree_map::Entry, BTreeMap, HashMap};
+
+use super::{Date, Datetime, Document, Offset, Table, Time, Value};
+use crate::error::TomlError;
+
+//表的定义方式，决定之后能否再次定义或追加键
+#[derive(Clone, Copy, PartialEq)]
+enum Kind {
+    //作为[a.b.c]的上级表隐式创建，之后可由表头定义一次
+    Implicit,
+    //由[表头]定义
+    Header,
+    //由点分键定义
+    Dotted,
+}
+
+enum Item {
+    //普通值，内联表与数组定义后不可再追加
+    Value(Value),
+    Table(Node),
+    //[[表数组]]，表头中的路径指向最后一个元素
+    Array(Vec<Node>),
+}
+
+struct Node {
+    kind: Kind,
+    entries: BTreeMap<String, Item>,
+}
+
+impl Node {
+    fn new(kind: Kind) -> Node {
+        Node {
+            kind,
+            entries: BTreeMap::new(),
+        }
+    }
+
+    fn into_table(self) -> Table {
+        self.entries
+            .into_iter()
+            .map(|(key, item)| {
+                let value = match item {
+                    Item::Value(value) => value,
+                    Item::Table(node) => Value::Table(node.into_table()),
+                    Item::Array(nodes) => Value::Array(
+                        nodes
+                            .into_iter()
+                            .map(|node| Value::Table(node.into_table()))
+                            .collect(),
+                    ),
+                };
+                (key, value)
+            })
+            .collect()
+    }
+}
+
+//按路径找到当前表头对应的表
+fn current_table<'n>(root: &'n mut Node, path: &[String]) -> &'n mut Node {
+    let mut node = root;
+    for key in path {
+        node = match node.entries.get_mut(key) {
+            Some(Item::Table(table)) => table,
+            Some(Item::Array(tables)) => tables.last_mut().unwrap(),
+            _ => unreachable!("the current table always exists"),
+        };
+    }
+    node
+}
+
+pub(super) fn is_bare_key(c: u8) -> bool {
+    c.is_ascii_alphanumeric() || c == b'_' || c == b'-'
+}
+
+//除制表符外的控制字符不能出现在字符串与注释中
+fn is_control(c: char) -> bool {
+    (c < '\u{20}' && c != '\t') || c == '\u{7f}'
+}
+
+//下划线两侧必须都是数字
+fn valid_digits(s: &str, is_digit: impl Fn(char) -> bool) -> bool {
+    let mut prev_digit = false;
+    for c in s.chars() {
+        if is_digit(c) {
+            prev_digit = true;
+        } else if c == '_' && prev_digit {
+            prev_digit = false;
+        } else {
+            return false;
+        }
+    }
+    prev_digit
+}
+
+fn no_leading_zero(s: &str) -> bool {
+    !(s.len() > 1 && s.starts_with('0'))
+}
+
+fn parse_number(token: &str) -> Option<Value> {
+    let (negative, signed, body) = match token.as_bytes().first()? {
+        b'+' => (false, true, &token[1..]),
+        b'-' => (true, true, &token[1..]),
+        _ => (false, false, token),
+    };
+    match body {
+        "inf" if negative => return Some(Value::Float(f64::NEG_INFINITY)),
+        "inf" => return Some(Value::Float(f64::INFINITY)),
+        "nan" if negative => return Some(Value::Float(-f64::NAN)),
+        "nan" => return Some(Value::Float(f64::NAN)),
+        _ => {}
+    }
+    for (prefix, radix) in [("0x", 16), ("0o", 8), ("0b", 2)] {
+        if let Some(digits) = body.strip_prefix(prefix) {
+            if signed || !valid_digits(digits, |c| c.is_digit(radix)) {
+                return None;
+            }
+            return i64::from_str_radix(&digits.replace('_', ""), radix)
+                .ok()
+                .map(Value::Integer);
+        }
+    }
+    let digit = |c: char| c.is_ascii_digit();
+    if body.contains(['.', 'e', 'E']) {
+        let (mantissa, exponent) = match body.find(['e', 'E']) {
+            Some(i) => (&body[..i], Some(&body[i + 1..])),
+            None => (body, None),
+        };
+        let (int, fraction) = match mantissa.find('.') {
+            Some(i) => (&mantissa[..i], Some(&mantissa[i + 1..])),
+            None => (mantissa, None),
+        };
+        //指数部分允许前导零
+        let valid = valid_digits(int, digit)
+            && no_leading_zero(int)
+            && fraction.is_none_or(|f| valid_digits(f, digit))
+            && exponent
+                .is_none_or(|e| valid_digits(e.strip_prefix(['+', '-']).unwrap_or(e), digit));
+        if !valid {
+            return None;
+        }
+        return token.replace('_', "").parse().ok().map(Value::Float);
+    }
+    if !valid_digits(body, digit) || !no_leading_zero(body) {
+        return None;
+    }
+    token.replace('_', "").parse().ok().map(Value::Integer)
+}
+
+fn days_in_month(year: u16, month: u8) -> u8 {
+    match month {
+        2 if (year.is_multiple_of(4) && !year.is_multiple_of(100)) || year.is_multiple_of(400) => {
+            29
+        }
+        2 => 28,
+        4 | 6 | 9 | 11 => 30,
+        _ => 31,
+    }
+}
+
+pub struct Parser<'a> {
+    src: &'a str,
+    bytes: &'a [u8],
+    pos: usize,
+    //已统计到的位置及其行号
+    line_pos: usize,
+    line: usize,
+}
+
+impl<'a> Parser<'a> {
+    pub fn new(src: &'a str) -> Parser<'a> {
+        Parser {
+            src,
+            bytes: src.as_bytes(),
+            pos: 0,
+            line_pos: 0,
+            line: 1,
+        }
+    }
+
+    pub fn parse(mut self) -> Result<Document, TomlError> {
+        if self.src.starts_with('\u{feff}') {
+            self.pos = '\u{feff}'.len_utf8();
+        }
+        let mut root = Node::new(Kind::Header);
+        let mut current: Vec<String> = Vec::new();
+        let mut lines = HashMap::new();
+        loop {
+            self.skip_ws();
+            let line = self.line();
+            match self.peek() {
+                None => break,
+                Some(b'#' | b'\n' | b'\r') => {}
+                Some(b'[') => {
+                    current = self.header(&mut root)?;
+                    lines.entry(current.join(".")).or_insert(line);
+                }
+                Some(_) => {
+                    let keys = self.keyval(current_table(&mut root, &current))?;
+                    let path: Vec<&str> = current.iter().chain(&keys).map(|k| k.as_str()).collect();
+                    lines.entry(path.join(".")).or_insert(line);
+                }
+            }
+            self.line_end()?;
+        }
+        Ok(Document {
+            table: root.into_table(),
+            lines,
+        })
+    }
+
+    //当前位置的行号，从上次统计的位置继续计数
+    fn line(&mut self) -> usize {
+        let newlines = self.bytes[self.line_pos..self.pos]
+            .iter()
+            .filter(|c| **c == b'\n')
+            .count();
+        self.line += newlines;
+        self.line_pos = self.pos;
+        self.line
+    }
+
+    fn error<T>(&self, pos: usize, message: impl Into<String>) -> Result<T, TomlError> {
+        let before = &self.src[..pos];
+        Err(TomlError {
+            line: before.matches('\n').count() + 1,
+            column: before.rsplit('\n').next().unwrap_or("").chars().count() + 1,
+            message: message.into(),
+        })
+    }
+
+    fn peek(&self) -> Option<u8> {
+        self.bytes.get(self.pos).copied()
+    }
+
+    fn peek_at(&self, offset: usize) -> Option<u8> {
+        self.bytes.get(self.pos + offset).copied()
+    }
+
+    fn next_char(&mut self) -> Option<char> {
+        let c = self.src[self.pos..].chars().next()?;
+        self.pos += c.len_utf8();
+        Some(c)
+    }
+
+    fn eat(&mut self, c: u8) -> bool {
+        if self.peek() == Some(c) {
+            self.pos += 1;
+            return true;
+        }
+        false
+    }
+
+    fn starts_with(&self, s: &str) -> bool {
+        self.src[self.pos..].starts_with(s)
+    }
+
+    fn skip_ws(&mut self) {
+        while matches!(self.peek(), Some(b' ' | b'\t')) {
+            self.pos += 1;
+        }
+    }
+
+    fn comment(&mut self) -> Result<(), TomlError> {
+        self.pos += 1;
+        while let Some(c) = self.src[self.pos..].chars().next() {
+            if c == '\n' || c == '\r' {
+                break;
+            }
+            if is_control(c) {
+                return self.error(self.pos, "control characters are not allowed in comments");
+            }
+            self.pos += c.len_utf8();
+        }
+        Ok(())
+    }
+
+    //行尾：可选的注释后必须是换行或文件结束
+    fn line_end(&mut self) -> Result<(), TomlError> {
+        self.skip_ws();
+        if self.peek() == Some(b'#') {
+            self.comment()?;
+        }
+        match self.peek() {
+            None => Ok(()),
+            Some(b'\n') => {
+                self.pos += 1;
+                Ok(())
+            }
+            Some(b'\r') if self.peek_at(1) == Some(b'\n') => {
+                self.pos += 2;
+                Ok(())
+            }
+            _ => self.error(self.pos, "expected a newline"),
+        }
+    }
+
+    //数组中可跨行并包含注释
+    fn skip_ws_comments(&mut self) -> Result<(), TomlError> {
+        loop {
+            self.skip_ws();
+            match self.peek() {
+                Some(b'#') => self.comment()?,
+                Some(b'\n') => self.pos += 1,
+                Some(b'\r') if self.peek_at(1) == Some(b'\n') => self.pos += 2,
+                _ => return Ok(()),
+            }
+        }
+    }
+
+    /*
+     * [表头]或[[表数组]]，返回之后键值对所属表的路径
+     */
+    fn header(&mut self, root: &mut Node) -> Result<Vec<String>, TomlError> {
+        self.pos += 1;
+        let array = self.eat(b'[');
+        self.skip_ws();
+        let start = self.pos;
+        let keys = self.key()?;
+        self.skip_ws();
+        if !self.eat(b']') || (array && !self.eat(b']')) {
+            return self.error(self.pos, "expected ']'");
+        }
+        let (last, parents) = keys.split_last().unwrap();
+        let mut node = root;
+        for key in parents {
+            node = match node
+                .entries
+                .entry(key.clone())
+                .or_insert_with(|| Item::Table(Node::new(Kind::Implicit)))
+            {
+                Item::Table(table) => table,
+                Item::Array(tables) => tables.last_mut().unwrap(),
+                Item::Value(_) => return self.error(start, format!("'{}' is not a table", key)),
+            };
+        }
+        match node.entries.entry(last.clone()) {
+            Entry::Vacant(entry) if array => {
+                entry.insert(Item::Array(vec![Node::new(Kind::Header)]));
+            }
+            Entry::Vacant(entry) => {
+                entry.insert(Item::Table(Node::new(Kind::Header)));
+            }
+            Entry::Occupied(mut entry) => match entry.get_mut() {
+                Item::Array(tables) if array => tables.push(Node::new(Kind::Header)),
+                Item::Table(table) if !array && table.kind == Kind::Implicit => {
+                    table.kind = Kind::Header
+                }
+                _ => return self.error(start, format!("duplicate table '{}'", keys.join("."))),
+            },
+        }
+        Ok(keys)
+    }
+
+    //点分键，各段之间允许空白
+    fn key(&mut self) -> Result<Vec<String>, TomlError> {
+        let mut keys = Vec::new();
+        loop {
+            self.skip_ws();
+            keys.push(self.simple_key()?);
+            self.skip_ws();
+            if !self.eat(b'.') {
+                return Ok(keys);
+            }
+        }
+    }
+
+    fn simple_key(&mut self) -> Result<String, TomlError> {
+        let start = self.pos;
+        match self.peek() {
+            Some(b'"') if !self.starts_with("\"\"\"") => self.basic_string(),
+            Some(b'\'') if !self.starts_with("'''") => self.literal_string(),
+            Some(c) if is_bare_key(c) => {
+                while self.peek().is_some_and(is_bare_key) {
+                    self.pos += 1;
+                }
+                Ok(self.src[start..self.pos].to_owned())
+            }
+            _ => self.error(start, "expected a key"),
+        }
+    }
+
+    //解析一个键值对，返回其中的键
+    fn keyval(&mut self, node: &mut Node) -> Result<Vec<String>, TomlError> {
+        let start = self.pos;
+        let keys = self.key()?;
+        self.skip_ws();
+        if !self.eat(b'=') {
+            return self.error(self.pos, "expected '=' after the key");
+        }
+        self.skip_ws();
+        let value = self.value()?;
+        let (last, parents) = keys.split_last().unwrap();
+        let mut node = node;
+        for key in parents {
+            node = match node
+                .entries
+                .entry(key.clone())
+                .or_insert_with(|| Item::Table(Node::new(Kind::Dotted)))
+            {
+                //点分键不能向表头定义的表追加
+                Item::Table(table) if table.kind != Kind::Header => {
+                    table.kind = Kind::Dotted;
+                    table
+                }
+                _ => return self.error(start, format!("cannot add keys to '{}'", key)),
+            };
+        }
+        match node.entries.entry(last.clone()) {
+            Entry::Vacant(entry) => {
+                entry.insert(Item::Value(value));
+                Ok(keys)
+            }
+            Entry::Occupied(_) => self.error(start, format!("duplicate key '{}'", keys.join("."))),
+        }
+    }
+
+    fn value(&mut self) -> Result<Value, TomlError> {
+        let start = self.pos;
+        match self.peek() {
+            Some(b'"') => self.basic_string().map(Value::String),
+            Some(b'\'') => self.literal_string().map(Value::String),
+            Some(b'[') => self.array(),
+            Some(b'{') => self.inline_table(),
+            Some(b't') if self.starts_with("true") => {
+                self.pos += 4;
+                Ok(Value::Boolean(true))
+            }
+            Some(b'f') if self.starts_with("false") => {
+                self.pos += 5;
+                Ok(Value::Boolean(false))
+            }
+            Some(c) if c.is_ascii_digit() || matches!(c, b'+' | b'-' | b'i' | b'n') => {
+                self.number_or_datetime()
+            }
+            _ => self.error(start, "expected a value"),
+        }
+    }
+
+    fn number_or_datetime(&mut self) -> Result<Value, TomlError> {
+        let start = self.pos;
+        let digits = |n: usize| (0..n).all(|i| self.peek_at(i).is_some_and(|c| c.is_ascii_digit()));
+        if (digits(4) && self.peek_at(4) == Some(b'-'))
+            || (digits(2) && self.peek_at(2) == Some(b':'))
+        {
+            return self.datetime().map(Value::Datetime);
+        }
+        while self
+            .peek()
+            .is_some_and(|c| c.is_ascii_alphanumeric() || matches!(c, b'_' | b'+' | b'-' | b'.'))
+        {
+            self.pos += 1;
+        }
+        let token = &self.src[start..self.pos];
+        match parse_number(token) {
+            Some(value) => Ok(value),
+            None => self.error(start, format!("invalid number '{}'", token)),
+        }
+    }
+
+    //读取固定位数的数字
+    fn digits(&mut self, n: usize) -> Option<u32> {
+        let mut value = 0;
+        for _ in 0..n {
+            let c = self.peek().filter(|c| c.is_ascii_digit())?;
+            value = value * 10 + (c - b'0') as u32;
+            self.pos += 1;
+        }
+        Some(value)
+    }
+
+    fn datetime(&mut self) -> Result<Datetime, TomlError> {
+        let start = self.pos;
+        let mut datetime = Datetime {
+            date: None,
+            time: None,
+            offset: None,
+        };
+        if self.peek_at(4) == Some(b'-') {
+            match self.date() {
+                Some(date) => datetime.date = Some(date),
+                None => return self.error(start, "invalid date"),
+            }
+            //日期与时间之间可用T或空格分隔
+            let separator = matches!(self.peek(), Some(b'T' | b't' | b' '));
+            if !separator || !self.peek_at(1).is_some_and(|c| c.is_ascii_digit()) {
+                return Ok(datetime);
+            }
+            self.pos += 1;
+        }
+        match self.time() {
+            Some(time) => datetime.time = Some(time),
+            None => return self.error(start, "invalid time"),
+        }
+        if datetime.date.is_some() {
+            match self.offset() {
+                Some(offset) => datetime.offset = offset,
+                None => return self.error(start, "invalid time offset"),
+            }
+        }
+        Ok(datetime)
+    }
+
+    fn date(&mut self) -> Option<Date> {
+        let year = self.digits(4)? as u16;
+        self.eat(b'-').then_some(())?;
+        let month = self.digits(2)? as u8;
+        self.eat(b'-').then_some(())?;
+        let day = self.digits(2)? as u8;
+        if !(1..=12).contains(&month) || day == 0 || day > days_in_month(year, month) {
+            return None;
+        }
+        Some(Date { year, month, day })
+    }
+
+    fn time(&mut self) -> Option<Time> {
+        let hour = self.digits(2)? as u8;
+        self.eat(b':').then_some(())?;
+        let minute = self.digits(2)? as u8;
+        self.eat(b':').then_some(())?;
+        let second = self.digits(2)? as u8;
+        //闰秒
+        if hour > 23 || minute > 59 || second > 60 {
+            return None;
+        }
+        let mut nanosecond = 0;
+        if self.eat(b'.') {
+            let start = self.pos;
+            while self.peek().is_some_and(|c| c.is_ascii_digit()) {
+                self.pos += 1;
+            }
+            let fraction = &self.src[start..self.pos];
+            if fraction.is_empty() {
+                return None;
+            }
+            //超过纳秒精度的部分截断
+            let fraction = format!("{:0<9}", &fraction[..fraction.len().min(9)]);
+            nanosecond = fraction.parse().ok()?;
+        }
+        Some(Time {
+            hour,
+            minute,
+            second,
+            nanosecond,
+        })
+    }
+
+    //外层Option表示格式是否正确，内层表示是否有时区偏移
+    fn offset(&mut self) -> Option<Option<Offset>> {
+        match self.peek() {
+            Some(b'Z' | b'z') => {
+                self.pos += 1;
+                Some(Some(Offset::Z))
+            }
+            Some(sign @ (b'+' | b'-')) => {
+                self.pos += 1;
+                let hour = self.digits(2)? as i16;
+                self.eat(b':').then_some(())?;
+                let minute = self.digits(2)? as i16;
+                if hour > 23 || minute > 59 {
+                    return None;
+                }
+                let minutes = hour * 60 + minute;
+                Some(Some(Offset::Minutes(if sign == b'-' {
+                    -minutes
+                } else {
+                    minutes
+                })))
+            }
+            _ => Some(None),
+        }
+    }
+
+    fn escape(&mut self, s: &mut String, start: usize) -> Result<(), TomlError> {
+        let c = match self.next_char() {
+            Some('b') => '\u{8}',
+            Some('t') => '\t',
+            Some('n') => '\n',
+            Some('f') => '\u{c}',
+            Some('r') => '\r',
+            Some('"') => '"',
+            Some('\\') => '\\',
+            Some(u @ ('u' | 'U')) => {
+                let len = if u == 'u' { 4 } else { 8 };
+                let hex = self.src.get(self.pos..self.pos + len).unwrap_or("");
+                let c = u32::from_str_radix(hex, 16)
+                    .ok()
+                    .filter(|_| hex.bytes().all(|c| c.is_ascii_hexdigit()))
+                    .and_then(char::from_u32);
+                match c {
+                    Some(c) => {
+                        self.pos += len;
+                        c
+                    }
+                    None => return self.error(start, "invalid unicode escape"),
+                }
+            }
+            _ => return self.error(start, "invalid escape sequence"),
+        };
+        s.push(c);
+        Ok(())
+    }
+
+    fn basic_string(&mut self) -> Result<String, TomlError> {
+        if self.starts_with("\"\"\"") {
+            return self.multiline_string('"');
+        }
+        self.pos += 1;
+        let mut s = String::new();
+        loop {
+            let start = self.pos;
+            match self.next_char() {
+                Some('"') => return Ok(s),
+                Some('\\') => self.escape(&mut s, start)?,
+                Some(c) if !is_control(c) => s.push(c),
+                Some('\n' | '\r') | None => return self.error(start, "unterminated string"),
+                Some(_) => {
+                    return self.error(start, "control characters are not allowed in strings")
+                }
+            }
+        }
+    }
+
+    fn literal_string(&mut self) -> Result<String, TomlError> {
+        if self.starts_with("'''") {
+            return self.multiline_string('\'');
+        }
+        self.pos += 1;
+        let start = self.pos;
+        loop {
+            let pos = self.pos;
+            match self.next_char() {
+                Some('\'') => return Ok(self.src[start..pos].to_owned()),
+                Some(c) if !is_control(c) => {}
+                Some('\n' | '\r') | None => return self.error(pos, "unterminated string"),
+                Some(_) => return self.error(pos, "control characters are not allowed in strings"),
+            }
+        }
+    }
+
+    /*
+     * 多行字符串，quote为'"'（支持转义与行尾反斜杠）或'\''（原样）
+     * 紧跟开头引号的换行被忽略，结尾最多可再包含两个引号
+     */
+    fn multiline_string(&mut self, quote: char) -> Result<String, TomlError> {
+        self.pos += 3;
+        if self.starts_with("\r\n") {
+            self.pos += 2;
+        } else {
+            self.eat(b'\n');
+        }
+        let mut s = String::new();
+        loop {
+            let start = self.pos;
+            match self.next_char() {
+                None => return self.error(start, "unterminated string"),
+                Some(c) if c == quote => {
+                    let count = self.src[start..]
+                        .chars()
+                        .take_while(|c| *c == quote)
+                        .count();
+                    if count < 3 {
+                        s.push(c);
+                        continue;
+                    }
+                    if count > 5 {
+                        return self.error(start, "too many quotes at the end of the string");
+                    }
+                    s.extend(std::iter::repeat_n(quote, count - 3));
+                    self.pos = start + count;
+                    return Ok(s);
+                }
+                Some('\\') if quote == '"' => {
+                    //行尾反斜杠：去掉换行及下一行开头的空白
+                    let mut lookahead = self.pos;
+                    while matches!(self.bytes.get(lookahead), Some(b' ' | b'\t')) {
+                        lookahead += 1;
+                    }
+                    if matches!(self.bytes.get(lookahead), Some(b'\n' | b'\r')) {
+                        self.pos = lookahead;
+                        loop {
+                            match self.peek() {
+                                Some(b' ' | b'\t' | b'\n') => self.pos += 1,
+                                Some(b'\r') if self.peek_at(1) == Some(b'\n') => self.pos += 2,
+                                _ => break,
+                            }
+                        }
+                    } else {
+                        self.escape(&mut s, start)?;
+                    }
+                }
+                Some('\r') if self.peek() == Some(b'\n') => {
+                    self.pos += 1;
+                    s.push_str("\r\n");
+                }
+                Some('\n') => s.push('\n'),
+                Some(c) if !is_control(c) => s.push(c),
+                Some(_) => {
+                    return self.error(start, "control characters are not allowed in strings")
+                }
+            }
+        }
+    }
+
+    fn array(&mut self) -> Result<Value, TomlError> {
+        self.pos += 1;
+        let mut items = Vec::new();
+        loop {
+            self.skip_ws_comments()?;
+            if self.eat(b']') {
+                return Ok(Value::Array(items));
+            }
+            items.push(self.value()?);
+            self.skip_ws_comments()?;
+            if self.eat(b']') {
+                return Ok(Value::Array(items));
+            }
+            if !self.eat(b',') {
+                return self.error(self.pos, "expected ',' or ']'");
+            }
+        }
+    }
+
+    //内联表必须写在一行内，不允许末尾逗号
+    fn inline_table(&mut self) -> Result<Value, TomlError> {
+        self.pos += 1;
+        let mut node = Node::new(Kind::Header);
+        self.skip_ws();
+        if self.eat(b'}') {
+            return Ok(Value::Table(node.into_table()));
+        }
+        loop {
+            self.skip_ws();
+            self.keyval(&mut node)?;
+            self.skip_ws();
+            if self.eat(b'}') {
+                return Ok(Value::Table(node.into_table()));
+            }
+            if !self.eat(b',') {
+                return self.error(self.pos, "expected ',' or '}'");
+            }
+        }
+    }
+}
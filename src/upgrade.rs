@@ -0,0 +1,256 @@
+use std::{
+    env,
+    io::{self, Read, Write},
+    net::TcpListener,
+    os::{
+        fd::{AsRawFd, FromRawFd, IntoRawFd, RawFd},
+        unix::net::{UnixListener, UnixStream},
+    },
+    process::{self, Command},
+    sync::{Mutex, OnceLock},
+    time::Duration,
+};
+
+use crate::{
+    config::{ListenAddr, ListenConfig},
+    listener::{self, Listener},
+    log_error, log_info, signal,
+};
+
+//升级时传递监听套接字，格式为“地址:描述符”，以逗号分隔
+const INHERIT_FDS: &str = "STAR_HTTP_INHERIT_FDS";
+//新进程就绪后写入一个字节的描述符
+const READY_FD: &str = "STAR_HTTP_READY_FD";
+//systemd套接字激活传入的描述符从3开始
+const SD_LISTEN_FDS_START: RawFd = 3;
+//等待新进程就绪的时间
+const READY_TIMEOUT: Duration = Duration::from_secs(30);
+
+const F_GETFD: i32 = 1;
+const F_SETFD: i32 = 2;
+const FD_CLOEXEC: i32 = 1;
+
+extern "C" {
+    fn fcntl(fd: i32, cmd: i32, ...) -> i32;
+}
+
+static INHERITED: OnceLock<Mutex<Vec<Listener>>> = OnceLock::new();
+
+//设置描述符在exec后是否保留
+fn set_inheritable(fd: RawFd, inheritable: bool) -> io::Result<()> {
+    let flags = unsafe { fcntl(fd, F_GETFD) };
+    if flags < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    let flags = if inheritable {
+        flags & !FD_CLOEXEC
+    } else {
+        flags | FD_CLOEXEC
+    };
+    if unsafe { fcntl(fd, F_SETFD, flags) } < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+fn parse_inherit_fds(value: &str) -> Vec<RawFd> {
+    value
+        .split(',')
+        .filter_map(|item| item.rsplit_once(':'))
+        .filter_map(|(_, fd)| fd.trim().parse().ok())
+        .collect()
+}
+
+//LISTEN_PID与当前进程一致时才接受LISTEN_FDS
+fn systemd_fds(pid: Option<&str>, count: Option<&str>) -> Vec<RawFd> {
+    if pid.and_then(|pid| pid.parse::<u32>().ok()) != Some(process::id()) {
+        return Vec::new();
+    }
+    let count: RawFd = count.and_then(|count| count.parse().ok()).unwrap_or(0);
+    (SD_LISTEN_FDS_START..SD_LISTEN_FDS_START + count.max(0)).collect()
+}
+
+//按描述符的地址类型识别TCP或Unix监听套接字
+fn inherit(fd: RawFd) -> io::Result<Listener> {
+    let tcp = unsafe { TcpListener::from_raw_fd(fd) };
+    let tcp_error = match tcp.local_addr() {
+        Ok(_) => return Ok(Listener::Tcp(tcp)),
+        Err(e) => e,
+    };
+    let _ = tcp.into_raw_fd();
+    let unix = unsafe { UnixListener::from_raw_fd(fd) };
+    match unix.local_addr() {
+        Ok(addr) if addr.as_pathname().is_some() => Ok(Listener::Unix(unix)),
+        _ => {
+            let _ = unix.into_raw_fd();
+            Err(tcp_error)
+        }
+    }
+}
+
+fn inherited() -> &'static Mutex<Vec<Listener>> {
+    INHERITED.get_or_init(|| {
+        let mut fds = env::var(INHERIT_FDS)
+            .map(|value| parse_inherit_fds(&value))
+            .unwrap_or_default();
+        fds.extend(systemd_fds(
+            env::var("LISTEN_PID").ok().as_deref(),
+            env::var("LISTEN_FDS").ok().as_deref(),
+        ));
+        let mut listeners = Vec::new();
+        for fd in fds {
+            //不是监听套接字时放弃，不关闭未知描述符
+            match inherit(fd) {
+                Ok(listener) => {
+                    let _ = set_inheritable(fd, false);
+                    if let Some(addr) = listener.local_addr() {
+                        log_info!("Inherited listener {} (fd {})", addr, fd);
+                    }
+                    listeners.push(listener);
+                }
+                Err(e) => log_error!("Ignoring inherited fd {}. Error:{}", fd, e),
+            }
+        }
+        Mutex::new(listeners)
+    })
+}
+
+/*
+ * 获取监听套接字：优先使用升级时继承或systemd传入的套接字，否则按配置绑定新地址
+ * 继承的套接字只按完整地址匹配，地址改变（如127.0.0.1:8080改为0.0.0.0:8080）时重新绑定
+ */
+pub fn listen(config: &ListenConfig) -> io::Result<Listener> {
+    let mut listeners = inherited().lock().unwrap_or_else(|e| e.into_inner());
+    match take_matching(&mut listeners, &config.addr) {
+        Some(listener) => Ok(listener),
+        None => listener::bind(config),
+    }
+}
+
+fn take_matching(listeners: &mut Vec<Listener>, addr: &ListenAddr) -> Option<Listener> {
+    let index = listeners
+        .iter()
+        .position(|l| l.local_addr().as_ref() == Some(addr))?;
+    Some(listeners.swap_remove(index))
+}
+
+//关闭没有被配置使用的继承套接字，在所有监听创建完成后调用
+pub fn close_unused() {
+    let mut listeners = inherited().lock().unwrap_or_else(|e| e.into_inner());
+    for listener in listeners.drain(..) {
+        log_info!(
+            "Closing inherited listener {} (fd {}), it is no longer configured",
+            listener
+                .local_addr()
+                .map_or_else(|| String::from("-"), |addr| addr.to_string()),
+            listener.as_raw_fd()
+        );
+    }
+}
+
+//是否由systemd套接字激活启动
+pub fn socket_activated() -> bool {
+    !systemd_fds(
+        env::var("LISTEN_PID").ok().as_deref(),
+        env::var("LISTEN_FDS").ok().as_deref(),
+    )
+    .is_empty()
+}
+
+//通知旧进程已经开始监听
+pub fn notify_ready() {
+    let fd = match env::var(READY_FD).ok().and_then(|fd| fd.parse().ok()) {
+        Some(fd) => fd,
+        None => return,
+    };
+    let mut ready = unsafe { UnixStream::from_raw_fd(fd) };
+    if let Err(e) = ready.write_all(b"1") {
+        log_error!("Failed to notify the old process. Error:{}", e);
+    }
+}
+
+/*
+ * 启动新版本程序并传递监听套接字，新进程就绪后返回，旧进程随后开始优雅关闭
+ * listeners为监听地址与描述符
+ */
+pub fn reexec(listeners: &[(String, RawFd)]) -> io::Result<()> {
+    let exe = env::current_exe()?;
+    let (mut reader, writer) = UnixStream::pair()?;
+    for (_, fd) in listeners {
+        set_inheritable(*fd, true)?;
+    }
+    set_inheritable(writer.as_raw_fd(), true)?;
+    let fds = listeners
+        .iter()
+        .map(|(addr, fd)| format!("{}:{}", addr, fd))
+        .collect::<Vec<String>>()
+        .join(",");
+    let result = Command::new(&exe)
+        .args(env::args_os().skip(1))
+        .env(INHERIT_FDS, fds)
+        .env(READY_FD, writer.as_raw_fd().to_string())
+        .env_remove("LISTEN_PID")
+        .env_remove("LISTEN_FDS")
+        .spawn();
+    for (_, fd) in listeners {
+        let _ = set_inheritable(*fd, false);
+    }
+    //关闭本进程的写端，新进程退出时读端才能读到EOF
+    drop(writer);
+    let mut child = result?;
+    log_info!("Started {} (pid {})", exe.display(), child.id());
+    let ready = signal::poll_readable(&[reader.as_raw_fd()], Some(READY_TIMEOUT))?;
+    let mut byte = [0u8; 1];
+    if ready[0] && reader.read(&mut byte)? == 1 {
+        return Ok(());
+    }
+    let _ = child.kill();
+    let _ = child.wait();
+    Err(io::Error::other("The new process did not become ready"))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_parse_fds() {
+        assert_eq!(parse_inherit_fds("8080:3,8443:4"), vec![3, 4]);
+        assert_eq!(
+            parse_inherit_fds("[::]:80:3,unix:/run/star-http.sock:4"),
+            vec![3, 4]
+        );
+        assert_eq!(parse_inherit_fds("bad,8080:x,:5"), vec![5]);
+        let pid = process::id().to_string();
+        assert_eq!(systemd_fds(Some(&pid), Some("2")), vec![3, 4]);
+        assert!(systemd_fds(Some("1"), Some("2")).is_empty());
+        assert!(systemd_fds(None, Some("2")).is_empty());
+    }
+
+    //只接受完整地址相同的继承套接字
+    #[test]
+    fn test_take_matching() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let local = listener.local_addr().unwrap();
+        let mut listeners = vec![Listener::Tcp(listener)];
+        let mut any = local;
+        any.set_ip("0.0.0.0".parse().unwrap());
+        assert!(take_matching(&mut listeners, &ListenAddr::Tcp(any)).is_none());
+        assert_eq!(listeners.len(), 1);
+        let taken = take_matching(&mut listeners, &ListenAddr::Tcp(local)).unwrap();
+        assert_eq!(taken.local_addr(), Some(ListenAddr::Tcp(local)));
+        assert!(listeners.is_empty());
+    }
+
+    #[test]
+    fn test_set_inheritable() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let fd = listener.as_raw_fd();
+        let flags = || unsafe { fcntl(fd, F_GETFD) } & FD_CLOEXEC;
+        assert_eq!(flags(), FD_CLOEXEC);
+        set_inheritable(fd, true).unwrap();
+        assert_eq!(flags(), 0);
+        set_inheritable(fd, false).unwrap();
+        assert_eq!(flags(), FD_CLOEXEC);
+    }
+}